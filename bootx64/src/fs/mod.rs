@@ -38,9 +38,16 @@ fn locate(
     let mut file_handler = get_handler(root, name);
     let file_bytes = size(bs, &mut file_handler);
 
-    let addr = allocate(bs, file_bytes);
+    let addr = allocate(bs, name, file_bytes);
     put_on_memory(&mut file_handler, addr, file_bytes);
 
+    info!(
+        "Loaded {} at {:?}, {} bytes",
+        name,
+        addr,
+        file_bytes.as_usize()
+    );
+
     (addr, file_bytes)
 }
 
@@ -74,7 +81,8 @@ pub fn fetch_entry_address_and_memory_size(addr: PhysAddr, bytes: Bytes) -> (Vir
 fn get_handler(root: &mut file::Directory, name: &'static str) -> file::RegularFile {
     let h = root
         .open(name, FileMode::Read, FileAttribute::empty())
-        .expect_success("Failed to get file handler of the kernel.");
+        .unwrap_or_else(|_| panic!("Failed to get file handler of {}.", name))
+        .unwrap();
 
     let h = h
         .into_type()
@@ -88,27 +96,26 @@ fn get_handler(root: &mut file::Directory, name: &'static str) -> file::RegularF
     }
 }
 
-fn allocate(boot_services: &boot::BootServices, kernel_bytes: Bytes) -> PhysAddr {
+fn allocate(boot_services: &boot::BootServices, name: &'static str, bytes: Bytes) -> PhysAddr {
     PhysAddr::new(
         boot_services
             .allocate_pages(
                 AllocateType::AnyPages,
                 MemoryType::LOADER_DATA,
-                kernel_bytes.as_num_of_pages::<Size4KiB>().as_usize(),
+                bytes.as_num_of_pages::<Size4KiB>().as_usize(),
             )
-            .expect_success("Failed to allocate memory for the kernel"),
+            .unwrap_or_else(|_| panic!("Failed to allocate memory for {}", name))
+            .unwrap(),
     )
 }
 
-fn put_on_memory(handler: &mut file::RegularFile, kernel_addr: PhysAddr, kernel_bytes: Bytes) {
+fn put_on_memory(handler: &mut file::RegularFile, addr: PhysAddr, bytes: Bytes) {
     // Reading should use while statement with the number of bytes which were actually read.
     // However, without while statement previous uefi implementation worked so this uefi
     // implementation also never use it.
     handler
-        .read(unsafe {
-            core::slice::from_raw_parts_mut(kernel_addr.as_u64() as _, kernel_bytes.as_usize())
-        })
-        .expect_success("Failed to read kernel");
+        .read(unsafe { core::slice::from_raw_parts_mut(addr.as_u64() as _, bytes.as_usize()) })
+        .expect_success("Failed to read a file");
 }
 
 fn size(bs: &boot::BootServices, r: &mut file::RegularFile) -> Bytes {
@@ -15,7 +15,11 @@ use bootx64::{
     mem::{paging, stack},
     rsdp,
 };
-use common::{constant::KERNEL_NAME, kernelboot, mem::reserved};
+use common::{
+    constant::{INITRD_NAME, KERNEL_NAME},
+    kernelboot,
+    mem::reserved,
+};
 use uefi::prelude::{Boot, Handle, SystemTable};
 
 #[start]
@@ -29,17 +33,22 @@ pub fn efi_main(image: Handle, system_table: SystemTable<Boot>) -> ! {
     let (entry_addr, actual_mem_size) =
         fs::fetch_entry_address_and_memory_size(phys_kernel_addr, bytes_kernel);
 
+    let (phys_initrd_addr, bytes_initrd) = fs::deploy(system_table.boot_services(), INITRD_NAME);
+    let initrd = reserved::PhysRange::new(phys_initrd_addr, bytes_initrd);
+
     let stack_addr = stack::allocate(system_table.boot_services());
     let rsdp = rsdp::get(&system_table);
+    let kernel_file = reserved::PhysRange::new(phys_kernel_addr, bytes_kernel);
     let reserved_regions = reserved::Map::new(
         &reserved::PhysRange::new(phys_kernel_addr, actual_mem_size),
         stack_addr,
         &vram_info,
+        &initrd,
     );
     let mem_map = bootx64::exit::boot_services(image, system_table);
 
-    let mut boot_info = kernelboot::Info::new(entry_addr, vram_info, mem_map, rsdp);
+    let mut boot_info = kernelboot::Info::new(entry_addr, vram_info, mem_map, rsdp, initrd);
 
-    paging::init(&mut boot_info, &reserved_regions);
+    paging::init(&mut boot_info, &reserved_regions, &kernel_file);
     jump::to_kernel(boot_info);
 }
@@ -1,15 +1,24 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use common::{constant::RECUR_PML4_ADDR, kernelboot, mem::reserved};
-use core::convert::TryFrom;
+use common::{
+    constant::{KERNEL_ADDR, RECUR_PML4_ADDR, RECUR_PML4_INDEX},
+    kernelboot,
+    mem::{paging, reserved},
+};
+use core::{convert::TryFrom, slice};
+use elf_rs::{Elf, ProgramType};
 use uefi::table::{boot, boot::MemoryType};
 use x86_64::{
     addr::PhysAddr,
-    registers::control::{Cr0, Cr0Flags, Cr3},
+    registers::{
+        control::{Cr0, Cr0Flags, Cr3},
+        model_specific::{Efer, EferFlags},
+    },
     structures::paging::{
         FrameAllocator, Mapper, Page, PageSize, PageTable, PageTableFlags, PhysFrame,
         RecursivePageTable, Size4KiB,
     },
+    VirtAddr,
 };
 
 struct AllocatorWithEfiMemoryMap<'a> {
@@ -38,8 +47,14 @@ unsafe impl<'a> FrameAllocator<Size4KiB> for AllocatorWithEfiMemoryMap<'a> {
     }
 }
 
-pub fn init(boot_info: &mut kernelboot::Info, reserved: &reserved::Map) {
+/// `kernel` is the ELF file of the kernel, whose segments decide the permissions of its pages.
+pub fn init(
+    boot_info: &mut kernelboot::Info,
+    reserved: &reserved::Map,
+    kernel: &reserved::PhysRange,
+) {
     remove_table_protection();
+    enable_no_execute();
 
     enable_recursive_mapping();
 
@@ -48,14 +63,17 @@ pub fn init(boot_info: &mut kernelboot::Info, reserved: &reserved::Map) {
     for region in reserved.iter() {
         map_virt_to_phys(region, &mut allocator);
     }
+
+    protect_kernel(kernel);
 }
 
+/// The recursive entry is not user-accessible, so that ring 3 cannot edit the page tables.
 fn enable_recursive_mapping() {
     let p4: &mut PageTable = unsafe { &mut *(get_pml4_addr().as_u64() as *mut _) };
 
-    p4[511].set_addr(
+    p4[usize::from(RECUR_PML4_INDEX)].set_addr(
         get_pml4_addr(),
-        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE,
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
     );
 }
 
@@ -67,24 +85,114 @@ fn remove_table_protection() {
     }
 }
 
+/// The pages must not have `NO_EXECUTE` before this, or accessing them causes a page fault.
+fn enable_no_execute() {
+    unsafe { Efer::update(|e| *e |= EferFlags::NO_EXECUTE_ENABLE) }
+}
+
 fn map_virt_to_phys(region: &reserved::Range, allocator: &mut AllocatorWithEfiMemoryMap) {
     let p4 = unsafe { &mut *(RECUR_PML4_ADDR.as_mut_ptr()) };
     let mut p4 = RecursivePageTable::new(p4).unwrap();
 
-    let num_of_pages = region.bytes().as_num_of_pages::<Size4KiB>().as_usize();
+    let f = PageTableFlags::PRESENT
+        | PageTableFlags::WRITABLE
+        | PageTableFlags::USER_ACCESSIBLE
+        | PageTableFlags::NO_EXECUTE;
+
+    let r = paging::Region {
+        virt: region.virt(),
+        phys: region.phys(),
+        bytes: region.bytes(),
+        flags: f,
+    };
+
+    // `protect_kernel` sets the flags of each page of the kernel, so only the other regions are
+    // mapped with huge pages.
+    if region.virt() == KERNEL_ADDR {
+        map_with_4kib_pages(&mut p4, &r, allocator);
+    } else {
+        unsafe { paging::map_range(&mut p4, r, allocator) };
+    }
+}
+
+fn map_with_4kib_pages(
+    p4: &mut RecursivePageTable,
+    r: &paging::Region,
+    allocator: &mut AllocatorWithEfiMemoryMap,
+) {
+    let num_of_pages = r.bytes.as_num_of_pages::<Size4KiB>().as_usize();
     for i in 0..num_of_pages {
         let v = Page::<Size4KiB>::containing_address(
-            region.virt() + usize::try_from(Size4KiB::SIZE).unwrap() * i,
-        );
-        let p = PhysFrame::containing_address(
-            region.phys() + usize::try_from(Size4KiB::SIZE).unwrap() * i,
+            r.virt + usize::try_from(Size4KiB::SIZE).unwrap() * i,
         );
-        let f =
-            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
-        unsafe { p4.map_to(v, p, f, allocator) }.unwrap().flush();
+        let p =
+            PhysFrame::containing_address(r.phys + usize::try_from(Size4KiB::SIZE).unwrap() * i);
+        unsafe { p4.map_to(v, p, r.flags, allocator) }
+            .unwrap()
+            .flush();
+    }
+}
+
+/// Sets the flags of the pages of the kernel to those of its segments, so that the code is
+/// read-only and the others are not executable.
+///
+/// The linker script aligns each section to a page, so no page belongs to two segments.
+fn protect_kernel(kernel: &reserved::PhysRange) {
+    let raw = unsafe {
+        slice::from_raw_parts(
+            kernel.start().as_u64() as *const u8,
+            kernel.bytes().as_usize(),
+        )
+    };
+
+    let elf = match Elf::from_bytes(raw) {
+        Ok(Elf::Elf64(e)) => e,
+        _ => panic!("The kernel is not a 64-bit ELF file."),
+    };
+
+    for ph in elf
+        .program_header_iter()
+        .filter(|ph| ph.ph.ph_type() == ProgramType::LOAD && ph.ph.memsz() > 0)
+    {
+        let start = VirtAddr::new(ph.ph.vaddr());
+        let end = start + ph.ph.memsz() - 1_u64;
+
+        update_flags(start, end, segment_flags(ph.ph.flags()));
+    }
+}
+
+fn update_flags(start: VirtAddr, end: VirtAddr, flags: PageTableFlags) {
+    let p4 = unsafe { &mut *(RECUR_PML4_ADDR.as_mut_ptr()) };
+    let mut p4 = RecursivePageTable::new(p4).unwrap();
+
+    for page in Page::<Size4KiB>::range_inclusive(
+        Page::containing_address(start),
+        Page::containing_address(end),
+    ) {
+        unsafe { p4.update_flags(page, flags) }
+            .expect("The kernel is not mapped.")
+            .flush();
     }
 }
 
+/// The kernel image is user-accessible as the processes of the user privilege run it in ring 3.
+fn segment_flags(elf_flags: u32) -> PageTableFlags {
+    const PF_X: u32 = 1;
+    const PF_W: u32 = 2;
+
+    let mut f = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+
+    if elf_flags & PF_W != 0 {
+        f |= PageTableFlags::WRITABLE;
+    }
+
+    if elf_flags & PF_X == 0 {
+        f |= PageTableFlags::NO_EXECUTE;
+    }
+
+    f
+}
+
 fn get_pml4_addr() -> PhysAddr {
     let (frame, _) = Cr3::read();
     frame.start_address()
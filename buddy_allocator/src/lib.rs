@@ -0,0 +1,232 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// A buddy allocator of physical frames.
+//
+// A block of the order `n` is `2^n` pages, and its start address is aligned to its size. The
+// buddy of a block is the other half of the block of the next order, so its address differs from
+// the block's only in the bit of the block size. Each order has a set of the start addresses of its
+// free blocks, so both allocating and freeing take `O(log n)` for each order.
+//
+// An allocation takes a block large enough for it, and returns the pages after it to the free
+// lists. So it costs exactly the requested number of pages, and its start address is aligned to the
+// smallest power of two which is not less than its size.
+//
+// This crate does not depend on the kernel so that its tests run on the host.
+
+#![no_std]
+#![feature(const_btree_new)]
+#![deny(clippy::pedantic)]
+#![deny(clippy::all)]
+
+extern crate alloc;
+
+#[cfg(test)]
+mod tests;
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::convert::TryFrom;
+use os_units::NumOfPages;
+use uefi::table::boot::{MemoryDescriptor, MemoryType};
+use x86_64::{
+    structures::paging::{PageSize, Size4KiB},
+    PhysAddr,
+};
+
+/// The number of the orders. The largest block is `2^(NUM_OF_ORDERS - 1)` pages.
+pub const NUM_OF_ORDERS: usize = 21;
+
+/// The end of the physical address space. Physical addresses are 52 bits at most.
+const PHYS_ADDR_END: u64 = (1 << 52) - 1;
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_BLOCKS: BTreeSet<u64> = BTreeSet::new();
+
+pub struct BuddyAllocator {
+    /// The start addresses of the free blocks of each order.
+    free: [BTreeSet<u64>; NUM_OF_ORDERS],
+
+    /// The numbers of the pages of the allocations, keyed by their start addresses.
+    used: BTreeMap<u64, usize>,
+}
+impl BuddyAllocator {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            free: [NO_BLOCKS; NUM_OF_ORDERS],
+            used: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the conventional memory in `mem_map`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if a descriptor does not fit in the address space.
+    pub fn add_memory_map(&mut self, mem_map: &[MemoryDescriptor]) {
+        for d in mem_map.iter().filter(|d| d.ty == MemoryType::CONVENTIONAL) {
+            self.add_region(
+                PhysAddr::new(d.phys_start),
+                NumOfPages::new(usize::try_from(d.page_count).unwrap()),
+            );
+        }
+    }
+
+    /// Adds the `num_of_pages` pages from `start` as free memory.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `start` is not page-aligned.
+    pub fn add_region(&mut self, start: PhysAddr, num_of_pages: NumOfPages<Size4KiB>) {
+        assert!(
+            start.is_aligned(Size4KiB::SIZE),
+            "The region is not aligned."
+        );
+
+        let start = start.as_u64();
+        self.free_range(start, start + bytes_of(num_of_pages.as_usize()));
+    }
+
+    /// Allocates `num_of_pages` pages. Allocating zero pages allocates one page.
+    pub fn alloc(&mut self, num_of_pages: NumOfPages<Size4KiB>) -> Option<PhysAddr> {
+        self.alloc_below(num_of_pages, PhysAddr::new(PHYS_ADDR_END))
+    }
+
+    /// Allocates `num_of_pages` pages which end at or below `end`.
+    pub fn alloc_below(
+        &mut self,
+        num_of_pages: NumOfPages<Size4KiB>,
+        end: PhysAddr,
+    ) -> Option<PhysAddr> {
+        let pages = num_of_pages.as_usize().max(1);
+        let order = order_of(pages)?;
+        let last_start = end.as_u64().checked_sub(block_bytes(order))?;
+
+        let (addr, mut found) = (order..NUM_OF_ORDERS).find_map(|o| {
+            let addr = *self.free[o].range(..=last_start).next()?;
+            Some((addr, o))
+        })?;
+
+        self.free[found].remove(&addr);
+
+        // Return the upper halves until the block is of the requested order.
+        while found > order {
+            found -= 1;
+            self.free[found].insert(addr + block_bytes(found));
+        }
+
+        self.used.insert(addr, pages);
+        self.free_range(addr + bytes_of(pages), addr + block_bytes(order));
+
+        Some(PhysAddr::new(addr))
+    }
+
+    /// Frees the allocation which starts at `addr`. This does nothing if no allocation starts
+    /// there.
+    pub fn free(&mut self, addr: PhysAddr) {
+        let addr = addr.as_u64();
+
+        if let Some(pages) = self.used.remove(&addr) {
+            self.free_range(addr, addr + bytes_of(pages));
+        }
+    }
+
+    /// Returns the number of pages which are not allocated.
+    #[must_use]
+    pub fn free_pages(&self) -> NumOfPages<Size4KiB> {
+        let pages = self
+            .free
+            .iter()
+            .enumerate()
+            .map(|(order, blocks)| blocks.len() << order)
+            .sum();
+
+        NumOfPages::new(pages)
+    }
+
+    /// Returns the number of the free blocks of each order and the number of the allocated pages.
+    #[must_use]
+    pub fn stats(&self) -> Stats {
+        let mut s = Stats {
+            used_pages: self.used.values().sum(),
+            ..Stats::default()
+        };
+
+        for (order, blocks) in self.free.iter().enumerate() {
+            s.free[order] = blocks.len();
+        }
+
+        s
+    }
+
+    /// Adds the pages from `start` to `end` as free, splitting them into aligned blocks.
+    fn free_range(&mut self, mut start: u64, end: u64) {
+        while start < end {
+            let mut order = max_order_aligned_to(start);
+            while start + block_bytes(order) > end {
+                order -= 1;
+            }
+
+            self.insert_free(start, order);
+            start += block_bytes(order);
+        }
+    }
+
+    /// Adds the block as free, merging it with its buddy as long as the buddy is free.
+    fn insert_free(&mut self, mut addr: u64, mut order: usize) {
+        while order < NUM_OF_ORDERS - 1 {
+            let buddy = addr ^ block_bytes(order);
+
+            if !self.free[order].remove(&buddy) {
+                break;
+            }
+
+            addr = addr.min(buddy);
+            order += 1;
+        }
+
+        self.free[order].insert(addr);
+    }
+}
+impl Default for BuddyAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of the free blocks of each order.
+    pub free: [usize; NUM_OF_ORDERS],
+
+    /// The number of the allocated pages.
+    pub used_pages: usize,
+}
+
+/// Returns the smallest order of a block which has `pages` pages, or `None` if no block is large
+/// enough.
+fn order_of(pages: usize) -> Option<usize> {
+    let pages = pages.checked_next_power_of_two()?;
+    let order = usize::try_from(pages.trailing_zeros()).unwrap();
+
+    (order < NUM_OF_ORDERS).then(|| order)
+}
+
+fn max_order_aligned_to(addr: u64) -> usize {
+    let page = addr / Size4KiB::SIZE;
+
+    if page == 0 {
+        NUM_OF_ORDERS - 1
+    } else {
+        usize::try_from(page.trailing_zeros())
+            .unwrap()
+            .min(NUM_OF_ORDERS - 1)
+    }
+}
+
+fn block_bytes(order: usize) -> u64 {
+    Size4KiB::SIZE << order
+}
+
+fn bytes_of(num_of_pages: usize) -> u64 {
+    u64::try_from(num_of_pages).unwrap() * Size4KiB::SIZE
+}
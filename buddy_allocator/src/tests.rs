@@ -0,0 +1,261 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+extern crate std;
+
+use super::{BuddyAllocator, NUM_OF_ORDERS};
+use core::convert::TryFrom;
+use os_units::NumOfPages;
+use std::{collections::BTreeSet, vec, vec::Vec};
+use uefi::table::boot::{MemoryDescriptor, MemoryType};
+use x86_64::PhysAddr;
+
+const PAGE: u64 = 0x1000;
+
+fn descriptor(ty: MemoryType, phys_start: u64, page_count: u64) -> MemoryDescriptor {
+    let mut d = MemoryDescriptor::default();
+    d.ty = ty;
+    d.phys_start = phys_start;
+    d.page_count = page_count;
+    d
+}
+
+fn conventional(phys_start: u64, page_count: u64) -> MemoryDescriptor {
+    descriptor(MemoryType::CONVENTIONAL, phys_start, page_count)
+}
+
+/// A memory map which looks like one of QEMU with OVMF.
+fn qemu_like_map() -> Vec<MemoryDescriptor> {
+    vec![
+        conventional(0, 0xa0),
+        descriptor(MemoryType::BOOT_SERVICES_DATA, 0x10_0000, 0x10),
+        conventional(0x11_0000, 0x6f0),
+        descriptor(MemoryType::ACPI_NON_VOLATILE, 0x80_0000, 0x8),
+        conventional(0x80_8000, 0x3),
+        descriptor(MemoryType::LOADER_CODE, 0x80_b000, 0x35),
+        conventional(0x84_0000, 0x3_e7c0),
+        descriptor(MemoryType::RUNTIME_SERVICES_DATA, 0x3f00_0000, 0x100),
+    ]
+}
+
+fn allocator(mem_map: &[MemoryDescriptor]) -> BuddyAllocator {
+    let mut a = BuddyAllocator::new();
+    a.add_memory_map(mem_map);
+    a
+}
+
+fn conventional_pages(mem_map: &[MemoryDescriptor]) -> usize {
+    mem_map
+        .iter()
+        .filter(|d| d.ty == MemoryType::CONVENTIONAL)
+        .map(|d| usize::try_from(d.page_count).unwrap())
+        .sum()
+}
+
+#[test]
+fn only_conventional_memory_is_free() {
+    let map = qemu_like_map();
+    let a = allocator(&map);
+
+    assert_eq!(a.free_pages().as_usize(), conventional_pages(&map));
+}
+
+#[test]
+fn blocks_are_aligned_to_their_sizes() {
+    let a = allocator(&qemu_like_map());
+
+    for (order, blocks) in a.free.iter().enumerate() {
+        for addr in blocks {
+            assert_eq!(
+                addr % (PAGE << order),
+                0,
+                "{:#x} of the order {}",
+                addr,
+                order
+            );
+        }
+    }
+}
+
+#[test]
+fn allocated_blocks_do_not_overlap() {
+    let map = qemu_like_map();
+    let mut a = allocator(&map);
+    let mut pages = BTreeSet::new();
+
+    for n in [1, 3, 8, 5, 1, 16, 2, 7].iter().cycle().take(200) {
+        let addr = a.alloc(NumOfPages::new(*n)).unwrap().as_u64();
+        let rounded = u64::try_from(n.next_power_of_two()).unwrap();
+
+        assert_eq!(addr % (PAGE * rounded), 0);
+
+        for i in 0..u64::try_from(*n).unwrap() {
+            assert!(
+                pages.insert(addr + PAGE * i),
+                "{:#x} is allocated twice.",
+                addr
+            );
+        }
+    }
+
+    assert_eq!(
+        a.free_pages().as_usize() + pages.len(),
+        conventional_pages(&map)
+    );
+}
+
+#[test]
+fn allocations_stay_in_conventional_memory() {
+    let map = qemu_like_map();
+    let mut a = allocator(&map);
+
+    while let Some(addr) = a.alloc(NumOfPages::new(1)) {
+        let addr = addr.as_u64();
+
+        assert!(map.iter().any(|d| d.ty == MemoryType::CONVENTIONAL
+            && d.phys_start <= addr
+            && addr < d.phys_start + d.page_count * PAGE));
+    }
+
+    assert_eq!(a.free_pages().as_usize(), 0);
+}
+
+#[test]
+fn freeing_everything_merges_blocks_back() {
+    let map = qemu_like_map();
+    let mut a = allocator(&map);
+    let initial = a.stats();
+
+    let addrs: Vec<_> = (1..100)
+        .map(|n| a.alloc(NumOfPages::new(n % 9 + 1)).unwrap())
+        .collect();
+
+    // Free in a different order from the allocation.
+    for addr in addrs
+        .iter()
+        .step_by(2)
+        .chain(addrs.iter().skip(1).step_by(2))
+    {
+        a.free(*addr);
+    }
+
+    assert_eq!(a.stats(), initial);
+}
+
+#[test]
+fn adjacent_descriptors_are_merged() {
+    let a = allocator(&[conventional(0x10_0000, 0x80), conventional(0x18_0000, 0x80)]);
+
+    let s = a.stats();
+    assert_eq!(s.free[8], 1);
+    assert_eq!(s.free.iter().sum::<usize>(), 1);
+}
+
+#[test]
+fn unaligned_region_is_split() {
+    let a = allocator(&[conventional(0x3000, 0xd)]);
+
+    // 0x3000 (1 page), 0x4000 (4 pages), 0x8000 (8 pages)
+    let s = a.stats();
+    assert_eq!(s.free[0], 1);
+    assert_eq!(s.free[2], 1);
+    assert_eq!(s.free[3], 1);
+    assert_eq!(a.free_pages().as_usize(), 0xd);
+}
+
+#[test]
+fn alloc_below_respects_the_end() {
+    let mut a = allocator(&qemu_like_map());
+    let end = PhysAddr::new(0x10_0000);
+
+    while let Some(addr) = a.alloc_below(NumOfPages::new(2), end) {
+        assert!(addr + 2 * PAGE <= end);
+    }
+
+    // Higher memory is still available.
+    assert!(a.alloc(NumOfPages::new(2)).unwrap() >= end);
+}
+
+#[test]
+fn alloc_below_too_low_end_fails() {
+    let mut a = allocator(&qemu_like_map());
+
+    assert_eq!(
+        a.alloc_below(NumOfPages::new(4), PhysAddr::new(0x2000)),
+        None
+    );
+}
+
+#[test]
+fn too_large_allocation_fails() {
+    let mut a = allocator(&qemu_like_map());
+
+    assert_eq!(a.alloc(NumOfPages::new(1 << NUM_OF_ORDERS)), None);
+    assert_eq!(a.alloc(NumOfPages::new(0x10_0000)), None);
+}
+
+#[test]
+fn stats_count_used_pages() {
+    let mut a = allocator(&qemu_like_map());
+
+    let x = a.alloc(NumOfPages::new(3)).unwrap();
+    let y = a.alloc(NumOfPages::new(1)).unwrap();
+
+    assert_eq!(a.stats().used_pages, 4);
+
+    a.free(x);
+    a.free(y);
+    assert_eq!(a.stats().used_pages, 0);
+}
+
+#[test]
+fn odd_sizes_cost_exactly_their_pages() {
+    let map = qemu_like_map();
+    let mut a = allocator(&map);
+    let total = conventional_pages(&map);
+
+    for n in &[9, 3, 17, 5, 1023] {
+        let before = a.free_pages().as_usize();
+        a.alloc(NumOfPages::new(*n)).unwrap();
+
+        assert_eq!(a.free_pages().as_usize(), before - n);
+    }
+
+    assert_eq!(a.free_pages().as_usize() + a.stats().used_pages, total);
+}
+
+#[test]
+fn pages_after_an_odd_allocation_are_allocatable() {
+    let mut a = allocator(&[conventional(0x10_0000, 0x10)]);
+
+    let x = a.alloc(NumOfPages::new(9)).unwrap();
+
+    for n in &[4, 2, 1] {
+        let y = a.alloc(NumOfPages::new(*n)).unwrap();
+        assert!(y >= x + 9 * PAGE);
+    }
+
+    assert_eq!(a.free_pages().as_usize(), 0);
+}
+
+#[test]
+fn freeing_an_odd_allocation_restores_the_blocks() {
+    let mut a = allocator(&[conventional(0x10_0000, 0x10)]);
+    let initial = a.stats();
+
+    let x = a.alloc(NumOfPages::new(9)).unwrap();
+    let y = a.alloc(NumOfPages::new(3)).unwrap();
+    a.free(x);
+    a.free(y);
+
+    assert_eq!(a.stats(), initial);
+}
+
+#[test]
+fn freeing_unknown_address_is_ignored() {
+    let mut a = allocator(&qemu_like_map());
+    let before = a.stats();
+
+    a.free(PhysAddr::new(0x1234_5000));
+
+    assert_eq!(a.stats(), before);
+}
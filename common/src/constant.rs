@@ -1,6 +1,6 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use os_units::{Bytes, NumOfPages};
+use os_units::NumOfPages;
 use x86_64::{
     structures::paging::{PageSize, Size4KiB},
     PhysAddr, VirtAddr,
@@ -10,18 +10,32 @@ pub const LOCAL_APIC_ID_REGISTER_ADDR: PhysAddr = PhysAddr::new_truncate(0xfee0_
 
 pub const KERNEL_ADDR: VirtAddr = VirtAddr::new_truncate(0xffff_ffff_8000_0000);
 pub const INITRD_ADDR: VirtAddr = VirtAddr::new_truncate(0xffff_ffff_8800_0000);
-pub const VRAM_ADDR: VirtAddr = VirtAddr::new_truncate(0xffff_ffff_a000_1000);
+// Aligned to 2 MiB so that the framebuffer is mapped with huge pages.
+pub const VRAM_ADDR: VirtAddr = VirtAddr::new_truncate(0xffff_ffff_a000_0000);
 pub const STACK_BASE: VirtAddr = VirtAddr::new_truncate(0xffff_ffff_c000_0000);
 pub const STACK_LOWER: VirtAddr =
     VirtAddr::new_truncate(STACK_BASE.as_u64() - NUM_OF_PAGES_STACK.as_bytes().as_usize() as u64);
-pub const INTERRUPT_STACK: VirtAddr = VirtAddr::new_truncate(
-    STACK_BASE.as_u64() - (NUM_OF_PAGES_STACK.as_bytes().as_usize() / 2) as u64,
-);
 pub const INIT_RSP: VirtAddr = VirtAddr::new_truncate(STACK_BASE.as_u64() - Size4KiB::SIZE);
-pub const RECUR_PML4_ADDR: VirtAddr = VirtAddr::new_truncate(0xffff_ffff_ffff_f000);
+
+// The PML4 entry 511 maps the addresses above, and 510 maps the heaps. The recursive entry
+// has its own slot so that it can be accessed only by the kernel mode, while the processes of the
+// user privilege run the kernel image in ring 3.
+pub const RECUR_PML4_INDEX: u16 = 509;
+pub const RECUR_PML4_ADDR: VirtAddr = VirtAddr::new_truncate(0xffff_feff_7fbf_d000);
+
+pub const KERNEL_HEAP_START: VirtAddr = VirtAddr::new_truncate(0xffff_ff60_0000_0000);
+pub const KERNEL_HEAP_END: VirtAddr = VirtAddr::new_truncate(0xffff_ff70_0000_0000);
+pub const USER_HEAP_START: VirtAddr = VirtAddr::new_truncate(0xffff_ff70_0000_0000);
+pub const USER_HEAP_END: VirtAddr = VirtAddr::new_truncate(0xffff_ff80_0000_0000);
+
+pub const HEAP_REGION_START: VirtAddr = VirtAddr::new_truncate(0x1000_0000_0000);
+pub const HEAP_REGION_END: VirtAddr = VirtAddr::new_truncate(0x4000_0000_0000);
+pub const STACK_REGION_START: VirtAddr = VirtAddr::new_truncate(0x4000_0000_0000);
+pub const STACK_REGION_END: VirtAddr = VirtAddr::new_truncate(0x5000_0000_0000);
+pub const MMIO_REGION_START: VirtAddr = VirtAddr::new_truncate(0x5000_0000_0000);
+pub const MMIO_REGION_END: VirtAddr = VirtAddr::new_truncate(0x7000_0000_0000);
 
 pub const NUM_OF_PAGES_STACK: NumOfPages<Size4KiB> = NumOfPages::new(16);
-pub const BYTES_AVAILABLE_RAM: Bytes = Bytes::new(0x1_0000_0000_0000);
 
 pub const PORT_KEY_STATUS: u16 = 0x0064;
 pub const PORT_KEY_CMD: u16 = 0x0064;
@@ -1,6 +1,6 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use crate::{constant::INIT_RSP, mem, vram};
+use crate::{constant::INIT_RSP, mem, mem::reserved, vram};
 use core::ptr;
 use uefi::table::boot;
 use x86_64::{PhysAddr, VirtAddr};
@@ -11,6 +11,7 @@ pub struct Info {
     vram_info: vram::Info,
     mem_map: mem::Map,
     rsdp: PhysAddr,
+    initrd: reserved::PhysRange,
 }
 
 impl Info {
@@ -21,12 +22,14 @@ impl Info {
         vram_info: vram::Info,
         mem_map: mem::Map,
         rsdp: PhysAddr,
+        initrd: reserved::PhysRange,
     ) -> Self {
         Self {
             entry_addr,
             vram_info,
             mem_map,
             rsdp,
+            initrd,
         }
     }
 
@@ -45,6 +48,11 @@ impl Info {
         self.rsdp
     }
 
+    #[must_use]
+    pub fn initrd(&self) -> reserved::PhysRange {
+        self.initrd
+    }
+
     pub fn set(self) {
         unsafe {
             ptr::write(INIT_RSP.as_mut_ptr::<Self>(), self);
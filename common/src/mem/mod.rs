@@ -1,5 +1,6 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+pub mod paging;
 pub mod reserved;
 
 use core::{ptr::NonNull, slice};
@@ -0,0 +1,111 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use core::{arch::x86_64::__cpuid, convert::TryFrom, fmt};
+use os_units::Bytes;
+use x86_64::{
+    structures::paging::{
+        FrameAllocator, Mapper, Page, PageSize, PageTableFlags, PhysFrame, Size1GiB, Size2MiB,
+        Size4KiB,
+    },
+    PhysAddr, VirtAddr,
+};
+
+/// A region which `map_range` maps.
+#[derive(Copy, Clone, Debug)]
+pub struct Region {
+    pub virt: VirtAddr,
+    pub phys: PhysAddr,
+    pub bytes: Bytes,
+    pub flags: PageTableFlags,
+}
+impl Region {
+    /// Returns the rest of this region after the first `bytes` bytes.
+    fn skip(&self, bytes: u64) -> Self {
+        let skipped = usize::try_from(bytes).unwrap();
+
+        Self {
+            virt: self.virt + bytes,
+            phys: self.phys + bytes,
+            bytes: Bytes::new(self.bytes.as_usize().saturating_sub(skipped)),
+            flags: self.flags,
+        }
+    }
+
+    /// Returns `true` if this region begins with a page of the size `size`.
+    fn begins_with_page_of(&self, size: u64) -> bool {
+        let bytes = u64::try_from(self.bytes.as_usize()).unwrap();
+
+        self.virt.is_aligned(size) && self.phys.is_aligned(size) && bytes >= size
+    }
+}
+
+/// Maps `region`. Each part is mapped with the largest page which both addresses are aligned to
+/// and the rest of the region fills, so a large region aligned like its physical address takes a
+/// few entries. The frames of new page tables are allocated from `allocator`.
+///
+/// # Safety
+///
+/// The caller must ensure that the region is not mapped and no one else uses the frames through
+/// another mapping which breaks memory safety.
+///
+/// # Panics
+///
+/// This function panics if mapping fails, such as when a page table cannot be allocated.
+pub unsafe fn map_range<M, A>(mapper: &mut M, region: Region, allocator: &mut A)
+where
+    M: Mapper<Size4KiB> + Mapper<Size2MiB> + Mapper<Size1GiB>,
+    A: FrameAllocator<Size4KiB>,
+{
+    let supports_1gib = supports_1gib_pages();
+    let bytes = region.bytes.as_num_of_pages::<Size4KiB>().as_bytes();
+    let region = Region { bytes, ..region };
+
+    let mut offset = 0;
+    while offset < u64::try_from(bytes.as_usize()).unwrap() {
+        let rest = region.skip(offset);
+
+        offset += if supports_1gib && rest.begins_with_page_of(Size1GiB::SIZE) {
+            map::<Size1GiB, _, _>(mapper, &rest, allocator)
+        } else if rest.begins_with_page_of(Size2MiB::SIZE) {
+            map::<Size2MiB, _, _>(mapper, &rest, allocator)
+        } else {
+            map::<Size4KiB, _, _>(mapper, &rest, allocator)
+        };
+    }
+}
+
+/// Returns the size of the largest page which a region of `bytes` bytes can contain. The MMIO and
+/// heap allocators align a region to this so that `map_range` maps it with such pages.
+#[must_use]
+pub fn largest_page_size(bytes: u64) -> u64 {
+    if bytes >= Size1GiB::SIZE && supports_1gib_pages() {
+        Size1GiB::SIZE
+    } else if bytes >= Size2MiB::SIZE {
+        Size2MiB::SIZE
+    } else {
+        Size4KiB::SIZE
+    }
+}
+
+/// Maps the first page of `region` with a page of the size `S`, and returns the size.
+unsafe fn map<S, M, A>(mapper: &mut M, region: &Region, allocator: &mut A) -> u64
+where
+    S: PageSize + fmt::Debug,
+    M: Mapper<S>,
+    A: FrameAllocator<Size4KiB>,
+{
+    let page = Page::<S>::from_start_address(region.virt).expect("The page is not aligned.");
+    let frame = PhysFrame::<S>::from_start_address(region.phys).expect("The frame is not aligned.");
+
+    mapper
+        .map_to(page, frame, region.flags, allocator)
+        .expect("Failed to map a page.")
+        .flush();
+
+    S::SIZE
+}
+
+fn supports_1gib_pages() -> bool {
+    // SAFETY: Every processor of the x86_64 architecture supports this leaf.
+    unsafe { __cpuid(0x8000_0001) }.edx & (1 << 26) != 0
+}
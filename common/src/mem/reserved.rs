@@ -1,12 +1,14 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use crate::{
-    constant::{KERNEL_ADDR, NUM_OF_PAGES_STACK, STACK_LOWER, VRAM_ADDR},
+    constant::{INITRD_ADDR, KERNEL_ADDR, NUM_OF_PAGES_STACK, STACK_LOWER, VRAM_ADDR},
     vram,
 };
 use os_units::Bytes;
 use x86_64::{PhysAddr, VirtAddr};
 
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
 pub struct PhysRange {
     start: PhysAddr,
     bytes: Bytes,
@@ -17,20 +19,36 @@ impl PhysRange {
     pub fn new(start: PhysAddr, bytes: Bytes) -> Self {
         Self { start, bytes }
     }
+
+    #[must_use]
+    pub fn start(&self) -> PhysAddr {
+        self.start
+    }
+
+    #[must_use]
+    pub fn bytes(&self) -> Bytes {
+        self.bytes
+    }
 }
 
 #[repr(C)]
 #[derive(Copy, Clone)]
-pub struct Map([Range; 3]);
+pub struct Map([Range; 4]);
 impl Map {
     #[must_use]
     #[allow(clippy::too_many_arguments)]
-    pub fn new(kernel: &PhysRange, phys_addr_stack: PhysAddr, vram: &vram::Info) -> Self {
+    pub fn new(
+        kernel: &PhysRange,
+        phys_addr_stack: PhysAddr,
+        vram: &vram::Info,
+        initrd: &PhysRange,
+    ) -> Self {
         Self {
             0: [
                 Range::kernel(&kernel),
                 Range::stack(phys_addr_stack),
                 Range::vram(vram),
+                Range::initrd(initrd),
             ],
         }
     }
@@ -68,6 +86,15 @@ impl Range {
         }
     }
 
+    #[must_use]
+    fn initrd(initrd: &PhysRange) -> Self {
+        Self {
+            virt: INITRD_ADDR,
+            phys: initrd.start,
+            bytes: initrd.bytes,
+        }
+    }
+
     #[must_use]
     fn stack(phys: PhysAddr) -> Self {
         Self {
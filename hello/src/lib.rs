@@ -0,0 +1,90 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+#![no_std]
+#![feature(asm, naked_functions)]
+#![deny(clippy::pedantic)]
+#![deny(clippy::all)]
+
+use core::{
+    convert::{TryFrom, TryInto},
+    ffi::c_void,
+    slice,
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+/// The number of the runs of this program in this process. Every process has its own copy, so the
+/// program started with the argument `count` exits with the status 1. The program started with the
+/// argument `env` exits with the number of the elements of its environment instead.
+static RUNS: AtomicI32 = AtomicI32::new(0);
+
+#[naked]
+#[no_mangle]
+extern "C" fn _start() -> ! {
+    // SAFETY: The kernel sets `rsp` to the address of `argc`.
+    unsafe { asm!("mov rdi, rsp", "call {}", sym main, options(noreturn)) }
+}
+
+/// # Safety
+///
+/// `stack` must point to `argc`, followed by `argv` and `envp`.
+unsafe extern "C" fn main(stack: *const u64) -> ! {
+    let num_args = *stack;
+    let args: *const *const u8 = stack.add(1).cast();
+
+    let runs = RUNS.fetch_add(1, Ordering::Relaxed) + 1;
+
+    print("Hello from a user program! Arguments:");
+    for i in 0..num_args {
+        print(core::str::from_utf8_unchecked(c_str(
+            *args.add(i.try_into().unwrap()),
+        )));
+    }
+
+    if num_args > 1 && c_str(*args.add(1)) == b"count" {
+        syscalls::exit_with_status(runs);
+    }
+
+    if num_args > 1 && c_str(*args.add(1)) == b"env" {
+        let envp = args.add(usize::try_from(num_args).unwrap() + 1);
+        syscalls::exit_with_status(count_env(envp));
+    }
+
+    syscalls::exit();
+}
+
+/// Prints the elements of the environment and returns the number of them.
+///
+/// # Safety
+///
+/// `envp` must be a null-terminated array of valid null-terminated strings.
+unsafe fn count_env(envp: *const *const u8) -> i32 {
+    let mut n = 0;
+    while !(*envp.add(n)).is_null() {
+        print(core::str::from_utf8_unchecked(c_str(*envp.add(n))));
+        n += 1;
+    }
+
+    n.try_into().unwrap()
+}
+
+/// # Safety
+///
+/// `s` must be a valid null-terminated string.
+unsafe fn c_str<'a>(s: *const u8) -> &'a [u8] {
+    let mut len = 0;
+    while *s.add(len) != 0 {
+        len += 1;
+    }
+
+    slice::from_raw_parts(s, len)
+}
+
+fn print(s: &str) {
+    // SAFETY: `s` is a valid string.
+    let _ = unsafe { syscalls::write(1, s.as_ptr().cast::<c_void>(), s.len().try_into().unwrap()) };
+}
+
+#[panic_handler]
+fn panic(_: &core::panic::PanicInfo) -> ! {
+    syscalls::exit();
+}
@@ -6,9 +6,19 @@ pub mod type_spec;
 
 use self::common::Common;
 use bar::Bar;
-use core::{convert::TryFrom, ops::Add};
+use core::{
+    convert::TryFrom,
+    ops::{Add, Range, RangeInclusive},
+};
 use type_spec::TypeSpec;
-use x86_64::PhysAddr;
+use x86_64::{
+    instructions::port::{PortReadOnly, PortWriteOnly},
+    PhysAddr,
+};
+
+/// The I/O ports to access the configuration spaces.
+pub const PORTS: RangeInclusive<u16> =
+    ConfigAddress::PORT_CONFIG_ADDR..=ConfigAddress::PORT_CONFIG_DATA + 3;
 
 #[derive(Debug)]
 pub struct Space {
@@ -30,6 +40,12 @@ impl Space {
         self.type_spec().base_address(index)
     }
 
+    /// Returns the range of the memory which the BAR `index` decodes. The BAR is rewritten to find
+    /// its size, so this must be called before a driver uses the device.
+    pub fn bar_range(&self, index: bar::Index) -> Range<PhysAddr> {
+        self.type_spec().bar_range(index)
+    }
+
     fn type_spec(&self) -> TypeSpec {
         TypeSpec::new(&self.registers, &self.common())
     }
@@ -64,6 +80,11 @@ impl Registers {
         let accessor = ConfigAddress::new(self.bus, self.device, Function::zero(), index);
         unsafe { accessor.read() }
     }
+
+    fn set(&self, index: RegisterIndex, value: u32) {
+        let accessor = ConfigAddress::new(self.bus, self.device, Function::zero(), index);
+        unsafe { accessor.write(value) }
+    }
 }
 
 struct ConfigAddress {
@@ -97,10 +118,18 @@ impl ConfigAddress {
         VALID | bus << 16 | device << 11 | function << 8 | register << 2
     }
 
-    /// SAFETY: `self` must contain the valid config address.
+    /// SAFETY: `self` must contain the valid config address. The current process must be allowed
+    /// to access `PORTS`.
     unsafe fn read(&self) -> u32 {
-        syscalls::outl(Self::PORT_CONFIG_ADDR, self.as_u32());
-        syscalls::inl(Self::PORT_CONFIG_DATA)
+        PortWriteOnly::new(Self::PORT_CONFIG_ADDR).write(self.as_u32());
+        PortReadOnly::new(Self::PORT_CONFIG_DATA).read()
+    }
+
+    /// SAFETY: `self` must contain the valid config address. The current process must be allowed
+    /// to access `PORTS`.
+    unsafe fn write(&self, value: u32) {
+        PortWriteOnly::new(Self::PORT_CONFIG_ADDR).write(self.as_u32());
+        PortWriteOnly::new(Self::PORT_CONFIG_DATA).write(value);
     }
 }
 
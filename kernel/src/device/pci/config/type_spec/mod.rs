@@ -7,6 +7,7 @@ use super::{
     common::{BridgeType, Common},
     Bar, RegisterIndex, Registers,
 };
+use core::ops::Range;
 use x86_64::PhysAddr;
 
 #[derive(Debug)]
@@ -26,4 +27,9 @@ impl<'a> TypeSpec<'a> {
         let TypeSpec::NonBridge(non_bridge) = self;
         non_bridge.base_addr(index)
     }
+
+    pub fn bar_range(&self, index: bar::Index) -> Range<PhysAddr> {
+        let TypeSpec::NonBridge(non_bridge) = self;
+        non_bridge.bar_range(index)
+    }
 }
@@ -1,8 +1,16 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use super::{bar, Bar, RegisterIndex, Registers};
+use core::ops::Range;
 use x86_64::PhysAddr;
 
+/// The register containing the command register in the lower half and the status register in the
+/// upper half. Writing zeros to the status register does not change it.
+const COMMAND: RegisterIndex = RegisterIndex(1);
+
+/// The bit of the command register which enables the device to respond to memory accesses.
+const MEMORY_SPACE: u32 = 1 << 1;
+
 #[derive(Debug)]
 pub struct TypeSpec<'a> {
     registers: &'a Registers,
@@ -29,6 +37,40 @@ impl<'a> TypeSpec<'a> {
             .expect("Could not calculate Base Address.")
     }
 
+    pub fn bar_range(&self, index: bar::Index) -> Range<PhysAddr> {
+        let start = self.base_addr(index);
+        start..start + self.bar_size(index)
+    }
+
+    fn bar_size(&self, index: bar::Index) -> u64 {
+        let low = self.probe(index);
+        let high = if self.bar(index).is_64bit() {
+            self.probe(index + 1)
+        } else {
+            !0
+        };
+
+        let mask = u64::from(high) << 32 | u64::from(low & !0xf);
+        (!mask).wrapping_add(1)
+    }
+
+    /// Writes all ones to the BAR `index`, and returns the value read back. The bits which stay
+    /// zero give the size of the decoded memory. Memory decoding is disabled meanwhile so that the
+    /// device does not respond to the temporary address.
+    fn probe(&self, index: bar::Index) -> u32 {
+        let command = self.registers.get(COMMAND) & 0xffff;
+        self.registers.set(COMMAND, command & !MEMORY_SPACE);
+
+        let register = RegisterIndex::from(index);
+        let original = self.registers.get(register);
+        self.registers.set(register, !0);
+        let probed = self.registers.get(register);
+        self.registers.set(register, original);
+
+        self.registers.set(COMMAND, command);
+        probed
+    }
+
     fn bar(&self, index: bar::Index) -> Bar {
         Bar::new(self.registers.get(RegisterIndex::from(index)))
     }
@@ -5,9 +5,10 @@ mod port;
 mod structures;
 mod xhc;
 
-use super::config::bar;
+use super::config::{self, bar};
 use crate::multitask::{self, task::Task};
 use alloc::sync::Arc;
+use core::ops::Range;
 use spinning_top::Spinlock;
 use structures::{
     dcbaa, extended_capabilities, registers,
@@ -64,6 +65,14 @@ fn init() -> event::Ring {
     event_ring
 }
 
+/// Returns the MMIO range of the xHCI controller which the driver uses. This must be called before
+/// the driver starts. See `config::Space::bar_range`.
+pub fn mmio() -> Option<Range<PhysAddr>> {
+    super::iter_devices()
+        .find(config::Space::is_xhci)
+        .map(|d| d.bar_range(bar::Index::new(0)))
+}
+
 fn iter_devices() -> impl Iterator<Item = PhysAddr> {
     super::iter_devices().filter_map(|device| {
         if device.is_xhci() {
@@ -1,7 +1,7 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use super::slot_structures_initializer::SlotStructuresInitializer;
-use crate::device::pci::xhci::structures::registers;
+use crate::{device::pci::xhci::structures::registers, multitask::timer::Timer};
 use xhci::registers::PortRegisterSet;
 
 pub(super) struct Resetter {
@@ -18,7 +18,7 @@ impl Resetter {
 
     pub(super) async fn reset(self) -> SlotStructuresInitializer {
         self.start_resetting();
-        self.wait_until_reset_is_completed();
+        self.wait_until_reset_is_completed().await;
         SlotStructuresInitializer::new(self).await
     }
 
@@ -26,8 +26,10 @@ impl Resetter {
         self.update_port_register(|r| r.portsc.set_port_reset(true));
     }
 
-    fn wait_until_reset_is_completed(&self) {
-        while !self.reset_completed() {}
+    async fn wait_until_reset_is_completed(&self) {
+        while !self.reset_completed() {
+            Timer::after(1).await;
+        }
     }
 
     fn reset_completed(&self) -> bool {
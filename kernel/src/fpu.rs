@@ -0,0 +1,167 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// The x87 FPU, SSE and AVX registers are saved to the area of the running process and restored
+// from that of the next process on every process switch.
+//
+// The kernel target disables SSE and uses `soft-float`, so the kernel never touches these
+// registers, and their contents always belong to the process which ran last. Do not enable SSE for
+// the kernel, or it clobbers the registers of processes.
+
+use conquer_once::spin::OnceCell;
+use core::arch::x86_64::{__cpuid, __cpuid_count};
+use page_box::PageBox;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+
+static MODE: OnceCell<Mode> = OnceCell::uninit();
+
+/// The size of the area used by `fxsave`.
+const FXSAVE_AREA_SIZE: usize = 512;
+
+/// The initial value of the x87 FPU control word. All exceptions are masked.
+const INITIAL_FCW: u16 = 0x037f;
+
+/// The initial value of MXCSR. All exceptions are masked.
+const INITIAL_MXCSR: u32 = 0x1f80;
+
+const MXCSR_OFFSET: usize = 24;
+
+/// The bits of XCR0.
+const XCR0_X87: u64 = 1 << 0;
+const XCR0_SSE: u64 = 1 << 1;
+const XCR0_AVX: u64 = 1 << 2;
+
+/// Enables the registers on the current CPU. Every CPU calls this.
+pub fn init() {
+    enable_sse();
+
+    let mode = if xsave_supported() {
+        enable_xsave()
+    } else {
+        Mode::Fxsave
+    };
+
+    // All CPUs have the same features, so the area size is the same.
+    MODE.init_once(|| mode);
+}
+
+/// The area to which the registers of a process are saved.
+#[derive(Debug)]
+pub struct Area(PageBox<[u8]>);
+impl Area {
+    /// Saves the registers of the current CPU to this area.
+    pub fn save(&mut self) {
+        let p = self.0.as_mut_ptr();
+
+        // SAFETY: The area is large enough and 64-byte aligned as it is page-aligned.
+        unsafe {
+            match mode() {
+                Mode::Xsave { .. } => {
+                    asm!("xsave64 [{}]", in(reg) p, in("eax") u32::MAX, in("edx") u32::MAX)
+                }
+                Mode::Fxsave => asm!("fxsave64 [{}]", in(reg) p),
+            }
+        }
+    }
+
+    /// Loads the registers from this area.
+    pub fn restore(&self) {
+        let p = self.0.as_ptr();
+
+        // SAFETY: The area contains the values saved by `save` or the valid initial values.
+        unsafe {
+            match mode() {
+                Mode::Xsave { .. } => {
+                    asm!("xrstor64 [{}]", in(reg) p, in("eax") u32::MAX, in("edx") u32::MAX)
+                }
+                Mode::Fxsave => asm!("fxrstor64 [{}]", in(reg) p),
+            }
+        }
+    }
+
+    pub fn page_box(&self) -> &PageBox<[u8]> {
+        &self.0
+    }
+}
+impl Default for Area {
+    /// Returns the area which restores the initial state. The XSAVE header is zero, so the
+    /// components other than MXCSR are set to their initial values.
+    fn default() -> Self {
+        let mut b = PageBox::new_slice(0, mode().area_size());
+
+        b[..2].copy_from_slice(&INITIAL_FCW.to_le_bytes());
+        b[MXCSR_OFFSET..MXCSR_OFFSET + 4].copy_from_slice(&INITIAL_MXCSR.to_le_bytes());
+
+        Self(b)
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Mode {
+    Xsave { area_size: usize },
+    Fxsave,
+}
+impl Mode {
+    fn area_size(self) -> usize {
+        match self {
+            Self::Xsave { area_size } => area_size,
+            Self::Fxsave => FXSAVE_AREA_SIZE,
+        }
+    }
+}
+
+fn mode() -> Mode {
+    *MODE.try_get().expect("`MODE` is not initialized.")
+}
+
+fn enable_sse() {
+    // SAFETY: The kernel does not use the FPU, so changing the settings does not affect it.
+    unsafe {
+        Cr0::update(|f| {
+            f.remove(Cr0Flags::EMULATE_COPROCESSOR);
+            f.insert(Cr0Flags::MONITOR_COPROCESSOR);
+        });
+        Cr4::update(|f| f.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE));
+    }
+}
+
+/// Enables the x87 FPU, SSE, and AVX if available, and returns the mode with the size of the area
+/// for them.
+fn enable_xsave() -> Mode {
+    // SAFETY: The CPU supports XSAVE.
+    unsafe { Cr4::update(|f| f.insert(Cr4Flags::OSXSAVE)) };
+
+    let mut xcr0 = XCR0_X87 | XCR0_SSE;
+    if avx_supported() {
+        xcr0 |= XCR0_AVX;
+    }
+
+    // SAFETY: The components are supported by the CPU, and the kernel does not use them.
+    unsafe { write_xcr0(xcr0) };
+
+    // SAFETY: The CPU supports the leaf 0x0d as it supports XSAVE.
+    let r = unsafe { __cpuid_count(0x0d, 0) };
+
+    // EBX is the size of the area for the components enabled in XCR0.
+    Mode::Xsave {
+        area_size: r.ebx as usize,
+    }
+}
+
+fn xsave_supported() -> bool {
+    // SAFETY: Every x86_64 CPU supports the leaf 1.
+    unsafe { __cpuid(1) }.ecx & (1 << 26) != 0
+}
+
+fn avx_supported() -> bool {
+    // SAFETY: Every x86_64 CPU supports the leaf 1.
+    unsafe { __cpuid(1) }.ecx & (1 << 28) != 0
+}
+
+/// SAFETY: This function is unsafe because enabling unsupported components causes an exception.
+unsafe fn write_xcr0(v: u64) {
+    // `xsetbv` takes the value in EDX:EAX.
+    #[allow(clippy::cast_possible_truncation)]
+    let (low, high) = (v as u32, (v >> 32) as u32);
+
+    asm!("xsetbv", in("ecx") 0, in("eax") low, in("edx") high);
+}
@@ -1,35 +1,21 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use crate::{
-    tss::TSS,
+    smp::{self, MAX_CPUS},
+    tss::{self, TSS},
     x86_64::{
         instructions::{segmentation, tables},
         structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector},
     },
 };
-use conquer_once::spin::Lazy;
+use conquer_once::spin::OnceCell;
 use x86_64::registers::model_specific::Star;
 
-pub static GDT: Lazy<Gdt> = Lazy::new(|| {
-    let mut gdt = GlobalDescriptorTable::new();
-    let kernel_code = gdt.add_entry(Descriptor::kernel_code_segment());
-    let kernel_data = gdt.add_entry(Descriptor::kernel_data_segment());
-    let user_data = gdt.add_entry(Descriptor::user_data_segment());
-    let user_code = gdt.add_entry(Descriptor::user_code_segment());
+/// The GDT of each CPU. They differ only in the TSS, so the selectors are the same.
+static GDT: [OnceCell<Gdt>; MAX_CPUS] = [GDT_INIT; MAX_CPUS];
 
-    // SAFETY: This operation is safe because there is no instances of `MutexGuard` which wraps
-    // `TSS`.
-    let tss_selector = gdt.add_entry(Descriptor::tss_segment(unsafe { &*TSS.data_ptr() }));
-
-    Gdt {
-        table: gdt,
-        kernel_code,
-        kernel_data,
-        user_data,
-        user_code,
-        tss_selector,
-    }
-});
+#[allow(clippy::declare_interior_mutable_const)]
+const GDT_INIT: OnceCell<Gdt> = OnceCell::uninit();
 
 pub struct Gdt {
     table: GlobalDescriptorTable,
@@ -39,52 +25,64 @@ pub struct Gdt {
     pub user_data: SegmentSelector,
     tss_selector: SegmentSelector,
 }
+impl Gdt {
+    fn new(cpu: usize) -> Self {
+        let mut gdt = GlobalDescriptorTable::new();
+        let kernel_code = gdt.add_entry(Descriptor::kernel_code_segment());
+        let kernel_data = gdt.add_entry(Descriptor::kernel_data_segment());
+        let user_data = gdt.add_entry(Descriptor::user_data_segment());
+        let user_code = gdt.add_entry(Descriptor::user_code_segment());
+
+        // SAFETY: This operation is safe because the TSS is never moved and the CPU accesses it
+        // only through the descriptor.
+        let tss_selector = gdt.add_entry(tss::descriptor(unsafe { &*TSS[cpu].data_ptr() }));
+
+        Self {
+            table: gdt,
+            kernel_code,
+            kernel_data,
+            user_data,
+            user_code,
+            tss_selector,
+        }
+    }
+}
 
+/// Loads the GDT and the TSS of the current CPU.
 pub fn init() {
-    GDT.table.load();
+    let cpu = smp::id();
+    GDT[cpu]
+        .try_init_once(|| Gdt::new(cpu))
+        .expect("The GDT is initialized more than once.");
+
+    let gdt = current();
+    gdt.table.load();
     unsafe {
-        segmentation::set_cs(GDT.kernel_code);
+        segmentation::set_cs(gdt.kernel_code);
 
-        segmentation::load_ds(GDT.kernel_data);
-        segmentation::load_es(GDT.kernel_data);
-        segmentation::load_fs(GDT.kernel_data);
-        segmentation::load_gs(GDT.kernel_data);
-        segmentation::load_ss(GDT.kernel_data);
-        tables::load_tss(GDT.tss_selector);
+        segmentation::load_ds(gdt.kernel_data);
+        segmentation::load_es(gdt.kernel_data);
+        segmentation::load_fs(gdt.kernel_data);
+        // GS is not loaded as it would clear the base, which points to the per-CPU block.
+        segmentation::load_ss(gdt.kernel_data);
+        tables::load_tss(gdt.tss_selector);
     }
 
     init_star();
 }
 
-pub fn enter_usermode() {
-    unsafe {
-        segmentation::load_ds(GDT.user_data);
-        segmentation::load_es(GDT.user_data);
-        segmentation::load_fs(GDT.user_data);
-        segmentation::load_gs(GDT.user_data);
-
-        let data = u64::from(GDT.user_data.0);
-        let code = u64::from(GDT.user_code.0);
-
-        asm!("
-                mov rax, rsp
-                push rbx
-                push rax
-                pushfq
-                push rcx
-                lea rdx, [1f + rip]
-                push rdx
-                iretq
-                1:", in("rbx") data, in("rcx") code,lateout("rdx") _,); // Do not specify in(reg) or lateout(reg) as these do not consider which registers are used. They may use registers which are already used.
-    }
+/// Returns the GDT of the current CPU.
+pub fn current() -> &'static Gdt {
+    GDT[smp::id()].get().expect("The GDT is not initialized.")
 }
 
 fn init_star() {
+    let gdt = current();
     Star::write(
-        GDT.user_code,
-        GDT.user_data,
-        GDT.kernel_code,
-        GDT.kernel_data,
+        gdt.user_code,
+        gdt.user_data,
+        gdt.kernel_code,
+        gdt.kernel_data,
     )
     .unwrap();
 }
@@ -0,0 +1,114 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// The initrd is an archive in the ustar format. See
+// https://pubs.opengroup.org/onlinepubs/9699919799/utilities/pax.html#tag_20_92_13_06
+
+use common::{constant::INITRD_ADDR, kernelboot};
+use conquer_once::spin::OnceCell;
+use core::{slice, str};
+
+static INITRD: OnceCell<&'static [u8]> = OnceCell::uninit();
+
+pub fn init(boot_info: &kernelboot::Info) {
+    let bytes = boot_info.initrd().bytes();
+
+    // SAFETY: The bootloader maps the whole initrd to `INITRD_ADDR`, and the region is never
+    // freed.
+    let s = unsafe { slice::from_raw_parts(INITRD_ADDR.as_ptr(), bytes.as_usize()) };
+
+    INITRD
+        .try_init_once(|| s)
+        .expect("`INITRD` is initialized more than once.");
+
+    info!("initrd: {} bytes", bytes.as_usize());
+}
+
+/// Returns the content of the file named `name` in the initrd.
+pub fn get(name: &str) -> Option<&'static [u8]> {
+    Files::new(initrd())
+        .find(|(n, _)| *n == name)
+        .map(|(_, c)| c)
+}
+
+/// Returns the content of the file at `path`. The initrd is the root directory, so `/name` and
+/// `name` are the same file.
+pub fn open(path: &str) -> Option<&'static [u8]> {
+    get(path.strip_prefix('/').unwrap_or(path))
+}
+
+fn initrd() -> &'static [u8] {
+    INITRD.try_get().expect("`INITRD` is not initialized.")
+}
+
+struct Files {
+    archive: &'static [u8],
+}
+impl Files {
+    fn new(archive: &'static [u8]) -> Self {
+        Self { archive }
+    }
+}
+impl Iterator for Files {
+    type Item = (&'static str, &'static [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let header = Header::new(self.archive)?;
+            let content_start = Header::SIZE;
+            let content_end = content_start + header.size();
+            let content = self.archive.get(content_start..content_end)?;
+
+            let next = content_start + round_up_to_block(header.size());
+            self.archive = self.archive.get(next..).unwrap_or(&[]);
+
+            if header.is_regular_file() {
+                return Some((header.name(), content));
+            }
+        }
+    }
+}
+
+struct Header {
+    raw: &'static [u8],
+}
+impl Header {
+    const SIZE: usize = 512;
+
+    fn new(archive: &'static [u8]) -> Option<Self> {
+        let raw = archive.get(..Self::SIZE)?;
+
+        // An archive ends with two zero-filled blocks.
+        if raw.iter().all(|b| *b == 0) {
+            None
+        } else {
+            Some(Self { raw })
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        let n = Self::null_terminated_str(&self.raw[0..100]);
+
+        // `tar` creates entries like `./foo`.
+        n.strip_prefix("./").unwrap_or(n)
+    }
+
+    fn size(&self) -> usize {
+        let s = Self::null_terminated_str(&self.raw[124..136]).trim();
+
+        usize::from_str_radix(s, 8).expect("Invalid file size in the initrd.")
+    }
+
+    fn is_regular_file(&self) -> bool {
+        matches!(self.raw[156], b'0' | b'\0')
+    }
+
+    fn null_terminated_str(s: &'static [u8]) -> &'static str {
+        let len = s.iter().position(|b| *b == 0).unwrap_or(s.len());
+
+        str::from_utf8(&s[..len]).expect("The initrd contains a non-UTF-8 name.")
+    }
+}
+
+fn round_up_to_block(n: usize) -> usize {
+    (n + Header::SIZE - 1) / Header::SIZE * Header::SIZE
+}
@@ -1,14 +1,15 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use super::pic;
-use crate::mem::{accessor::Single, allocator};
+use crate::{
+    mem::{accessor::Single, allocator},
+    smp,
+};
 use acpi::{platform::IoApic, AcpiTables, InterruptModel};
 use bit_field::BitField;
 use core::convert::TryInto;
 use x86_64::PhysAddr;
 
-/// Currently this OS does not support multiple I/O APIC.
-
 struct Registers {
     addr: Single<u32>,
     data: Single<u32>,
@@ -23,8 +24,8 @@ impl Registers {
     /// the all fields of the struct are public, this method is unsafe.
     ///
     /// This method must be called in the kernel privilege.
-    unsafe fn new(io_apics: &[IoApic]) -> Self {
-        let io_apic_base = PhysAddr::new(io_apics[0].address.into());
+    unsafe fn new(io_apic: &IoApic) -> Self {
+        let io_apic_base = PhysAddr::new(io_apic.address.into());
 
         Self {
             addr: crate::mem::accessor::kernel(io_apic_base),
@@ -42,8 +43,6 @@ impl Registers {
     }
 
     fn mask_all(&mut self) {
-        const MAX_IRQ: u8 = 24;
-
         for i in 0..MAX_IRQ {
             self.mask(i);
         }
@@ -130,22 +129,46 @@ impl TriggerMode {
     }
 }
 
+/// The number of the redirection entries of an I/O APIC.
+const MAX_IRQ: u8 = 24;
+
+const KEYBOARD_IRQ: u32 = 1;
+const MOUSE_IRQ: u32 = 12;
+
+/// Routes the interrupts of the devices to the BSP. An IRQ is handled by the I/O APIC whose range
+/// of global system interrupts contains it.
 pub fn init(table: &AcpiTables<allocator::acpi::Mapper>) {
     pic::disable();
     let platform_info = table.platform_info().unwrap();
     let interrupt = platform_info.interrupt_model;
     if let InterruptModel::Apic(apic) = interrupt {
-        let id = apic.io_apics[0].id;
+        let bsp = smp::local_apic_id();
+
+        for io_apic in &apic.io_apics {
+            // SAFETY: This operation is safe because `table` contains valid information.
+            let mut registers = unsafe { Registers::new(io_apic) };
+            registers.mask_all();
 
-        // SAFETY: This operation is safe because `table` contains valid information.
-        let mut registers = unsafe { Registers::new(&apic.io_apics) };
-        registers.mask_all();
-        init_ps2_keyboard(&mut registers, id);
-        init_ps2_mouse(&mut registers, id);
+            if let Some(pin) = pin_of(io_apic, KEYBOARD_IRQ) {
+                init_ps2_keyboard(&mut registers, pin, bsp);
+            }
+
+            if let Some(pin) = pin_of(io_apic, MOUSE_IRQ) {
+                init_ps2_mouse(&mut registers, pin, bsp);
+            }
+        }
     }
 }
 
-fn init_ps2_keyboard(r: &mut Registers, apic_id: u8) {
+/// Returns the pin of `io_apic` to which `irq` is connected, or `None` if it is connected to
+/// another I/O APIC.
+fn pin_of(io_apic: &IoApic, irq: u32) -> Option<u8> {
+    irq.checked_sub(io_apic.global_system_interrupt_base)
+        .filter(|pin| *pin < u32::from(MAX_IRQ))
+        .map(|pin| pin.try_into().unwrap())
+}
+
+fn init_ps2_keyboard(r: &mut Registers, pin: u8, apic_id: u8) {
     let key = RedirectionBuilder::default()
         .vec(0x21)
         .delivery(Delivery::Normal)
@@ -156,10 +179,10 @@ fn init_ps2_keyboard(r: &mut Registers, apic_id: u8) {
         .build()
         .unwrap();
 
-    r.set_redirection(1, &key);
+    r.set_redirection(pin, &key);
 }
 
-fn init_ps2_mouse(r: &mut Registers, apic_id: u8) {
+fn init_ps2_mouse(r: &mut Registers, pin: u8, apic_id: u8) {
     let mouse = RedirectionBuilder::default()
         .vec(0x2c)
         .delivery(Delivery::Normal)
@@ -170,5 +193,5 @@ fn init_ps2_mouse(r: &mut Registers, apic_id: u8) {
         .build()
         .unwrap();
 
-    r.set_redirection(12, &mouse);
+    r.set_redirection(pin, &mouse);
 }
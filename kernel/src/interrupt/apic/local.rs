@@ -1,12 +1,76 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use bit_field::BitField;
 use x86_64::PhysAddr;
 
 const REGISTER_BASE: PhysAddr = PhysAddr::new_truncate(0xfee0_0000);
 
+const EOI: usize = 0xb0;
+const SPURIOUS_INTERRUPT_VECTOR: usize = 0xf0;
+const INTERRUPT_COMMAND_LOW: usize = 0x300;
+const INTERRUPT_COMMAND_HIGH: usize = 0x310;
+
+/// The vector of the spurious interrupts. Its handler does not send EOI.
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
 pub fn end_of_interrupt() {
     // SAFETY: This operation is safe because `REGISTER_BASE` is the valid address to the Local APIC
     // registers.
-    let mut r = unsafe { crate::mem::accessor::kernel::<u32>(REGISTER_BASE + 0xb0_usize) };
+    let mut r = unsafe { crate::mem::accessor::kernel::<u32>(REGISTER_BASE + EOI) };
     r.write(0);
 }
+
+/// Enables the local APIC of the current CPU. An AP starts with its local APIC disabled.
+pub fn enable() {
+    // SAFETY: See `end_of_interrupt`.
+    let mut r =
+        unsafe { crate::mem::accessor::kernel::<u32>(REGISTER_BASE + SPURIOUS_INTERRUPT_VECTOR) };
+
+    let mut v = r.read();
+    v.set_bits(0..8, SPURIOUS_VECTOR.into());
+    v.set_bit(8, true);
+    r.write(v);
+}
+
+/// Sends an interrupt of `vector` to the CPU of `local_apic_id`.
+pub fn send_ipi(local_apic_id: u8, vector: u8) {
+    const FIXED: u32 = 0b000 << 8;
+
+    send_command(local_apic_id, FIXED | u32::from(vector));
+}
+
+/// Sends an INIT IPI to the CPU of `local_apic_id`.
+pub fn send_init(local_apic_id: u8) {
+    const INIT: u32 = 0b101 << 8;
+    const ASSERT: u32 = 1 << 14;
+
+    send_command(local_apic_id, INIT | ASSERT);
+}
+
+/// Sends a startup IPI to the CPU of `local_apic_id`. The CPU starts in the real mode at the
+/// physical address `page * 0x1000`.
+pub fn send_startup(local_apic_id: u8, page: u8) {
+    const STARTUP: u32 = 0b110 << 8;
+    const ASSERT: u32 = 1 << 14;
+
+    send_command(local_apic_id, STARTUP | ASSERT | u32::from(page));
+}
+
+fn send_command(local_apic_id: u8, command: u32) {
+    const DELIVERY_PENDING: usize = 12;
+
+    // SAFETY: See `end_of_interrupt`.
+    let mut high =
+        unsafe { crate::mem::accessor::kernel::<u32>(REGISTER_BASE + INTERRUPT_COMMAND_HIGH) };
+    let mut low =
+        unsafe { crate::mem::accessor::kernel::<u32>(REGISTER_BASE + INTERRUPT_COMMAND_LOW) };
+
+    high.write(u32::from(local_apic_id) << 24);
+
+    // Writing the low half sends the IPI.
+    low.write(command);
+
+    while low.read().get_bit(DELIVERY_PENDING) {
+        core::hint::spin_loop();
+    }
+}
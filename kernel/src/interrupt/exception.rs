@@ -0,0 +1,136 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// Handlers of the CPU exceptions. A fault in a user process kills only that process. A fault in
+// the kernel mode is a bug of the kernel, so it panics.
+//
+// A page fault is first passed to the current process, which maps the page if it is allocated
+// lazily or shared as copy-on-write, or grows the stack. A fault in the guard region below a stack
+// is reported as a stack overflow.
+
+use crate::{
+    process::{self, Fault},
+    smp::percpu::KernelGs,
+    syscall, tss,
+};
+use x86_64::{
+    registers::control::Cr2,
+    structures::idt::{InterruptStackFrame, PageFaultErrorCode},
+};
+
+macro_rules! handler {
+    ($name:ident, $description:literal) => {
+        pub extern "x86-interrupt" fn $name(f: &mut InterruptStackFrame) {
+            let _gs = KernelGs::enter(f);
+            fault($description, f, None);
+        }
+    };
+    ($name:ident, $description:literal, error_code) => {
+        pub extern "x86-interrupt" fn $name(f: &mut InterruptStackFrame, error_code: u64) {
+            let _gs = KernelGs::enter(f);
+            fault($description, f, Some(error_code));
+        }
+    };
+}
+
+handler!(divide_error, "Divide error");
+handler!(debug, "Debug exception");
+handler!(breakpoint, "Breakpoint");
+handler!(overflow, "Overflow");
+handler!(bound_range_exceeded, "BOUND range exceeded");
+handler!(invalid_opcode, "Invalid opcode");
+handler!(device_not_available, "Device not available");
+handler!(invalid_tss, "Invalid TSS", error_code);
+handler!(segment_not_present, "Segment not present", error_code);
+handler!(stack_segment_fault, "Stack-segment fault", error_code);
+handler!(
+    general_protection_fault,
+    "General protection fault",
+    error_code
+);
+handler!(x87_floating_point, "x87 floating-point exception");
+handler!(alignment_check, "Alignment check", error_code);
+handler!(simd_floating_point, "SIMD floating-point exception");
+handler!(virtualization, "Virtualization exception");
+handler!(security_exception, "Security exception", error_code);
+
+pub extern "x86-interrupt" fn page_fault(
+    f: &mut InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    let _gs = KernelGs::enter(f);
+    let addr = Cr2::read();
+    let write = error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE);
+
+    let description = match process::manager::handle_page_fault(addr, write) {
+        Fault::Resolved => return,
+        Fault::StackOverflow => "Stack overflow",
+        Fault::Invalid => "Page fault",
+    };
+
+    if tss::is_interrupt_stack_guard(addr) {
+        print_info("Stack overflow", f, Some(error_code.bits()));
+        panic!("The interrupt stack overflowed.");
+    }
+
+    if syscall::is_stack_probe(f.instruction_pointer) {
+        print_info(description, f, Some(error_code.bits()));
+        error!("The stack is too small to make a system call. Killing the process.");
+        process::manager::exit(syscalls::STATUS_KILLED);
+    }
+
+    fault(description, f, Some(error_code.bits()));
+}
+
+/// An NMI is not caused by the running process, so the whole system stops.
+pub extern "x86-interrupt" fn non_maskable_interrupt(f: &mut InterruptStackFrame) {
+    let _gs = KernelGs::enter(f);
+    print_info("Non-maskable interrupt", f, None);
+    panic!("Non-maskable interrupt.");
+}
+
+/// This handler runs on its own stack as a double fault may be caused by a stack overflow.
+pub extern "x86-interrupt" fn double_fault(f: &mut InterruptStackFrame, error_code: u64) -> ! {
+    let _gs = KernelGs::enter(f);
+    print_info("Double fault", f, Some(error_code));
+    panic!("Double fault.");
+}
+
+pub extern "x86-interrupt" fn machine_check(f: &mut InterruptStackFrame) -> ! {
+    let _gs = KernelGs::enter(f);
+    print_info("Machine check", f, None);
+    panic!("Machine check.");
+}
+
+fn fault(description: &str, f: &InterruptStackFrame, error_code: Option<u64>) {
+    print_info(description, f, error_code);
+
+    if from_user_mode(f) {
+        error!("Killing the process.");
+        process::manager::exit(syscalls::STATUS_KILLED);
+    } else {
+        panic!("{} in the kernel mode.", description);
+    }
+}
+
+fn print_info(description: &str, f: &InterruptStackFrame, error_code: Option<u64>) {
+    error!("{}", description);
+
+    if let Some(pid) = process::manager::try_getpid() {
+        error!("PID: {}", pid);
+    } else {
+        error!("PID: unknown");
+    }
+
+    error!("RIP: {:?}", f.instruction_pointer);
+    error!("CR2: {:?}", Cr2::read());
+
+    if let Some(e) = error_code {
+        error!("Error code: {:#x}", e);
+    }
+
+    error!("{:#?}", **f);
+}
+
+fn from_user_mode(f: &InterruptStackFrame) -> bool {
+    f.code_segment & 3 == 3
+}
@@ -1,56 +1,132 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use super::apic;
-use crate::process;
+use crate::{
+    process,
+    smp::{self, percpu::KernelGs},
+};
 use alloc::{collections::BTreeMap, vec::Vec};
-use common::constant::INTERRUPT_STACK;
+use core::convert::TryInto;
 use spinning_top::Spinlock;
+use x86_64::structures::idt::InterruptStackFrame;
 
 static NOTIFY_ON_INTERRUPT: Spinlock<BTreeMap<usize, Vec<i32>>> = Spinlock::new(BTreeMap::new());
 
-pub extern "x86-interrupt" fn h_20(
-    _stack_frame: &mut x86_64::structures::idt::InterruptStackFrame,
-) {
+pub extern "x86-interrupt" fn h_20(f: &mut InterruptStackFrame) {
     // Here, the stack pointer points the stack frame of the current task. By cloberring registers,
-    // the state will be stored on the stack frame.
+    // the state will be stored on the stack frame. Then the handler moves to the interrupt stack of
+    // the current CPU, whose top is at the start of the per-CPU block.
+    //
+    // The GS base is that of ring 3 if the interrupted code ran there, and the next process may run
+    // in either ring, so the handler checks the RPL of the saved code segments before reading the
+    // per-CPU block and before returning.
     //
-    // SAFETY: This operation is safe. After calling the `switch` function, `rax` contains the address to the top of the stack frame of
-    // the new process. It does not violate any memory safety.
+    // SAFETY: This operation is safe. After calling the `preempt` function, `rax` contains the address to the top of the stack frame of
+    // the next process. It does not violate any memory safety.
     unsafe {
         asm!(
             "
-            mov rsp, {}
+            test byte ptr [rdi + 8], 3
+            jz 2f
+            swapgs
+        2:
+            mov rsp, gs:[0]
+            call {}
             call {}
             call {}
             mov rsp, rax
-        ", const INTERRUPT_STACK.as_u64(), sym apic::local::end_of_interrupt, sym process::manager::switch, out("rax") _, out("rbx") _, out("rcx") _, out("rdx") _, out("rsi") _, out("rdi") _,  out("r8") _, out("r9") _, out("r10") _, out("r11") _, out("r12") _, out("r13") _, out("r14") _, out("r15") _);
+            test byte ptr [rax + {}], 3
+            jz 3f
+            swapgs
+        3:
+        ", sym apic::local::end_of_interrupt, sym super::timer::tick, sym process::manager::preempt, const process::CODE_SEGMENT_OFFSET, inout("rdi") f as *mut InterruptStackFrame => _, out("rax") _, out("rbx") _, out("rcx") _, out("rdx") _, out("rsi") _, out("r8") _, out("r9") _, out("r10") _, out("r11") _, out("r12") _, out("r13") _, out("r14") _, out("r15") _);
     }
 }
 
-pub extern "x86-interrupt" fn h_21(
-    _stack_frame: &mut x86_64::structures::idt::InterruptStackFrame,
-) {
+/// Switches to the next process without waiting for the timer. A process raises this interrupt to
+/// give up the CPU.
+pub extern "x86-interrupt" fn h_81(f: &mut InterruptStackFrame) {
+    // SAFETY: See `h_20`.
+    unsafe {
+        asm!(
+            "
+            test byte ptr [rdi + 8], 3
+            jz 2f
+            swapgs
+        2:
+            mov rsp, gs:[0]
+            call {}
+            mov rsp, rax
+            test byte ptr [rax + {}], 3
+            jz 3f
+            swapgs
+        3:
+        ", sym process::manager::switch, const process::CODE_SEGMENT_OFFSET, inout("rdi") f as *mut InterruptStackFrame => _, out("rax") _, out("rbx") _, out("rcx") _, out("rdx") _, out("rsi") _, out("r8") _, out("r9") _, out("r10") _, out("r11") _, out("r12") _, out("r13") _, out("r14") _, out("r15") _);
+    }
+}
+
+/// Another CPU added a process to the run queue of this idle CPU.
+pub extern "x86-interrupt" fn kick(f: &mut InterruptStackFrame) {
+    // SAFETY: See `h_20`.
+    unsafe {
+        asm!(
+            "
+            test byte ptr [rdi + 8], 3
+            jz 2f
+            swapgs
+        2:
+            mov rsp, gs:[0]
+            call {}
+            call {}
+            mov rsp, rax
+            test byte ptr [rax + {}], 3
+            jz 3f
+            swapgs
+        3:
+        ", sym apic::local::end_of_interrupt, sym process::manager::preempt, const process::CODE_SEGMENT_OFFSET, inout("rdi") f as *mut InterruptStackFrame => _, out("rax") _, out("rbx") _, out("rcx") _, out("rdx") _, out("rsi") _, out("r8") _, out("r9") _, out("r10") _, out("r11") _, out("r12") _, out("r13") _, out("r14") _, out("r15") _);
+    }
+}
+
+pub extern "x86-interrupt" fn tlb_shootdown(f: &mut InterruptStackFrame) {
+    let _gs = KernelGs::enter(f);
+    smp::tlb::handle_ipi();
+}
+
+pub extern "x86-interrupt" fn h_21(f: &mut InterruptStackFrame) {
+    let _gs = KernelGs::enter(f);
     apic::local::end_of_interrupt();
     notify(0x21);
 }
 
-pub extern "x86-interrupt" fn h_2c(
-    _stack_frame: &mut x86_64::structures::idt::InterruptStackFrame,
-) {
+pub extern "x86-interrupt" fn h_2c(f: &mut InterruptStackFrame) {
+    let _gs = KernelGs::enter(f);
     apic::local::end_of_interrupt();
     notify(0x2c);
 }
 
+/// The local APIC does not expect EOI for a spurious interrupt.
+pub extern "x86-interrupt" fn spurious(
+    _stack_frame: &mut x86_64::structures::idt::InterruptStackFrame,
+) {
+}
+
 pub fn notify_on_interrupt(vec: usize, pid: i32) {
     let mut l = NOTIFY_ON_INTERRUPT.lock();
     let a = l.entry(vec).or_insert_with(Vec::new);
     a.push(pid);
 }
 
+/// Removes all the registrations of the process `pid`.
+pub fn forget(pid: i32) {
+    for pids in NOTIFY_ON_INTERRUPT.lock().values_mut() {
+        pids.retain(|p| *p != pid);
+    }
+}
+
 fn notify(vec: usize) {
     if let Some(a) = NOTIFY_ON_INTERRUPT.lock().get(&vec) {
         for pid in a {
-            process::manager::notify(*pid);
+            process::manager::notify(*pid, vec.try_into().unwrap());
         }
     }
 }
@@ -2,25 +2,83 @@
 
 // See P.114
 
-use crate::{interrupt, x86_64::structures::idt::InterruptDescriptorTable};
+use crate::{
+    interrupt::{self, apic, exception},
+    smp, tss,
+    x86_64::structures::idt::InterruptDescriptorTable,
+};
 use conquer_once::spin::Lazy;
 
 static IDT: Lazy<InterruptDescriptorTable> = Lazy::new(|| {
     let mut idt = InterruptDescriptorTable::new();
 
-    // SAFETY: This operation is safe as the stack index 0 is allocated for the timer
-    // interruption.
+    register_faults(&mut idt);
+    register_faults_with_error_code(&mut idt);
+
+    // SAFETY: This operation is safe as the stack index 0 is allocated for the process switch.
     unsafe {
         idt[0x20]
             .set_handler_fn(interrupt::handler::h_20)
             .set_stack_index(0);
+        idt[0x81]
+            .set_handler_fn(interrupt::handler::h_81)
+            .set_stack_index(0);
+        idt[usize::from(smp::KICK_VECTOR)]
+            .set_handler_fn(interrupt::handler::kick)
+            .set_stack_index(0);
     }
     idt[0x21].set_handler_fn(interrupt::handler::h_21);
     idt[0x2c].set_handler_fn(interrupt::handler::h_2c);
+    idt[usize::from(smp::tlb::VECTOR)].set_handler_fn(interrupt::handler::tlb_shootdown);
+    idt[usize::from(apic::local::SPURIOUS_VECTOR)].set_handler_fn(interrupt::handler::spurious);
 
     idt
 });
 
+fn register_faults(idt: &mut InterruptDescriptorTable) {
+    idt.divide_error.set_handler_fn(exception::divide_error);
+    idt.debug.set_handler_fn(exception::debug);
+    idt.non_maskable_interrupt
+        .set_handler_fn(exception::non_maskable_interrupt);
+    idt.breakpoint.set_handler_fn(exception::breakpoint);
+    idt.overflow.set_handler_fn(exception::overflow);
+    idt.bound_range_exceeded
+        .set_handler_fn(exception::bound_range_exceeded);
+    idt.invalid_opcode.set_handler_fn(exception::invalid_opcode);
+    idt.device_not_available
+        .set_handler_fn(exception::device_not_available);
+    idt.x87_floating_point
+        .set_handler_fn(exception::x87_floating_point);
+    idt.machine_check.set_handler_fn(exception::machine_check);
+    idt.simd_floating_point
+        .set_handler_fn(exception::simd_floating_point);
+    idt.virtualization.set_handler_fn(exception::virtualization);
+}
+
+fn register_faults_with_error_code(idt: &mut InterruptDescriptorTable) {
+    // SAFETY: This operation is safe as the stack indexes are allocated only for the double fault
+    // and the page fault.
+    unsafe {
+        idt.double_fault
+            .set_handler_fn(exception::double_fault)
+            .set_stack_index(tss::DOUBLE_FAULT_STACK_INDEX);
+        idt.page_fault
+            .set_handler_fn(exception::page_fault)
+            .set_stack_index(tss::PAGE_FAULT_STACK_INDEX);
+    }
+    idt.invalid_tss.set_handler_fn(exception::invalid_tss);
+    idt.segment_not_present
+        .set_handler_fn(exception::segment_not_present);
+    idt.stack_segment_fault
+        .set_handler_fn(exception::stack_segment_fault);
+    idt.general_protection_fault
+        .set_handler_fn(exception::general_protection_fault);
+    idt.alignment_check
+        .set_handler_fn(exception::alignment_check);
+    idt.security_exception
+        .set_handler_fn(exception::security_exception);
+}
+
 pub fn init() {
     IDT.load();
 }
@@ -1,6 +1,7 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 pub mod apic;
+pub mod exception;
 pub mod handler;
 pub mod idt;
 pub mod timer;
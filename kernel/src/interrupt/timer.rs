@@ -1,8 +1,15 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use crate::mem::{accessor::Single, allocator};
+use crate::{
+    mem::{accessor::Single, allocator},
+    process, smp,
+};
 use acpi::{platform::address::AddressSpace, AcpiTables};
-use core::convert::TryInto;
+use conquer_once::spin::OnceCell;
+use core::{
+    convert::TryInto,
+    sync::atomic::{AtomicU64, Ordering},
+};
 use x86_64::{instructions::port::PortReadOnly, PhysAddr};
 
 const LVT_TIMER: PhysAddr = PhysAddr::new_truncate(0xfee0_0320);
@@ -11,9 +18,62 @@ const CURRENT_COUNT: PhysAddr = PhysAddr::new_truncate(0xfee0_0390);
 const DIVIDE_CONFIG: PhysAddr = PhysAddr::new_truncate(0xfee0_03e0);
 const TIMER_VECTOR: u8 = 0x20;
 
+/// The number of the timer interrupts per second.
+pub const TICK_HZ: u32 = 1000;
+
+/// The number of the timer interrupts of the BSP since the boot.
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// The frequency of the local APIC timer measured on the BSP. All CPUs share it.
+static FREQUENCY: OnceCell<u32> = OnceCell::uninit();
+
 pub fn init(table: &AcpiTables<allocator::acpi::Mapper>) {
-    let mut local_apic_tm = LocalApic::new(table);
-    local_apic_tm.init();
+    let mut local_apic_tm = LocalApic::new();
+    let f = local_apic_tm.get_frequency(&mut AcpiPm::new(table));
+    info!("Frequency: {}", f);
+
+    FREQUENCY
+        .try_init_once(|| f)
+        .expect("`FREQUENCY` is initialized more than once.");
+
+    local_apic_tm.set_modes(f);
+}
+
+/// Starts the timer of an AP with the frequency measured on the BSP.
+pub fn init_ap() {
+    let f = *FREQUENCY.get().expect("`FREQUENCY` is not initialized.");
+
+    LocalApic::new().set_modes(f);
+}
+
+/// Called on every timer interrupt of every CPU. Only the BSP counts the ticks so that the time
+/// does not pass faster on more CPUs.
+pub fn tick() {
+    allocator::heap::grow_on_tick();
+
+    if smp::id() == 0 {
+        let now = TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+        process::manager::wake_sleeping(now);
+    }
+}
+
+/// Waits for `milliseconds` with the ACPI PM timer. This works without interrupts.
+pub fn wait_milliseconds(table: &AcpiTables<allocator::acpi::Mapper>, milliseconds: u32) {
+    AcpiPm::new(table).wait_milliseconds(milliseconds);
+}
+
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+pub fn uptime_milliseconds() -> u64 {
+    ticks() * 1000 / u64::from(TICK_HZ)
+}
+
+/// Converts `milliseconds` to the number of ticks, rounding up so that a sleep never ends early.
+pub fn milliseconds_to_ticks(milliseconds: u64) -> u64 {
+    let hz = u64::from(TICK_HZ);
+    milliseconds.saturating_mul(hz).saturating_add(999) / 1000
 }
 
 struct LocalApic {
@@ -21,50 +81,38 @@ struct LocalApic {
     initial_count: Single<u32>,
     current_count: Single<u32>,
     divide_config: Single<u32>,
-    pm: AcpiPm,
-    frequency: Option<u32>,
 }
 impl LocalApic {
-    fn new(table: &AcpiTables<allocator::acpi::Mapper>) -> Self {
+    fn new() -> Self {
         // SAFETY: These operations are safe because the addresses are the correct ones.
         let lvt_timer = unsafe { crate::mem::accessor::kernel::<u32>(LVT_TIMER) };
         let initial_count = unsafe { crate::mem::accessor::kernel::<u32>(INITIAL_COUNT) };
         let current_count = unsafe { crate::mem::accessor::kernel::<u32>(CURRENT_COUNT) };
         let divide_config = unsafe { crate::mem::accessor::kernel::<u32>(DIVIDE_CONFIG) };
-        let pm = AcpiPm::new(table);
 
         Self {
             lvt_timer,
             initial_count,
             current_count,
             divide_config,
-            pm,
-            frequency: None,
         }
     }
 
-    fn init(&mut self) {
-        self.get_frequency();
-        self.set_modes();
-    }
-
-    fn get_frequency(&mut self) {
+    fn get_frequency(&mut self, pm: &mut AcpiPm) -> u32 {
         const MAX_COUNT: u32 = !0;
 
         self.divide_config.write(0b1011);
         self.lvt_timer.write(1 << 16 | 32);
         self.initial_count.write(MAX_COUNT);
-        self.pm.wait_milliseconds(100);
+        pm.wait_milliseconds(100);
 
-        self.frequency = Some((MAX_COUNT - self.current_count.read()) * 10);
+        (MAX_COUNT - self.current_count.read()) * 10
     }
 
-    fn set_modes(&mut self) {
-        let f = self.frequency.expect("Get the frequency first.");
-        info!("Frequency: {}", f);
-        self.divide_config.write(3);
+    fn set_modes(&mut self, f: u32) {
+        self.divide_config.write(0b1011);
         self.lvt_timer.write(u32::from(TIMER_VECTOR) | (1 << 17));
-        self.initial_count.write(f * 10);
+        self.initial_count.write(f / TICK_HZ);
     }
 }
 
@@ -94,7 +142,10 @@ impl AcpiPm {
     pub fn wait_milliseconds(&mut self, t: u32) {
         const FREQUENCY: u32 = 3_579_545;
         let start = self.reader.read();
-        let mut end = start.wrapping_add(FREQUENCY.wrapping_mul(t / 1000));
+        let ticks: u32 = (u64::from(FREQUENCY) * u64::from(t) / 1000)
+            .try_into()
+            .unwrap();
+        let mut end = start.wrapping_add(ticks);
         if let SupportedBits::Bits24 = self.supported {
             end &= 0x00ff_ffff;
         }
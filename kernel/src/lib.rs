@@ -9,6 +9,7 @@
 #![feature(linked_list_remove)]
 #![feature(const_fn)]
 #![feature(asm)]
+#![feature(global_asm)]
 #![feature(panic_info_message)]
 #![feature(start)]
 #![feature(naked_functions)]
@@ -28,25 +29,29 @@ extern crate derive_builder;
 
 mod acpi;
 mod device;
+mod fpu;
 mod gdt;
+mod initrd;
 mod interrupt;
 mod mem;
 mod multitask;
 mod panic;
 mod process;
 mod qemu;
+mod smp;
 mod syscall;
 mod tests;
 mod tss;
 
 use common::kernelboot;
-use device::pci::xhci;
+use device::pci::{self, xhci};
 use futures_intrusive::sync::{GenericMutex, GenericMutexGuard};
 use interrupt::{apic, idt, timer};
 use mem::allocator::{heap, phys::FrameManager};
 use multitask::{executor::Executor, task::Task};
-use process::Privilege;
+use process::{Capabilities, Privilege};
 use spinning_top::RawSpinlock;
+use syscalls::Priority;
 use terminal::vram;
 pub type Futurelock<T> = GenericMutex<RawSpinlock, T>;
 pub type FuturelockGuard<'a, T> = GenericMutexGuard<'a, RawSpinlock, T>;
@@ -60,22 +65,27 @@ pub extern "win64" fn os_main(mut boot_info: kernelboot::Info) -> ! {
 
 fn init(boot_info: &mut kernelboot::Info) {
     initialize_in_kernel_mode(boot_info);
-    initialize_in_user_mode();
+
+    // Finding the xHCI controller reads the configuration spaces, which the kernel mode can do
+    // before any process allows the user privilege to access the ports.
+    let usb_driver = usb_driver_capabilities();
+
+    start_processes(usb_driver);
 }
 
 fn initialize_in_kernel_mode(boot_info: &mut kernelboot::Info) {
+    smp::init_bsp();
+    tss::init();
     gdt::init();
     idt::init();
+    mem::paging::init();
+    fpu::init();
 
-    // It is bothering to initialize heap memory in the user mode as this is to map the area, which an initialized
-    // frame manager is needed.
-    heap::init();
-
-    // This function unmaps all user memory, which needs the kernel privilege.
-    FrameManager::init(boot_info.mem_map_mut());
+    init_memory(boot_info);
 
     let acpi = unsafe { acpi::get(boot_info.rsdp()) };
 
+    apic::local::enable();
     apic::io::init(&acpi);
 
     timer::init(&acpi);
@@ -88,33 +98,88 @@ fn initialize_in_kernel_mode(boot_info: &mut kernelboot::Info) {
 
     vram::print_info();
 
+    initrd::init(boot_info);
+
     syscall::init();
+
+    // APs need the heap, the frame manager, and the calibrated timer.
+    smp::init(&acpi);
 }
 
-fn initialize_in_user_mode() {
-    gdt::enter_usermode();
+fn init_memory(boot_info: &mut kernelboot::Info) {
+    // It is bothering to initialize heap memory in the user mode as this is to map the area, which an initialized
+    // frame manager is needed.
+    heap::init();
 
+    // This function unmaps all user memory, which needs the kernel privilege.
+    FrameManager::init(boot_info.mem_map_mut());
+
+    heap::init_user();
+}
+
+/// This stays in the kernel mode, as the per-CPU block is reachable only in ring 0.
+fn start_processes(usb_driver: Capabilities) {
     process::manager::init();
-    add_processes();
+    smp::start();
+    add_processes(usb_driver);
+
+    heap::log_stats();
 }
 
-fn add_processes() {
-    process::manager::add(run_tasks, Privilege::User);
+fn add_processes(usb_driver: Capabilities) {
+    // The USB driver runs ahead of other processes to keep the input latency low.
+    process::manager::add_function(process::manager::Function {
+        priority: Priority::High,
+        capabilities: usb_driver,
+        ..process::manager::Function::new(run_tasks, Privilege::User)
+    });
 
     if cfg!(feature = "qemu_test") {
-        process::manager::add(tests::main, Privilege::User);
-        process::manager::add(tests::process::kernel_privilege_test, Privilege::Kernel);
-        process::manager::add(tests::process::exit_test, Privilege::User);
+        add_test_processes();
+    }
+}
+
+fn add_test_processes() {
+    process::manager::add(tests::main, Privilege::User);
+    process::manager::add(tests::process::kernel_privilege_test, Privilege::Kernel);
+    process::manager::add(tests::process::exit_test, Privilege::User);
+    process::manager::add(tests::process::exit_stress_test, Privilege::Kernel);
+    process::manager::add(tests::ipc::server, Privilege::User);
+    process::manager::add(tests::exception::page_fault, Privilege::User);
+    process::manager::add(tests::exception::stack_overflow, Privilege::User);
+    process::manager::add(tests::mem::peer, Privilege::User);
+    process::manager::add(tests::mem::mapping_test, Privilege::Kernel);
+    process::manager::add(tests::scheduler::starvation_test, Privilege::Kernel);
+    process::manager::add(tests::fpu::clobber, Privilege::User);
+    process::manager::spawn("hello", &["world"]);
+
+    for _ in 0..smp::MAX_CPUS {
+        process::manager::add(tests::smp::record_cpu, Privilege::Kernel);
+    }
+
+    for _ in 0..100 {
+        process::manager::add(tests::process::do_nothing, Privilege::User);
+    }
+}
+
+/// Allows the USB driver to map the registers of the xHCI controller, to find it in the
+/// configuration spaces, and to receive its interrupts.
+fn usb_driver_capabilities() -> Capabilities {
+    let c = Capabilities::default()
+        .io_ports(pci::config::PORTS)
+        .interrupts();
 
-        for _ in 0..100 {
-            process::manager::add(tests::process::do_nothing, Privilege::User);
-        }
+    match xhci::mmio() {
+        Some(r) => c.mmio(r),
+        None => c,
     }
 }
 
 fn wait_until_timer_interrupt_happens() -> ! {
+    // No process is running yet, so the system call enables interrupts and halts. This context
+    // is discarded on the first timer interrupt.
     loop {
-        syscalls::enable_interrupt_and_halt()
+        syscalls::enable_interrupt_and_halt();
     }
 }
 
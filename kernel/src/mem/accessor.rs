@@ -29,8 +29,8 @@ impl Mappers {
 
     pub fn user() -> Self {
         Self {
-            mapper: syscalls::map_pages,
-            unmapper: syscalls::unmap_pages,
+            mapper: |p, b| syscalls::map_pages(p, b).expect("Failed to map pages."),
+            unmapper: |v, b| syscalls::unmap_pages(v, b).expect("Failed to unmap pages."),
         }
     }
 }
@@ -0,0 +1,442 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// There are two heaps. The kernel mode allocates from the kernel heap, whose frames are mapped only
+// for ring 0. The processes of the user privilege run the kernel image in ring 3, so they allocate
+// from the user heap, whose frames are user-accessible. Either heap frees the allocations in the
+// area which it maps.
+//
+// The kernel heap starts with an area in the kernel image, as the frame manager uses the heap
+// before it can allocate frames. When the free memory of a heap falls below `LOW_WATERMARK`, the
+// heap maps new frames after the start of its area. The frame manager and the page tables may be
+// locked by the CPU which allocates, so growing only tries to lock them, and the allocations
+// meanwhile are served from the remaining memory. The locks are held with interrupts disabled,
+// which the user privilege cannot do, so the timer interrupt grows the user heap. An allocation
+// which fails in the user privilege records its size and waits for the next growth.
+//
+// The stack of the user privilege is mapped on page faults, and resolving one uses the heap. So the
+// user privilege probes its stack before locking the heap.
+//
+// Small allocations such as the nodes of `BTreeMap`, `Arc<Spinlock<AtomicWaker>>` and short
+// `Vec<Descriptor>` are served from the slab caches.
+
+mod slab;
+
+use super::phys::{FrameManager, FRAME_MANAGER};
+use crate::mem::paging::{self, pml4::PML4};
+use common::constant::{KERNEL_HEAP_END, KERNEL_HEAP_START, USER_HEAP_END, USER_HEAP_START};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    convert::TryFrom,
+    hint,
+    ops::Range,
+    ptr::{self, NonNull},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use linked_list_allocator::Heap;
+use os_units::{Bytes, NumOfPages};
+use slab::Caches;
+use spinning_top::{Spinlock, SpinlockGuard};
+use x86_64::{
+    instructions::{interrupts, segmentation},
+    structures::paging::{
+        FrameAllocator, Mapper, Page, PageTableFlags, RecursivePageTable, Size4KiB,
+    },
+    PrivilegeLevel, VirtAddr,
+};
+
+pub use slab::CacheStats;
+
+/// The heap grows when its free memory falls below this.
+const LOW_WATERMARK: usize = 0x8000;
+
+/// The minimum number of bytes by which the heap grows at once.
+const GROWTH: usize = 0x10_0000;
+
+const PAGE_BYTES: usize = 0x1000;
+
+/// The number of growths for which a failed allocation in the user privilege waits.
+const MAX_WAITS: usize = 3;
+
+extern "C" {
+    static HEAP_START: usize;
+    static HEAP_END: usize;
+}
+
+// `#[global_allocator]` generates `__rg_realloc`, which takes four arguments.
+#[allow(clippy::too_many_arguments)]
+mod global {
+    #[global_allocator]
+    static ALLOCATOR: super::Allocator = super::Allocator;
+}
+
+static KERNEL: Pool = Pool::new(
+    "Kernel",
+    KERNEL_HEAP_START..KERNEL_HEAP_END,
+    paging::KERNEL_DATA,
+);
+
+static USER: Pool = Pool::new("User", USER_HEAP_START..USER_HEAP_END, paging::USER_DATA);
+
+/// Set while a CPU maps new frames to either heap.
+static GROWING: AtomicBool = AtomicBool::new(false);
+
+/// The largest size of the allocations which failed in the user privilege since the last growth of
+/// the user heap.
+static REQUESTED: AtomicUsize = AtomicUsize::new(0);
+
+/// The number of the growths tried by the timer interrupt.
+static ATTEMPTS: AtomicUsize = AtomicUsize::new(0);
+
+// Using UEFI's `allocate_pages` doesn't work for allocating larger memory. It returns out of
+// resrouces.
+pub fn init() {
+    let s = unsafe { &HEAP_START as *const usize as usize };
+    let e = unsafe { &HEAP_END as *const usize as usize };
+
+    protect_initial_area(VirtAddr::new(s as u64), VirtAddr::new(e as u64));
+
+    unsafe { KERNEL.lock().initial.init(s, e - s) }
+}
+
+/// Maps the first frames of the user heap, as the user privilege leaves growing it to the timer
+/// interrupt, which does not happen until the first process runs. This also makes the PML4 entry
+/// of the heaps user-accessible before the processes copy it.
+///
+/// This must be called in the kernel privilege with interrupts disabled.
+pub fn init_user() {
+    USER.grow(Bytes::new(0));
+}
+
+/// Grows the kernel heap if it runs low, and the user heap if it runs low or an allocation in the
+/// user privilege failed. The timer interrupt calls this on every CPU.
+pub fn grow_on_tick() {
+    // The interrupted code may hold the locks.
+    if KERNEL.is_low() == Some(true) {
+        KERNEL.grow(Bytes::new(0));
+    }
+
+    // In the address space of a binary, the PML4 entry of the heaps is only for ring 0, and mapping
+    // a user-accessible page there would make it user-accessible.
+    if !paging::is_kernel_user_accessible() {
+        return;
+    }
+
+    let low = match USER.is_low() {
+        Some(l) => l,
+        None => return,
+    };
+
+    let requested = REQUESTED.swap(0, Ordering::Relaxed);
+    if low || requested > 0 {
+        USER.grow(Bytes::new(requested));
+    }
+
+    ATTEMPTS.fetch_add(1, Ordering::Release);
+}
+
+/// Returns the statistics of the heap which the current privilege allocates from.
+pub fn stats() -> Stats {
+    Pool::current().lock().stats()
+}
+
+pub fn log_stats() {
+    let s = stats();
+
+    info!(
+        "{} heap: {} of {} bytes used, {} bytes grown.",
+        Pool::current().name,
+        s.used,
+        s.size,
+        s.grown
+    );
+
+    for c in s.caches.iter().filter(|c| c.slabs > 0) {
+        info!(
+            "Slab cache of {} bytes: {} slabs, {} objects in use.",
+            c.object_size, c.slabs, c.in_use
+        );
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Stats {
+    /// The total bytes of the heap.
+    pub size: usize,
+
+    /// The bytes used by allocations and the slabs.
+    pub used: usize,
+
+    /// The bytes mapped after the start of the area of the heap.
+    pub grown: usize,
+
+    pub caches: [CacheStats; slab::NUM_OF_CACHES],
+}
+
+struct Allocator;
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let pool = Pool::current();
+        let (p, low) = {
+            let mut h = pool.lock();
+            (h.alloc(layout), h.free() < LOW_WATERMARK)
+        };
+
+        if in_kernel_mode() && (p.is_none() || low) {
+            interrupts::without_interrupts(|| pool.grow(Bytes::new(layout.size())));
+        }
+
+        p.or_else(|| pool.retry(layout))
+            .map_or(ptr::null_mut(), NonNull::as_ptr)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        Pool::containing(VirtAddr::from_ptr(ptr))
+            .lock()
+            .dealloc(NonNull::new(ptr).unwrap(), layout);
+    }
+}
+
+/// A heap and the area to which it maps frames.
+struct Pool {
+    name: &'static str,
+    inner: Spinlock<Inner>,
+    area: Range<VirtAddr>,
+    flags: PageTableFlags,
+}
+impl Pool {
+    const fn new(name: &'static str, area: Range<VirtAddr>, flags: PageTableFlags) -> Self {
+        Self {
+            name,
+            inner: Spinlock::new(Inner::new()),
+            area,
+            flags,
+        }
+    }
+
+    fn current() -> &'static Self {
+        if in_kernel_mode() {
+            &KERNEL
+        } else {
+            &USER
+        }
+    }
+
+    /// The kernel heap also contains the area in the kernel image.
+    fn containing(addr: VirtAddr) -> &'static Self {
+        if USER.area.contains(&addr) {
+            &USER
+        } else {
+            &KERNEL
+        }
+    }
+
+    fn lock(&self) -> SpinlockGuard<'_, Inner> {
+        if !in_kernel_mode() {
+            paging::probe_stack();
+        }
+
+        self.inner.lock()
+    }
+
+    /// Returns `None` if the heap is locked.
+    fn is_low(&self) -> Option<bool> {
+        self.inner.try_lock().map(|h| h.free() < LOW_WATERMARK)
+    }
+
+    /// Maps at least `GROWTH` bytes and `bytes` bytes after the grown area, and adds them to the
+    /// heap. This does nothing if another CPU is growing a heap, or the frame manager or the page
+    /// tables are locked.
+    ///
+    /// This must be called in the kernel privilege with interrupts disabled.
+    fn grow(&self, bytes: Bytes) {
+        if GROWING
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return;
+        }
+
+        let start = self.area.start + self.lock().grown.size();
+
+        // Leave room for the headers of `linked_list_allocator` and the alignment.
+        let n = Bytes::new(bytes.as_usize().max(GROWTH) + PAGE_BYTES * 2).as_num_of_pages();
+
+        let mapped = self.map_frames(start, n);
+        if mapped.as_usize() > 0 {
+            self.lock()
+                .extend(self.area.start, mapped.as_bytes().as_usize());
+        }
+
+        GROWING.store(false, Ordering::Release);
+    }
+
+    /// Maps up to `n` new frames from `start`, and returns the number of the mapped pages.
+    fn map_frames(&self, start: VirtAddr, n: NumOfPages<Size4KiB>) -> NumOfPages<Size4KiB> {
+        let (mut frames, mut pml4) = match (FRAME_MANAGER.try_lock(), PML4.try_lock()) {
+            (Some(f), Some(p)) => (f, p),
+            _ => return NumOfPages::new(0),
+        };
+
+        let pages = Page::range(
+            Page::containing_address(start),
+            Page::containing_address(start + n.as_bytes().as_usize()),
+        );
+
+        let mapped = pages
+            .take_while(|p| p.start_address() < self.area.end)
+            .take_while(|p| map_frame(*p, &mut frames, &mut pml4))
+            .count();
+
+        NumOfPages::new(mapped)
+    }
+
+    fn retry(&self, layout: Layout) -> Option<NonNull<u8>> {
+        if in_kernel_mode() || !interrupts::are_enabled() {
+            self.lock().alloc(layout)
+        } else {
+            (0..MAX_WAITS).find_map(|_| {
+                REQUESTED.fetch_max(layout.size(), Ordering::Relaxed);
+                wait_for_growth();
+                self.lock().alloc(layout)
+            })
+        }
+    }
+}
+
+struct Inner {
+    /// The area in the kernel image.
+    initial: Heap,
+
+    /// The frames mapped by growing.
+    grown: Heap,
+
+    caches: Caches,
+}
+impl Inner {
+    const fn new() -> Self {
+        Self {
+            initial: Heap::empty(),
+            grown: Heap::empty(),
+            caches: Caches::new(),
+        }
+    }
+
+    fn alloc(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        let Self {
+            initial,
+            grown,
+            caches,
+        } = self;
+        let mut large = |l| Self::alloc_large(initial, grown, l);
+
+        match caches.get_mut(layout) {
+            Some(c) => c.alloc(&mut large),
+            None => large(layout),
+        }
+    }
+
+    fn dealloc(&mut self, p: NonNull<u8>, layout: Layout) {
+        if let Some(c) = self.caches.get_mut(layout) {
+            c.dealloc(p);
+        } else if self.grown.bottom() <= p.as_ptr() as usize
+            && (p.as_ptr() as usize) < self.grown.top()
+        {
+            unsafe { self.grown.deallocate(p, layout) }
+        } else {
+            unsafe { self.initial.deallocate(p, layout) }
+        }
+    }
+
+    fn alloc_large(initial: &mut Heap, grown: &mut Heap, l: Layout) -> Option<NonNull<u8>> {
+        grown
+            .allocate_first_fit(l)
+            .or_else(|_| initial.allocate_first_fit(l))
+            .ok()
+    }
+
+    /// Adds the `bytes` bytes which are mapped after the grown area, which starts at `start`.
+    fn extend(&mut self, start: VirtAddr, bytes: usize) {
+        if self.grown.size() == 0 {
+            let start = usize::try_from(start.as_u64()).unwrap();
+            unsafe { self.grown.init(start, bytes) }
+        } else {
+            unsafe { self.grown.extend(bytes) }
+        }
+    }
+
+    fn free(&self) -> usize {
+        self.initial.free() + self.grown.free()
+    }
+
+    fn stats(&self) -> Stats {
+        Stats {
+            size: self.initial.size() + self.grown.size(),
+            used: self.initial.used() + self.grown.used(),
+            grown: self.grown.size(),
+            caches: self.caches.stats(),
+        }
+    }
+}
+
+fn wait_for_growth() {
+    let n = ATTEMPTS.load(Ordering::Acquire);
+    while ATTEMPTS.load(Ordering::Acquire) == n {
+        hint::spin_loop();
+    }
+}
+
+/// Maps a new frame to `page` with the flags of the heap containing it, and returns `false` if it
+/// fails.
+fn map_frame(
+    page: Page<Size4KiB>,
+    frames: &mut FrameManager,
+    pml4: &mut RecursivePageTable<'_>,
+) -> bool {
+    let frame = if let Some(f) = frames.allocate_frame() {
+        f
+    } else {
+        return false;
+    };
+
+    let flags = Pool::containing(page.start_address()).flags;
+
+    // SAFETY: No one uses the page yet. Nothing caches a page which is not present, so the TLB
+    // need not be flushed.
+    if let Ok(flush) = unsafe { pml4.map_to(page, frame, flags, frames) } {
+        flush.ignore();
+        true
+    } else {
+        frames.free(frame.start_address());
+        false
+    }
+}
+
+/// Makes the area in the kernel image accessible only by ring 0. The bootloader maps it with the
+/// other statics, which are user-accessible.
+fn protect_initial_area(start: VirtAddr, end: VirtAddr) {
+    let mut pml4 = PML4.lock();
+
+    for page in Page::<Size4KiB>::range(
+        Page::containing_address(start),
+        Page::containing_address(end),
+    ) {
+        // SAFETY: The bootloader maps the kernel image with 4 KiB pages, and only the heap uses
+        // these pages.
+        unsafe { pml4.update_flags(page, paging::KERNEL_DATA) }
+            .expect("The heap is not mapped.")
+            .flush();
+    }
+}
+
+fn in_kernel_mode() -> bool {
+    segmentation::cs().rpl() == PrivilegeLevel::Ring0
+}
+
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    panic!(
+        "Allocation failed! {:?}, {:?}",
+        layout,
+        Pool::current().inner.try_lock().map(|h| h.stats())
+    );
+}
@@ -0,0 +1,122 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// Each cache serves objects of one size, which is a power of two. A slab is a page-aligned block
+// taken from the heap and divided into the objects, so an object is aligned to its size. Free
+// objects form a singly linked list through their first word. Slabs are never returned to the
+// heap, as the objects of the caches are allocated and freed repeatedly.
+
+use core::{alloc::Layout, ptr::NonNull};
+
+/// The sizes of the objects of the caches.
+const OBJECT_SIZES: [usize; NUM_OF_CACHES] = [16, 32, 64, 128, 256, 512, 1024, 2048];
+pub(super) const NUM_OF_CACHES: usize = 8;
+
+const SLAB_BYTES: usize = 0x4000;
+const SLAB_ALIGN: usize = 0x1000;
+
+pub(super) struct Caches([Cache; NUM_OF_CACHES]);
+impl Caches {
+    pub(super) const fn new() -> Self {
+        Self([
+            Cache::new(OBJECT_SIZES[0]),
+            Cache::new(OBJECT_SIZES[1]),
+            Cache::new(OBJECT_SIZES[2]),
+            Cache::new(OBJECT_SIZES[3]),
+            Cache::new(OBJECT_SIZES[4]),
+            Cache::new(OBJECT_SIZES[5]),
+            Cache::new(OBJECT_SIZES[6]),
+            Cache::new(OBJECT_SIZES[7]),
+        ])
+    }
+
+    /// Returns the cache which serves `layout`, or `None` if it is too large for any cache.
+    pub(super) fn get_mut(&mut self, layout: Layout) -> Option<&mut Cache> {
+        let size = layout.size().max(layout.align());
+        self.0.iter_mut().find(|c| c.object_size >= size)
+    }
+
+    pub(super) fn stats(&self) -> [CacheStats; NUM_OF_CACHES] {
+        let mut s = [CacheStats::default(); NUM_OF_CACHES];
+
+        for (s, c) in s.iter_mut().zip(self.0.iter()) {
+            *s = c.stats();
+        }
+
+        s
+    }
+}
+
+pub(super) struct Cache {
+    object_size: usize,
+
+    /// The address of the first free object, or 0 if none.
+    free: usize,
+
+    slabs: usize,
+    in_use: usize,
+}
+impl Cache {
+    const fn new(object_size: usize) -> Self {
+        Self {
+            object_size,
+            free: 0,
+            slabs: 0,
+            in_use: 0,
+        }
+    }
+
+    /// Allocates an object. A new slab is taken with `alloc_slab` if no object is free.
+    pub(super) fn alloc(
+        &mut self,
+        alloc_slab: &mut impl FnMut(Layout) -> Option<NonNull<u8>>,
+    ) -> Option<NonNull<u8>> {
+        if self.free == 0 {
+            let slab = alloc_slab(Layout::from_size_align(SLAB_BYTES, SLAB_ALIGN).unwrap())?;
+            self.add_slab(slab.as_ptr() as usize);
+        }
+
+        let p = self.free;
+
+        // SAFETY: `p` is a free object, whose first word holds the next one.
+        self.free = unsafe { *(p as *const usize) };
+        self.in_use += 1;
+
+        NonNull::new(p as *mut u8)
+    }
+
+    pub(super) fn dealloc(&mut self, p: NonNull<u8>) {
+        // SAFETY: The object is not used anymore, and is large enough to hold a word.
+        unsafe { *p.cast::<usize>().as_ptr() = self.free };
+        self.free = p.as_ptr() as usize;
+        self.in_use -= 1;
+    }
+
+    fn add_slab(&mut self, start: usize) {
+        for i in (0..SLAB_BYTES / self.object_size).rev() {
+            let p = start + i * self.object_size;
+
+            // SAFETY: The slab is allocated for this cache.
+            unsafe { *(p as *mut usize) = self.free };
+            self.free = p;
+        }
+
+        self.slabs += 1;
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            object_size: self.object_size,
+            slabs: self.slabs,
+            in_use: self.in_use,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct CacheStats {
+    pub object_size: usize,
+    pub slabs: usize,
+
+    /// The number of the allocated objects.
+    pub in_use: usize,
+}
@@ -1,47 +1,62 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use core::convert::TryFrom;
+use common::mem::paging as common_paging;
 use os_units::NumOfPages;
 use phys::FRAME_MANAGER;
+use virt::AddressSpace;
 use x86_64::{
-    structures::paging::{Mapper, Page, PageSize, Size4KiB, Translate},
+    structures::paging::{FrameAllocator, Size4KiB, Translate},
     PhysAddr, VirtAddr,
 };
 
-use super::paging::pml4::PML4;
+use super::paging::{self, pml4::PML4};
 
 pub mod acpi;
 pub mod heap;
 pub mod phys;
 pub mod virt;
 
-pub fn allocate_pages(num_of_pages: NumOfPages<Size4KiB>) -> Option<VirtAddr> {
+/// Allocates pages and maps them to the heap region of `space`, which must be the current address
+/// space. The frames of new page tables are allocated from `page_tables`.
+pub fn allocate_pages(
+    num_of_pages: NumOfPages<Size4KiB>,
+    space: &mut AddressSpace,
+    page_tables: &mut impl FrameAllocator<Size4KiB>,
+) -> Option<(VirtAddr, PhysAddr)> {
     let phys_addr = allocate_phys(num_of_pages)?;
 
-    let virt_addr = super::map_pages(phys_addr, num_of_pages.as_bytes());
-
-    Some(virt_addr)
+    let virt_addr = if let Some(v) = space.allocate_heap_for(num_of_pages, phys_addr) {
+        v
+    } else {
+        deallocate_phys(phys_addr);
+        return None;
+    };
+
+    let region = common_paging::Region {
+        virt: virt_addr,
+        phys: phys_addr,
+        bytes: num_of_pages.as_bytes(),
+        flags: paging::USER_DATA,
+    };
+    super::map_to(region, page_tables);
+
+    Some((virt_addr, phys_addr))
 }
 
+/// Unmaps the pages and frees the frames. The caller must return the virtual addresses to the
+/// address space afterwards.
 pub fn deallocate_pages(virt: VirtAddr, num_of_pages: NumOfPages<Size4KiB>) {
-    deallocate_phys(virt);
-    deallocate_virt(virt, num_of_pages);
+    let phys = PML4.lock().translate_addr(virt).unwrap();
+
+    // Free the frames after no CPU caches the pages.
+    super::unmap(virt, num_of_pages);
+    deallocate_phys(phys);
 }
 
 fn allocate_phys(num_of_pages: NumOfPages<Size4KiB>) -> Option<PhysAddr> {
     FRAME_MANAGER.lock().alloc(num_of_pages)
 }
 
-fn deallocate_phys(virt: VirtAddr) {
-    let phys = PML4.lock().translate_addr(virt).unwrap();
+fn deallocate_phys(phys: PhysAddr) {
     FRAME_MANAGER.lock().free(phys);
 }
-
-fn deallocate_virt(virt: VirtAddr, num_of_pages: NumOfPages<Size4KiB>) {
-    for i in 0..u64::try_from(num_of_pages.as_usize()).unwrap() {
-        let page = Page::<Size4KiB>::from_start_address(virt + Size4KiB::SIZE * i).unwrap();
-
-        let (_, flush) = PML4.lock().unmap(page).unwrap();
-        flush.flush();
-    }
-}
@@ -1,172 +1,108 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use crate::mem::paging;
-use alloc::collections::vec_deque::VecDeque;
-use bit_field::BitField;
+use alloc::{
+    collections::{btree_map::Entry, BTreeMap},
+    vec::Vec,
+};
+use buddy_allocator::{BuddyAllocator, Stats};
 use conquer_once::spin::Lazy;
-use core::convert::{TryFrom, TryInto};
+use core::convert::TryFrom;
 use os_units::NumOfPages;
 use spinning_top::Spinlock;
-use uefi::table::boot::{self, MemoryType};
+use uefi::table::boot;
 use x86_64::{
-    structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB},
+    structures::paging::{FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size4KiB},
     PhysAddr,
 };
 
 pub static FRAME_MANAGER: Lazy<Spinlock<FrameManager>> =
-    Lazy::new(|| Spinlock::new(FrameManager(VecDeque::new())));
-
-pub struct FrameManager(VecDeque<Frames>);
+    Lazy::new(|| Spinlock::new(FrameManager::default()));
+
+#[derive(Default)]
+pub struct FrameManager {
+    allocator: BuddyAllocator,
+    /// The numbers of the references to the allocations shared between address spaces. An
+    /// allocation which is not here has one reference.
+    refs: BTreeMap<PhysAddr, usize>,
+}
 impl FrameManager {
     pub fn init(mem_map: &[boot::MemoryDescriptor]) {
-        FRAME_MANAGER.lock().init_static(mem_map);
+        FRAME_MANAGER.lock().allocator.add_memory_map(mem_map);
         paging::mark_pages_as_unused();
     }
 
     pub fn alloc(&mut self, num_of_pages: NumOfPages<Size4KiB>) -> Option<PhysAddr> {
-        let num_of_pages = NumOfPages::new(num_of_pages.as_usize().next_power_of_two());
-
-        for i in 0..self.0.len() {
-            if self.0[i].num_of_pages >= num_of_pages && self.0[i].available {
-                self.split_node(i, num_of_pages);
-
-                let addr = self.0[i].start;
-                self.0[i].available = false;
-
-                return Some(addr);
-            }
-        }
-
-        None
-    }
-
-    pub fn free(&mut self, addr: PhysAddr) {
-        for i in 0..self.0.len() {
-            if self.0[i].start == addr && !self.0[i].available {
-                self.0[i].available = true;
-                return self.merge_all_nodes();
-            }
-        }
+        self.allocator.alloc(num_of_pages)
     }
 
-    fn init_static(&mut self, mem_map: &[boot::MemoryDescriptor]) {
-        for descriptor in mem_map {
-            if Self::available(descriptor.ty) {
-                self.init_for_descriptor(descriptor);
-            }
-        }
-
-        self.merge_all_nodes();
+    /// Allocates frames which end at or below `end`. Some devices and the real mode cannot access
+    /// high memory.
+    pub fn alloc_below(
+        &mut self,
+        num_of_pages: NumOfPages<Size4KiB>,
+        end: PhysAddr,
+    ) -> Option<PhysAddr> {
+        self.allocator.alloc_below(num_of_pages, end)
     }
 
-    fn init_for_descriptor(&mut self, descriptor: &boot::MemoryDescriptor) {
-        let mut offset = NumOfPages::<Size4KiB>::new(0);
-
-        // By reversing the range, bigger memory chanks come first.
-        // This will make it faster to search a small amount of memory.
-        for i in (0..u64::BITS).rev() {
-            if descriptor.page_count.get_bit(i.try_into().unwrap()) {
-                let addr = PhysAddr::new(
-                    descriptor.phys_start + u64::try_from(offset.as_bytes().as_usize()).unwrap(),
-                );
-                let pages = NumOfPages::new(2_usize.pow(i));
-                let frames = Frames::new(addr, pages, true);
-
-                self.0.push_back(frames);
-
-                offset += pages;
-            }
-        }
+    /// Returns the number of pages which are not allocated.
+    pub fn free_pages(&self) -> NumOfPages<Size4KiB> {
+        self.allocator.free_pages()
     }
 
-    fn split_node(&mut self, i: usize, num_of_pages: NumOfPages<Size4KiB>) {
-        if self.0[i].available {
-            while self.0[i].num_of_pages > num_of_pages {
-                let start = self.0[i].start;
-                let num_of_pages = self.0[i].num_of_pages;
-
-                let new_frames = Frames::new(
-                    start + num_of_pages.as_bytes().as_usize() / 2,
-                    num_of_pages / 2,
-                    true,
-                );
-
-                self.0[i].num_of_pages /= 2;
-                self.0.insert(i + 1, new_frames);
-            }
-        }
+    /// Returns the number of the free blocks of each order and the number of the allocated pages.
+    pub fn stats(&self) -> Stats {
+        self.allocator.stats()
     }
 
-    fn merge_all_nodes(&mut self) {
-        // By reversing the range, bigger memory chanks come first.
-        // This will make it faster to search a small amount of memory.
-        for i in (0..self.0.len()).rev() {
-            if self.mergeable(i) {
-                self.merge_two_nodes(i);
-                return self.merge_all_nodes();
-            }
-        }
+    /// Adds a reference to the allocation at `addr`. `free` removes a reference, and frees the
+    /// allocation when it removes the last one.
+    pub fn add_ref(&mut self, addr: PhysAddr) {
+        *self.refs.entry(addr).or_insert(1) += 1;
     }
 
-    fn mergeable(&self, i: usize) -> bool {
-        if i < self.0.len() - 1 {
-            let node = &self.0[i];
-            let next = &self.0[i + 1];
+    pub fn free(&mut self, addr: PhysAddr) {
+        if let Entry::Occupied(mut e) = self.refs.entry(addr) {
+            *e.get_mut() -= 1;
 
-            Self::two_nodes_available(node, next)
-                && Self::two_nodes_consecutive(node, next)
-                && Self::two_nodes_have_same_pages(node, next)
+            if *e.get() == 1 {
+                e.remove();
+            }
         } else {
-            false
+            self.allocator.free(addr);
         }
     }
-
-    fn two_nodes_available(node: &Frames, next: &Frames) -> bool {
-        node.available && next.available
-    }
-
-    fn two_nodes_consecutive(node: &Frames, next: &Frames) -> bool {
-        node.start + u64::try_from(node.num_of_pages.as_bytes().as_usize()).unwrap() == next.start
-    }
-
-    fn two_nodes_have_same_pages(node: &Frames, next: &Frames) -> bool {
-        node.num_of_pages == next.num_of_pages
-    }
-
-    fn merge_two_nodes(&mut self, i: usize) {
-        self.0[i].num_of_pages *= 2;
-        self.0.remove(i + 1);
-    }
-
-    fn available(ty: boot::MemoryType) -> bool {
-        ty == MemoryType::CONVENTIONAL
-    }
 }
-unsafe impl FrameAllocator<Size4KiB> for FrameManager {
-    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
-        let addr = self.alloc(NumOfPages::new(1))?;
+/// Frames of the huge page sizes are also allocated, as an allocation is aligned to the smallest
+/// power of two which is not less than its size.
+unsafe impl<S: PageSize> FrameAllocator<S> for FrameManager {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<S>> {
+        let n = usize::try_from(S::SIZE / Size4KiB::SIZE).unwrap();
+        let addr = self.alloc(NumOfPages::new(n))?;
         Some(PhysFrame::from_start_address(addr).unwrap())
     }
 }
-impl FrameDeallocator<Size4KiB> for FrameManager {
-    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<Size4KiB>) {
+impl<S: PageSize> FrameDeallocator<S> for FrameManager {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<S>) {
         let addr = frame.start_address();
         self.free(addr);
     }
 }
 
-#[derive(Debug)]
-struct Frames {
-    start: PhysAddr,
-    num_of_pages: NumOfPages<Size4KiB>,
-    available: bool,
+/// A frame allocator which remembers the frames it takes from `FRAME_MANAGER` so that they can be
+/// freed later.
+#[derive(Default)]
+pub struct Recorder(Vec<PhysFrame>);
+impl Recorder {
+    pub fn into_frames(self) -> Vec<PhysFrame> {
+        self.0
+    }
 }
-impl Frames {
-    fn new(start: PhysAddr, num_of_pages: NumOfPages<Size4KiB>, available: bool) -> Self {
-        Self {
-            start,
-            num_of_pages,
-            available,
-        }
+unsafe impl FrameAllocator<Size4KiB> for Recorder {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<Size4KiB>> {
+        let f = FRAME_MANAGER.lock().allocate_frame()?;
+        self.0.push(f);
+        Some(f)
     }
 }
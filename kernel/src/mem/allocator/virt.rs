@@ -1,40 +1,251 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use crate::mem::paging::pml4::PML4;
-use common::constant::BYTES_AVAILABLE_RAM;
-use core::convert::TryFrom;
+// The lower half of an address space is divided into regions:
+//
+// - The heap region for `allocate_pages`.
+// - The stack region for the stacks of processes.
+// - The MMIO region for `map_pages`.
+//
+// Each address space has its own allocators of the heap and the stack regions. The MMIO region is
+// shared by all address spaces, as the kernel maps registers to whichever address space is
+// current. Addresses outside these regions, such as those of ELF segments, are never allocated.
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    vec::Vec,
+};
+use common::{
+    constant::{
+        HEAP_REGION_END, HEAP_REGION_START, MMIO_REGION_END, MMIO_REGION_START, STACK_REGION_END,
+        STACK_REGION_START,
+    },
+    mem::paging,
+};
+use conquer_once::spin::Lazy;
+use core::{convert::TryFrom, ops::Range};
 use os_units::NumOfPages;
+use spinning_top::Spinlock;
 use x86_64::{
-    structures::paging::{PageSize, Size4KiB, Translate},
-    VirtAddr,
+    structures::paging::{PageSize, Size4KiB},
+    PhysAddr, VirtAddr,
 };
 
-pub fn search_free_addr(num_pages: NumOfPages<Size4KiB>) -> Option<VirtAddr> {
-    let mut cnt = 0;
-    let mut start = None;
-    for addr in
-        (0..BYTES_AVAILABLE_RAM.as_usize()).step_by(usize::try_from(Size4KiB::SIZE).unwrap())
-    {
-        let addr = VirtAddr::new(addr as _);
-        if available(addr) {
-            if start.is_none() {
-                start = Some(addr);
+static MMIO: Lazy<Spinlock<Allocator>> =
+    Lazy::new(|| Spinlock::new(Allocator::new(MMIO_REGION_START..MMIO_REGION_END)));
+
+/// The address space which is used before any process runs.
+pub static KERNEL: Lazy<Spinlock<AddressSpace>> =
+    Lazy::new(|| Spinlock::new(AddressSpace::default()));
+
+/// Allocates `n` pages in the MMIO region to map the frames from `phys`.
+pub fn allocate_mmio(n: NumOfPages<Size4KiB>, phys: PhysAddr) -> Option<VirtAddr> {
+    MMIO.lock().allocate_like(n, phys)
+}
+
+/// Frees the `n` pages from `start`, which `allocate_mmio` returned.
+pub fn free_mmio(start: VirtAddr, n: NumOfPages<Size4KiB>) {
+    MMIO.lock().free(start, n);
+}
+
+/// The allocators of the regions which belong to an address space.
+#[derive(Debug)]
+pub struct AddressSpace {
+    heap: Allocator,
+    stack: Allocator,
+}
+impl AddressSpace {
+    pub fn allocate_heap(&mut self, n: NumOfPages<Size4KiB>) -> Option<VirtAddr> {
+        self.heap.allocate(n)
+    }
+
+    /// Allocates `n` pages in the heap region to map the frames from `phys`.
+    pub fn allocate_heap_for(
+        &mut self,
+        n: NumOfPages<Size4KiB>,
+        phys: PhysAddr,
+    ) -> Option<VirtAddr> {
+        self.heap.allocate_like(n, phys)
+    }
+
+    pub fn allocate_stack(&mut self, n: NumOfPages<Size4KiB>) -> Option<VirtAddr> {
+        self.stack.allocate(n)
+    }
+
+    /// Frees the `n` pages from `start` in the region which contains them.
+    pub fn free(&mut self, start: VirtAddr, n: NumOfPages<Size4KiB>) {
+        self.heap.free(start, n);
+        self.stack.free(start, n);
+    }
+
+    /// Marks the `n` pages from `start` as used, so that they are not allocated. This is for the
+    /// pages mapped to a fixed address.
+    pub fn reserve(&mut self, start: VirtAddr, n: NumOfPages<Size4KiB>) {
+        self.heap.reserve(start, n);
+        self.stack.reserve(start, n);
+    }
+}
+impl Default for AddressSpace {
+    fn default() -> Self {
+        Self {
+            heap: Allocator::new(HEAP_REGION_START..HEAP_REGION_END),
+            stack: Allocator::new(STACK_REGION_START..STACK_REGION_END),
+        }
+    }
+}
+
+/// An allocator of the pages in a region.
+///
+/// The free ranges are indexed both by their start addresses to merge adjacent ones, and by their
+/// sizes to find the smallest one which is large enough. Both take `O(log n)`.
+#[derive(Debug)]
+struct Allocator {
+    region: Range<u64>,
+
+    /// The end addresses of the free ranges, keyed by their start addresses.
+    by_start: BTreeMap<u64, u64>,
+
+    /// The sizes and the start addresses of the free ranges.
+    by_size: BTreeSet<(u64, u64)>,
+}
+impl Allocator {
+    fn new(region: Range<VirtAddr>) -> Self {
+        let region = region.start.as_u64()..region.end.as_u64();
+
+        let mut a = Self {
+            region: region.clone(),
+            by_start: BTreeMap::new(),
+            by_size: BTreeSet::new(),
+        };
+        a.insert(region.start, region.end);
+        a
+    }
+
+    fn allocate(&mut self, n: NumOfPages<Size4KiB>) -> Option<VirtAddr> {
+        self.allocate_aligned(
+            n,
+            Alignment {
+                size: Size4KiB::SIZE,
+                offset: 0,
+            },
+        )
+    }
+
+    /// Allocates `n` pages whose start address has the same offset from the boundary of the
+    /// largest page they can contain as `phys`, so that `map_range` maps them to the frames from
+    /// `phys` with huge pages.
+    fn allocate_like(&mut self, n: NumOfPages<Size4KiB>, phys: PhysAddr) -> Option<VirtAddr> {
+        let size = paging::largest_page_size(bytes_of(n));
+        let offset = phys.as_u64() % size;
+        self.allocate_aligned(n, Alignment { size, offset })
+    }
+
+    /// Allocates `n` pages which start at an address aligned to `align`.
+    fn allocate_aligned(&mut self, n: NumOfPages<Size4KiB>, align: Alignment) -> Option<VirtAddr> {
+        let bytes = bytes_of(n);
+        if bytes == 0 {
+            return None;
+        }
+
+        // Any free range of this size contains an address of the offset.
+        let (_, start) = *self
+            .by_size
+            .range((bytes + align.size - Size4KiB::SIZE, 0)..)
+            .next()?;
+        let end = self.remove(start);
+
+        let v = align.first_from(start);
+
+        if start < v {
+            self.insert(start, v);
+        }
+
+        if v + bytes < end {
+            self.insert(v + bytes, end);
+        }
+
+        Some(VirtAddr::new(v))
+    }
+
+    fn free(&mut self, start: VirtAddr, n: NumOfPages<Size4KiB>) {
+        let mut start = start.as_u64();
+        let mut end = start + bytes_of(n);
+
+        if !self.contains(start, end) {
+            return;
+        }
+
+        if let Some((&prev, &prev_end)) = self.by_start.range(..start).next_back() {
+            if prev_end == start {
+                self.remove(prev);
+                start = prev;
             }
+        }
+
+        if self.by_start.contains_key(&end) {
+            end = self.remove(end);
+        }
+
+        self.insert(start, end);
+    }
+
+    fn reserve(&mut self, start: VirtAddr, n: NumOfPages<Size4KiB>) {
+        let start = start.as_u64();
+        let end = start + bytes_of(n);
 
-            cnt += 1;
+        let overlapping: Vec<_> = self
+            .by_start
+            .range(..end)
+            .rev()
+            .take_while(|(_, e)| **e > start)
+            .map(|(s, e)| (*s, *e))
+            .collect();
 
-            if cnt >= num_pages.as_usize() {
-                return start;
+        for (s, e) in overlapping {
+            self.remove(s);
+
+            if s < start {
+                self.insert(s, start);
+            }
+
+            if end < e {
+                self.insert(end, e);
             }
-        } else {
-            cnt = 0;
-            start = None;
         }
     }
 
-    None
+    fn contains(&self, start: u64, end: u64) -> bool {
+        self.region.start <= start && end <= self.region.end
+    }
+
+    fn insert(&mut self, start: u64, end: u64) {
+        self.by_start.insert(start, end);
+        self.by_size.insert((end - start, start));
+    }
+
+    /// Removes the free range which starts at `start`, and returns its end.
+    fn remove(&mut self, start: u64) -> u64 {
+        let end = self
+            .by_start
+            .remove(&start)
+            .expect("No free range starts there.");
+        self.by_size.remove(&(end - start, start));
+        end
+    }
+}
+
+/// An address is aligned to this if it is `offset` bytes after a multiple of `size`.
+#[derive(Copy, Clone)]
+struct Alignment {
+    size: u64,
+    offset: u64,
+}
+impl Alignment {
+    /// Returns the lowest aligned address at or above `start`.
+    fn first_from(self, start: u64) -> u64 {
+        start + (self.offset + self.size - start % self.size) % self.size
+    }
 }
 
-fn available(addr: VirtAddr) -> bool {
-    PML4.lock().translate_addr(addr).is_none() && !addr.is_null()
+fn bytes_of(n: NumOfPages<Size4KiB>) -> u64 {
+    u64::try_from(n.as_usize()).unwrap() * Size4KiB::SIZE
 }
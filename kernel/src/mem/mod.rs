@@ -1,59 +1,132 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use crate::smp;
 use allocator::{phys::FRAME_MANAGER, virt};
+use common::mem::{paging as common_paging, reserved::PhysRange};
 use core::convert::TryFrom;
-use os_units::Bytes;
+use os_units::{Bytes, NumOfPages};
 use paging::pml4::PML4;
 use x86_64::{
-    structures::paging::{Mapper, Page, PageSize, PageTableFlags, PhysFrame, Size4KiB},
-    PhysAddr, VirtAddr,
+    instructions::segmentation,
+    structures::paging::{
+        mapper::TranslateResult, FrameAllocator, Mapper, Page, PageSize, PageTableFlags,
+        RecursivePageTable, Size1GiB, Size2MiB, Size4KiB, Translate,
+    },
+    PhysAddr, PrivilegeLevel, VirtAddr,
 };
 
 pub mod accessor;
 pub mod allocator;
 pub mod paging;
 
+/// Maps the physical region to the current address space, such as the registers of the APIC.
+///
+/// The kernel maps a region only while accessing it, so the pages are user-accessible only if this
+/// is called in ring 3, where the processes of the user privilege run.
 pub fn map_pages(start: PhysAddr, object_size: Bytes) -> VirtAddr {
-    let start_frame_addr = start.align_down(Size4KiB::SIZE);
-    let end_frame_addr = (start + object_size.as_usize()).align_down(Size4KiB::SIZE);
+    let flags = if segmentation::cs().rpl() == PrivilegeLevel::Ring0 {
+        paging::KERNEL_DATA
+    } else {
+        paging::USER_DATA
+    };
+
+    let range = PhysRange::new(start, object_size);
+    map_pages_with(range, flags, &mut *FRAME_MANAGER.lock())
+}
 
-    let num_pages = Bytes::new(usize::try_from(end_frame_addr - start_frame_addr).unwrap() + 1)
-        .as_num_of_pages::<Size4KiB>();
+/// Same as `map_pages`, but the pages are mapped with the flags `flags`, and the frames of new page
+/// tables are allocated from `page_tables`.
+pub fn map_pages_with(
+    range: PhysRange,
+    flags: PageTableFlags,
+    page_tables: &mut impl FrameAllocator<Size4KiB>,
+) -> VirtAddr {
+    let (start, object_size) = (range.start(), range.bytes());
+    let num_pages = num_of_pages_spanned(start.as_u64(), object_size);
 
-    let virt = virt::search_free_addr(num_pages)
+    let frame = start.align_down(Size4KiB::SIZE);
+    let virt = virt::allocate_mmio(num_pages, frame)
         .expect("OOM during creating a new accessor to a register.");
 
-    for i in 0..num_pages.as_usize() {
-        let page = Page::<Size4KiB>::containing_address(virt + Size4KiB::SIZE * i as u64);
-        let frame = PhysFrame::containing_address(start_frame_addr + Size4KiB::SIZE * i as u64);
-        let flag =
-            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
-
-        unsafe {
-            PML4.lock()
-                .map_to(page, frame, flag, &mut *FRAME_MANAGER.lock())
-                .unwrap()
-                .flush()
-        }
-    }
+    let region = common_paging::Region {
+        virt,
+        phys: frame,
+        bytes: num_pages.as_bytes(),
+        flags,
+    };
+    map_to(region, page_tables);
 
     let page_offset = start.as_u64() % Size4KiB::SIZE;
 
     virt + page_offset
 }
 
+/// Unmaps the region which `map_pages` or `map_pages_with` mapped with the same size.
 pub fn unmap_pages(start: VirtAddr, object_size: Bytes) {
     let start_frame_addr = start.align_down(Size4KiB::SIZE);
-    let end_frame_addr = (start + object_size.as_usize()).align_down(Size4KiB::SIZE);
+    let num_pages = num_of_pages_spanned(start.as_u64(), object_size);
+
+    unmap(start_frame_addr, num_pages);
+
+    virt::free_mmio(start_frame_addr, num_pages);
+}
+
+/// Returns the virtual addresses of a region which was mapped by `map_pages` or `map_pages_with`,
+/// without unmapping it. This is for the regions of an address space which is discarded.
+pub fn forget_mapping(start: VirtAddr, object_size: Bytes) {
+    let num_pages = num_of_pages_spanned(start.as_u64(), object_size);
+    virt::free_mmio(start.align_down(Size4KiB::SIZE), num_pages);
+}
 
-    let num_pages = Bytes::new(usize::try_from(end_frame_addr - start_frame_addr).unwrap())
-        .as_num_of_pages::<Size4KiB>();
+/// Returns the number of the pages which the `bytes` bytes from `start` span. An empty region
+/// still occupies the page which contains `start`.
+fn num_of_pages_spanned(start: u64, bytes: Bytes) -> NumOfPages<Size4KiB> {
+    let first = start / Size4KiB::SIZE;
+    let end = start + u64::try_from(bytes.as_usize()).unwrap();
+    let last = end.saturating_sub(1).max(start) / Size4KiB::SIZE;
 
-    for i in 0..num_pages.as_usize() {
-        let page =
-            Page::<Size4KiB>::containing_address(start_frame_addr + Size4KiB::SIZE * i as u64);
+    NumOfPages::new(usize::try_from(last - first + 1).unwrap())
+}
+
+/// Maps `region`, using huge pages where the addresses are aligned to them. The frames of new page
+/// tables are allocated from `page_tables`.
+fn map_to(region: common_paging::Region, page_tables: &mut impl FrameAllocator<Size4KiB>) {
+    // SAFETY: The virtual addresses are allocated for these frames, so they are not mapped.
+    unsafe { common_paging::map_range(&mut *PML4.lock(), region, page_tables) };
+}
+
+/// Unmaps the `n` pages from `virt`, which `map_to` mapped, and flushes the TLB of all CPUs.
+fn unmap(virt: VirtAddr, n: NumOfPages<Size4KiB>) {
+    let end = virt + n.as_bytes().as_usize();
+    let mut pml4 = PML4.lock();
+
+    let mut v = virt;
+    while v < end {
+        let size = match pml4.translate(v) {
+            TranslateResult::Mapped { frame, .. } => frame.size(),
+            _ => panic!("The page is not mapped."),
+        };
 
-        let (_, flush) = PML4.lock().unmap(page).unwrap();
-        flush.flush();
+        match size {
+            Size1GiB::SIZE => unmap_page::<Size1GiB>(&mut pml4, v),
+            Size2MiB::SIZE => unmap_page::<Size2MiB>(&mut pml4, v),
+            _ => unmap_page::<Size4KiB>(&mut pml4, v),
+        }
+
+        v += size;
     }
+
+    drop(pml4);
+    smp::tlb::shootdown(Page::containing_address(virt), n);
+}
+
+/// The caller flushes the TLB.
+fn unmap_page<S: PageSize>(pml4: &mut RecursivePageTable<'_>, v: VirtAddr)
+where
+    for<'a> RecursivePageTable<'a>: Mapper<S>,
+{
+    let (_, flush) = pml4
+        .unmap(Page::<S>::containing_address(v))
+        .expect("Failed to unmap a page.");
+    flush.ignore();
 }
@@ -2,14 +2,207 @@
 
 pub mod pml4;
 
-use common::constant::RECUR_PML4_ADDR;
-use x86_64::structures::paging::PageTable;
+/// The bytes of the stack below the stack pointer which `probe_stack` and the entry of the system
+/// calls make mapped. The kernel must not use more stack than this while handling a system call.
+pub const STACK_PROBE_BYTES: usize = 0x4000;
+
+/// The flags of the pages which both privileges read and write, such as the heaps and the stacks.
+/// The processes of the user privilege run in ring 3 and use them, so they are user-accessible.
+pub const USER_DATA: PageTableFlags = PageTableFlags::from_bits_truncate(
+    PageTableFlags::PRESENT.bits()
+        | PageTableFlags::WRITABLE.bits()
+        | PageTableFlags::USER_ACCESSIBLE.bits()
+        | PageTableFlags::NO_EXECUTE.bits(),
+);
+
+/// The flags of the pages which only the kernel mode accesses, such as the registers of the APIC
+/// and the stack frames of processes.
+pub const KERNEL_DATA: PageTableFlags = PageTableFlags::from_bits_truncate(
+    PageTableFlags::PRESENT.bits()
+        | PageTableFlags::WRITABLE.bits()
+        | PageTableFlags::NO_EXECUTE.bits(),
+);
+
+use common::constant::{KERNEL_ADDR, RECUR_PML4_ADDR, RECUR_PML4_INDEX};
+use conquer_once::spin::OnceCell;
+use core::arch::x86_64::{__cpuid, __cpuid_count};
+use x86_64::{
+    registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags},
+    structures::paging::{Page, PageTable, PageTableFlags, PageTableIndex, Size4KiB},
+    VirtAddr,
+};
+
+// The bootloader enables `NO_EXECUTE` before mapping the kernel, and the APs copy EFER of the BSP.
+//
+// SMEP and SMAP are enabled while the address space of a binary is loaded, if CPUID reports them
+// (leaf 7, EBX bits 7 and 20). The processes of the user privilege run the code of the kernel image
+// and use the user heap in ring 3, so the kernel image and the heaps are user-accessible in their
+// address spaces, and ring 0 would fault on its own code and data there. The address space of a
+// binary maps them only for ring 0, so while it is loaded:
+//
+// - SMEP makes ring 0 fault on fetching an instruction from the pages of the binary.
+// - SMAP makes ring 0 fault on accessing the pages of the binary, except in `with_user_access`,
+//   which `UserPtr`, `UserSlice` and the handler of page faults use. The system calls from ring 3
+//   run on a stack of the kernel for this.
+//
+// In the other address spaces, the system calls check user pointers against the page tables (see
+// `syscall::user_ptr`), and the kernel mode maps MMIO regions without `USER_ACCESSIBLE`.
+
+/// The protections out of SMEP and SMAP which the CPU supports. All CPUs have the same features.
+static PROTECTIONS: OnceCell<Cr4Flags> = OnceCell::uninit();
+
+pub fn init() {
+    enable_write_protect();
+    PROTECTIONS.init_once(supported_protections);
+}
+
+/// Enables SMEP and SMAP if the CPU supports them. The kernel image and the heaps must not be
+/// user-accessible in the current address space.
+pub fn enable_protections() {
+    // SAFETY: Ring 0 neither runs the code of the current address space nor accesses its data
+    // outside `with_user_access`.
+    unsafe { Cr4::update(|f| f.insert(protections())) }
+}
+
+/// Disables SMEP and SMAP. This must be called before loading an address space in which the
+/// kernel image is user-accessible.
+pub fn disable_protections() {
+    // SAFETY: Disabling them only allows more accesses.
+    unsafe { Cr4::update(|f| f.remove(protections())) }
+}
+
+/// Calls `f` with the accesses of ring 0 to user-accessible pages allowed, which SMAP forbids.
+/// Calls of this must not be nested.
+pub fn with_user_access<T>(f: impl FnOnce() -> T) -> T {
+    let smap = protections().contains(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION);
+
+    // `stac` and `clac` cause an invalid opcode exception if the CPU does not support SMAP. They
+    // must not have `nomem`, so that the accesses of `f` are not moved outside them.
+    if smap {
+        // SAFETY: `f` accesses only the pages which it validated.
+        unsafe { asm!("stac", options(nostack)) }
+    }
+
+    let r = f();
+
+    if smap {
+        // SAFETY: This only forbids the accesses again.
+        unsafe { asm!("clac", options(nostack)) }
+    }
+
+    r
+}
+
+/// Returns `true` if the kernel image is user-accessible in the current address space, which is
+/// not that of a binary.
+pub fn is_kernel_user_accessible() -> bool {
+    effective_flags(KERNEL_ADDR).map_or(false, |f| f.contains(PageTableFlags::USER_ACCESSIBLE))
+}
 
 pub fn mark_pages_as_unused() {
     let page_table = unsafe { &mut *(RECUR_PML4_ADDR.as_mut_ptr::<PageTable>()) };
 
-    // Entry 510 and 511 are used by kernel.
-    for i in 0..510 {
+    // The recursive entry and the entries after it are used by kernel.
+    for i in 0..usize::from(RECUR_PML4_INDEX) {
         page_table[i].set_unused();
     }
 }
+
+/// Reads the stack `STACK_PROBE_BYTES` below the stack pointer, so that the unmapped pages of a
+/// stack of the user privilege are mapped there before taking a lock. Resolving a page fault on
+/// the stack takes the locks of the kernel, so it must not happen while one of them is held.
+pub fn probe_stack() {
+    // SAFETY: This only reads the stack of the current process, and a page fault there is
+    // resolved or kills the process.
+    unsafe {
+        asm!(
+            "cmp byte ptr [rsp - {}], 0",
+            const STACK_PROBE_BYTES,
+            options(nostack, readonly)
+        );
+    }
+}
+
+/// Walks the page tables of the current address space, and returns the flags of the page
+/// containing `addr`. `WRITABLE` and `USER_ACCESSIBLE` are set only if the entries of all levels
+/// have them. Returns `None` if the page is not mapped.
+pub fn effective_flags(addr: VirtAddr) -> Option<PageTableFlags> {
+    const FLAGS: PageTableFlags = PageTableFlags::from_bits_truncate(
+        PageTableFlags::PRESENT.bits()
+            | PageTableFlags::WRITABLE.bits()
+            | PageTableFlags::USER_ACCESSIBLE.bits(),
+    );
+
+    let mut flags = FLAGS;
+
+    for (table, i) in &tables_to_walk(addr) {
+        // SAFETY: The recursive entry maps each page table of the path to `table`, and the
+        // previous level is present.
+        let e = &unsafe { &*table.as_ptr::<PageTable>() }[*i];
+
+        if !e.flags().contains(PageTableFlags::PRESENT) {
+            return None;
+        }
+
+        flags &= e.flags();
+
+        if e.flags().contains(PageTableFlags::HUGE_PAGE) {
+            break;
+        }
+    }
+
+    Some(flags & FLAGS)
+}
+
+/// Returns the addresses of the page tables from the PML4 to the level 1 table through the
+/// recursive entry, and the index of the entry for `addr` in each table.
+fn tables_to_walk(addr: VirtAddr) -> [(VirtAddr, PageTableIndex); 4] {
+    let p = Page::<Size4KiB>::containing_address(addr);
+    let r = PageTableIndex::new(RECUR_PML4_INDEX);
+    let table = |a, b, c, d| Page::<Size4KiB>::from_page_table_indices(a, b, c, d).start_address();
+
+    [
+        (table(r, r, r, r), p.p4_index()),
+        (table(r, r, r, p.p4_index()), p.p3_index()),
+        (table(r, r, p.p4_index(), p.p3_index()), p.p2_index()),
+        (
+            table(r, p.p4_index(), p.p3_index(), p.p2_index()),
+            p.p1_index(),
+        ),
+    ]
+}
+
+fn protections() -> Cr4Flags {
+    PROTECTIONS.get().copied().unwrap_or_else(Cr4Flags::empty)
+}
+
+fn supported_protections() -> Cr4Flags {
+    const SMEP: u32 = 1 << 7;
+    const SMAP: u32 = 1 << 20;
+
+    // SAFETY: Every x86_64 CPU supports the leaf 0.
+    if unsafe { __cpuid(0) }.eax < 7 {
+        return Cr4Flags::empty();
+    }
+
+    // SAFETY: The CPU supports the leaf 7.
+    let ebx = unsafe { __cpuid_count(7, 0) }.ebx;
+
+    let mut f = Cr4Flags::empty();
+    if ebx & SMEP != 0 {
+        f |= Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION;
+    }
+    if ebx & SMAP != 0 {
+        f |= Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION;
+    }
+
+    f
+}
+
+/// Makes the kernel mode respect read-only pages. The bootloader disables this to edit the page
+/// tables of the firmware.
+fn enable_write_protect() {
+    // SAFETY: The kernel never writes to a read-only page. Copy-on-write pages are replaced before
+    // being written.
+    unsafe { Cr0::update(|f| f.insert(Cr0Flags::WRITE_PROTECT)) }
+}
@@ -8,7 +8,7 @@
 //
 // THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
-use super::task;
+use super::{task, timer};
 use alloc::collections::BTreeMap;
 use core::task::{Context, Poll, Waker};
 use task::Task;
@@ -26,15 +26,16 @@ impl Executor {
 
     pub fn run(&mut self) -> ! {
         loop {
+            timer::wake_expired();
             self.run_woken_tasks();
             Self::sleep_if_idle();
         }
     }
 
     fn sleep_if_idle() {
-        syscalls::disable_interrupt();
+        syscalls::disable_interrupt().expect("Failed to disable interrupts.");
         if task::COLLECTION.lock().woken_task_exists() {
-            syscalls::enable_interrupt();
+            syscalls::enable_interrupt().expect("Failed to enable interrupts.");
         } else {
             syscalls::enable_interrupt_and_halt();
         }
@@ -0,0 +1,67 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll, Waker},
+};
+use spinning_top::Spinlock;
+
+/// The deadline in the uptime milliseconds and the waker of each pending timer, keyed by the ID of
+/// the timer. A timer which is polled again replaces its waker.
+static WAITING: Spinlock<BTreeMap<u64, (u64, Waker)>> = Spinlock::new(BTreeMap::new());
+
+/// A future which completes when the uptime reaches the deadline.
+pub struct Timer {
+    id: u64,
+    deadline: u64,
+}
+impl Timer {
+    /// Creates a timer which completes after `milliseconds` milliseconds.
+    pub fn after(milliseconds: u64) -> Self {
+        static ID: AtomicU64 = AtomicU64::new(0);
+
+        Self {
+            id: ID.fetch_add(1, Ordering::Relaxed),
+            deadline: syscalls::get_uptime() + milliseconds,
+        }
+    }
+}
+impl Future for Timer {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if syscalls::get_uptime() >= self.deadline {
+            WAITING.lock().remove(&self.id);
+            Poll::Ready(())
+        } else {
+            WAITING
+                .lock()
+                .insert(self.id, (self.deadline, cx.waker().clone()));
+            Poll::Pending
+        }
+    }
+}
+impl Drop for Timer {
+    fn drop(&mut self) {
+        WAITING.lock().remove(&self.id);
+    }
+}
+
+/// Wakes the tasks whose deadlines have passed.
+pub(super) fn wake_expired() {
+    let now = syscalls::get_uptime();
+
+    let mut waiting = WAITING.lock();
+    let expired: Vec<_> = waiting
+        .iter()
+        .filter(|(_, (deadline, _))| *deadline <= now)
+        .map(|(id, _)| *id)
+        .collect();
+
+    for (_, waker) in expired.iter().filter_map(|id| waiting.remove(id)) {
+        waker.wake();
+    }
+}
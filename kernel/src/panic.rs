@@ -1,10 +1,14 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 use crate::qemu;
+use x86_64::{
+    instructions::{self, interrupts, segmentation},
+    PrivilegeLevel,
+};
 
 #[panic_handler]
 fn panic(i: &core::panic::PanicInfo) -> ! {
-    syscalls::disable_interrupt();
+    disable_interrupts();
     print_banner();
     print_info(i);
 
@@ -26,7 +30,30 @@ fn fini() -> ! {
         qemu::exit_failure();
     } else {
         loop {
-            syscalls::halt()
+            halt();
         }
     }
 }
+
+// The kernel panics in ring 0 when an exception happens in the kernel mode, where system calls
+// cannot be used.
+fn disable_interrupts() {
+    if in_kernel_mode() {
+        interrupts::disable();
+    } else {
+        // Processes without the capability to manage interrupts continue with interrupts enabled.
+        let _ = syscalls::disable_interrupt();
+    }
+}
+
+fn halt() {
+    if in_kernel_mode() {
+        instructions::hlt();
+    } else if syscalls::halt().is_err() {
+        core::hint::spin_loop();
+    }
+}
+
+fn in_kernel_mode() -> bool {
+    segmentation::cs().rpl() == PrivilegeLevel::Ring0
+}
@@ -0,0 +1,90 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::qemu;
+use alloc::vec::Vec;
+use core::ops::{Range, RangeInclusive};
+use x86_64::PhysAddr;
+
+/// Hardware resources which a user process is allowed to access, and operations which it is
+/// allowed to do.
+///
+/// Processes with the kernel privilege can access everything regardless of their capabilities.
+#[derive(Clone, Debug)]
+pub struct Capabilities {
+    io_ports: Vec<RangeInclusive<u16>>,
+    mmio: Vec<Range<PhysAddr>>,
+    interrupts: bool,
+    scheduling: bool,
+}
+impl Capabilities {
+    /// Allows the process to access `ports` with `in` and `out` instructions and the system calls.
+    #[must_use]
+    pub fn io_ports(mut self, ports: RangeInclusive<u16>) -> Self {
+        self.io_ports.push(ports);
+        self
+    }
+
+    /// Allows the process to map the physical range `range` with the `MapPages` system call.
+    #[must_use]
+    pub fn mmio(mut self, range: Range<PhysAddr>) -> Self {
+        self.mmio.push(range);
+        self
+    }
+
+    /// Allows the process to enable and disable interrupts, halt the CPU, and receive interrupts
+    /// as messages.
+    #[must_use]
+    pub fn interrupts(mut self) -> Self {
+        self.interrupts = true;
+        self
+    }
+
+    /// Allows the process to change the priority of any process and the time slice of the
+    /// scheduler.
+    #[must_use]
+    pub fn scheduling(mut self) -> Self {
+        self.scheduling = true;
+        self
+    }
+
+    pub(super) fn io_port_ranges(&self) -> &[RangeInclusive<u16>] {
+        &self.io_ports
+    }
+
+    pub fn allows_io_ports(&self, ports: &RangeInclusive<u16>) -> bool {
+        self.io_ports
+            .iter()
+            .any(|r| r.contains(ports.start()) && r.contains(ports.end()))
+    }
+
+    pub fn allows_mmio(&self, range: &Range<PhysAddr>) -> bool {
+        self.mmio
+            .iter()
+            .any(|r| r.start <= range.start && range.end <= r.end)
+    }
+
+    pub fn allows_interrupts(&self) -> bool {
+        self.interrupts
+    }
+
+    pub fn allows_scheduling(&self) -> bool {
+        self.scheduling
+    }
+}
+impl Default for Capabilities {
+    /// Returns the capabilities which allow nothing except exiting QEMU in the test build.
+    fn default() -> Self {
+        let c = Self {
+            io_ports: Vec::new(),
+            mmio: Vec::new(),
+            interrupts: false,
+            scheduling: false,
+        };
+
+        if cfg!(feature = "qemu_test") {
+            c.io_ports(qemu::EXIT_PORTS)
+        } else {
+            c
+        }
+    }
+}
@@ -0,0 +1,44 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use super::Id;
+use alloc::collections::{BTreeMap, BTreeSet};
+use syscalls::Error;
+
+/// The children of a process and the exit statuses which the process has not received yet.
+#[derive(Debug, Default)]
+pub(super) struct Children {
+    running: BTreeSet<Id>,
+    exited: BTreeMap<Id, i32>,
+}
+impl Children {
+    pub(super) fn add(&mut self, id: Id) {
+        self.running.insert(id);
+    }
+
+    /// Forgets the child `id`, which did not start.
+    pub(super) fn remove(&mut self, id: Id) {
+        self.running.remove(&id);
+    }
+
+    /// Records that the child `id` exited with `status`.
+    pub(super) fn exit(&mut self, id: Id, status: i32) {
+        if self.running.remove(&id) {
+            self.exited.insert(id, status);
+        }
+    }
+
+    /// Returns the exit status of the child `id`, or `None` if the child is still running.
+    pub(super) fn take_status(&mut self, id: Id) -> Result<Option<i32>, Error> {
+        if let Some(status) = self.exited.remove(&id) {
+            Ok(Some(status))
+        } else if self.running.contains(&id) {
+            Ok(None)
+        } else {
+            Err(Error::NoSuchProcess)
+        }
+    }
+
+    pub(super) fn running(&self) -> impl Iterator<Item = Id> + '_ {
+        self.running.iter().copied()
+    }
+}
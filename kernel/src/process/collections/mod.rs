@@ -1,4 +1,5 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
 pub(super) mod process;
+pub(super) mod sleeping_pid;
 pub(super) mod woken_pid;
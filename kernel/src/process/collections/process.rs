@@ -34,12 +34,33 @@ where
     f(p)
 }
 
-pub(in crate::process) fn remove(id: process::Id) {
-    lock_processes().remove(&id).expect("No such process.");
+pub(in crate::process) fn handle_running_mut<T, U>(f: T) -> U
+where
+    T: FnOnce(&mut Process) -> U,
+{
+    let id = woken_pid::active_pid();
+    let mut l = lock_processes();
+    let p = l
+        .get_mut(&id)
+        .unwrap_or_else(|| panic!("Process of PID {} does not exist.", id.as_i32()));
+    f(p)
 }
 
+/// Returns `None` if there is no process of PID `id`.
+pub(in crate::process) fn try_handle_mut<T, U>(id: process::Id, f: T) -> Option<U>
+where
+    T: FnOnce(&mut Process) -> U,
+{
+    lock_processes().get_mut(&id).map(f)
+}
+
+/// Removes the process `id` from the collection. The caller drops the returned process after the
+/// lock is released, as dropping it takes time and uses system calls.
+pub(in crate::process) fn remove(id: process::Id) -> Process {
+    lock_processes().remove(&id).expect("No such process.")
+}
+
+/// Other CPUs also lock the processes, so this spins until the lock is released.
 fn lock_processes() -> SpinlockGuard<'static, BTreeMap<process::Id, Process>> {
-    PROCESSES
-        .try_lock()
-        .expect("Failed to acquire the lock of `PROCESSES`.")
+    PROCESSES.lock()
 }
@@ -0,0 +1,39 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::process;
+use alloc::{collections::BinaryHeap, vec::Vec};
+use conquer_once::spin::Lazy;
+use core::cmp::Reverse;
+use spinning_top::{Spinlock, SpinlockGuard};
+
+/// Pairs of the tick to wake a process at and its PID, the earliest first.
+type Heap = BinaryHeap<Reverse<(u64, process::Id)>>;
+
+static SLEEPING_PIDS: Lazy<Spinlock<Heap>> = Lazy::new(|| Spinlock::new(Heap::new()));
+
+/// Registers the process `id` to be woken at the tick `wake_at`.
+pub(in crate::process) fn add(wake_at: u64, id: process::Id) {
+    lock_heap().push(Reverse((wake_at, id)));
+}
+
+/// Removes and returns the processes which should be woken at or before the tick `now`.
+pub(in crate::process) fn pop_expired(now: u64) -> Vec<process::Id> {
+    let mut h = lock_heap();
+    let mut expired = Vec::new();
+
+    while let Some(Reverse((wake_at, id))) = h.peek() {
+        if *wake_at > now {
+            break;
+        }
+
+        expired.push(*id);
+        h.pop();
+    }
+
+    expired
+}
+
+/// Processes on any CPU sleep while the BSP wakes them, so this spins until the lock is released.
+fn lock_heap() -> SpinlockGuard<'static, Heap> {
+    SLEEPING_PIDS.lock()
+}
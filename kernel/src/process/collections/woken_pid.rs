@@ -1,33 +1,197 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use crate::process;
+// A multi-level feedback queue. A process starts at the level of its priority. It goes down a
+// level each time it uses up its time slice, and goes back to the level of its priority when it
+// is woken after blocking or sleeping. Every `BOOST_INTERVAL` ticks, all processes of a CPU go back
+// to the levels of their priorities so that processes which keep returning to higher levels do not
+// starve the demoted ones.
+
+use crate::{
+    process,
+    smp::{self, percpu},
+};
 use alloc::collections::VecDeque;
-use conquer_once::spin::Lazy;
-use spinning_top::{Spinlock, SpinlockGuard};
+use core::{
+    mem,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+use spinning_top::SpinlockGuard;
+use syscalls::Priority;
+
+/// The number of the levels. The lowest level is below that of the lowest priority so that
+/// CPU-bound processes do not delay interactive ones of the same priority.
+const NUM_LEVELS: usize = Priority::Low as usize + 2;
+
+/// The number of the ticks between boosts, which is a second.
+const BOOST_INTERVAL: u64 = 1000;
+
+/// The CPU to which the next process is assigned.
+static NEXT_CPU: AtomicUsize = AtomicUsize::new(0);
+
+/// The time slice of the highest level in timer ticks. The time slice doubles at each lower level.
+static QUANTUM: AtomicU64 = AtomicU64::new(10);
+
+/// Sets the process which runs on `cpu` only when no other process is ready.
+pub(in crate::process) fn init_idle(cpu: usize, id: process::Id) {
+    let mut q = lock_queue(cpu);
+    assert!(q.idle.is_none(), "The idle process is set more than once.");
+
+    q.idle = Some(id);
+}
+
+/// Returns the CPU which runs a new process. The CPUs are assigned in turn.
+pub(in crate::process) fn assign_cpu() -> usize {
+    NEXT_CPU.fetch_add(1, Ordering::Relaxed) % smp::count()
+}
+
+pub(in crate::process) fn set_quantum(ticks: u64) {
+    QUANTUM.store(ticks, Ordering::Relaxed);
+}
 
-static WOKEN_PIDS: Lazy<Spinlock<VecDeque<process::Id>>> =
-    Lazy::new(|| Spinlock::new(VecDeque::new()));
+/// Adds the process `id` to the queue of `cpu` at the level of `priority`. If `cpu` is another idle
+/// CPU, it is kicked to run the process without waiting for the next tick.
+pub(in crate::process) fn add(cpu: usize, id: process::Id, priority: Priority) {
+    let mut q = lock_queue(cpu);
+    q.ready[level_of(priority)].push_back((id, priority));
 
-pub(in crate::process) fn add(id: process::Id) {
-    lock_queue().push_back(id)
+    let idle = q.is_idle();
+    drop(q);
+
+    if idle && cpu != smp::id() {
+        smp::kick(cpu);
+    }
 }
 
+/// Makes the next ready process active on the current CPU. The current active process goes to the
+/// back of the queue of its level unless it has been removed by `pop`.
 pub(in crate::process) fn change_active_pid() {
-    lock_queue().rotate_left(1)
+    lock_current_queue().change_active_pid();
+}
+
+/// Consumes a tick of the time slice of the active process of the current CPU, and returns `true`
+/// if the process should be preempted.
+pub(in crate::process) fn tick() -> bool {
+    lock_current_queue().tick()
 }
 
 pub(super) fn active_pid() -> process::Id {
-    lock_queue()[0]
+    try_active_pid().expect("No process is running.")
+}
+
+/// Returns the active process of the current CPU, or `None` if no process is running yet.
+pub(in crate::process) fn try_active_pid() -> Option<process::Id> {
+    lock_current_queue().active
 }
 
+/// Removes the active process of the current CPU from the queue. The process will not run until it
+/// is added again.
 pub(in crate::process) fn pop() -> process::Id {
-    lock_queue()
-        .pop_front()
-        .expect("All processes are terminated.")
+    lock_current_queue()
+        .active
+        .take()
+        .expect("No process is running.")
+}
+
+fn level_of(priority: Priority) -> usize {
+    priority as usize
+}
+
+fn quantum_of(level: usize) -> u64 {
+    QUANTUM.load(Ordering::Relaxed) << level
+}
+
+fn lock_current_queue() -> SpinlockGuard<'static, Queue> {
+    percpu::current().run_queue().lock()
+}
+
+/// Other CPUs also lock the queue to add processes, so this spins until the lock is released.
+fn lock_queue(cpu: usize) -> SpinlockGuard<'static, Queue> {
+    percpu::get(cpu).run_queue().lock()
+}
+
+/// The run queue of a CPU, which is in the per-CPU block. A process always runs on the same CPU so
+/// that its registers are saved and restored by one CPU.
+#[derive(Default)]
+pub(crate) struct Queue {
+    idle: Option<process::Id>,
+    active: Option<process::Id>,
+    active_level: usize,
+    active_priority: Option<Priority>,
+    slice_left: u64,
+    ticks: u64,
+    ready: [VecDeque<(process::Id, Priority)>; NUM_LEVELS],
 }
+impl Queue {
+    fn change_active_pid(&mut self) {
+        if let Some(id) = self.active.take() {
+            if let Some(priority) = self.active_priority {
+                self.ready[self.active_level].push_back((id, priority));
+            }
+        }
+
+        let (id, level, priority) = self
+            .pop_highest()
+            .map_or((self.idle_pid(), NUM_LEVELS - 1, None), |(id, level, p)| {
+                (id, level, Some(p))
+            });
+        self.active = Some(id);
+        self.active_level = level;
+        self.active_priority = priority;
+        self.slice_left = quantum_of(level);
+    }
+
+    fn tick(&mut self) -> bool {
+        self.ticks += 1;
+        if self.ticks % BOOST_INTERVAL == 0 {
+            self.boost();
+        }
+
+        if self.active == Some(self.idle_pid()) {
+            return self.ready.iter().any(|q| !q.is_empty());
+        }
+
+        self.slice_left = self.slice_left.saturating_sub(1);
+        if self.slice_left == 0 {
+            self.active_level = (self.active_level + 1).min(NUM_LEVELS - 1);
+            true
+        } else {
+            self.higher_level_is_ready()
+        }
+    }
+
+    /// Moves all processes up to the levels of their priorities. The processes of higher levels
+    /// stay ahead.
+    fn boost(&mut self) {
+        for level in 1..NUM_LEVELS {
+            for (id, priority) in mem::take(&mut self.ready[level]) {
+                self.ready[level_of(priority).min(level)].push_back((id, priority));
+            }
+        }
+
+        if let Some(priority) = self.active_priority {
+            self.active_level = self.active_level.min(level_of(priority));
+        }
+    }
+
+    fn higher_level_is_ready(&self) -> bool {
+        self.ready[..self.active_level]
+            .iter()
+            .any(|q| !q.is_empty())
+    }
+
+    /// Returns `true` if the CPU runs its idle process.
+    fn is_idle(&self) -> bool {
+        self.active.is_some() && self.active == self.idle
+    }
+
+    fn idle_pid(&self) -> process::Id {
+        self.idle.expect("The idle process is not set.")
+    }
 
-fn lock_queue() -> SpinlockGuard<'static, VecDeque<process::Id>> {
-    WOKEN_PIDS
-        .try_lock()
-        .expect("Failed to acquire the lock of `WOKEN_PIDS`.")
+    fn pop_highest(&mut self) -> Option<(process::Id, usize, Priority)> {
+        self.ready
+            .iter_mut()
+            .enumerate()
+            .find_map(|(level, q)| q.pop_front().map(|(id, p)| (id, level, p)))
+    }
 }
@@ -0,0 +1,329 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use super::page_table::{self, Target};
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::convert::{TryFrom, TryInto};
+use elf_rs::{Elf, ElfMachine, ElfType, ProgramType};
+use page_box::PageBox;
+use spinning_top::Spinlock;
+use x86_64::{
+    structures::paging::{PageSize, PageTableFlags, Size4KiB},
+    VirtAddr,
+};
+
+/// The lowest address of the higher half. User programs must not be placed at or above this.
+const USER_END: u64 = 0x0000_8000_0000_0000;
+
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+
+/// The images of the running executables, keyed by the addresses of the executables.
+static IMAGES: Spinlock<BTreeMap<usize, Weak<Image>>> = Spinlock::new(BTreeMap::new());
+
+#[derive(Debug)]
+pub(super) struct Binary {
+    image: Arc<Image>,
+}
+impl Binary {
+    /// Loads an ELF64 executable and maps its loadable segments into `tables`. The segments are
+    /// shared with the other processes of the same executable. The writable ones are copied on
+    /// write.
+    pub(super) fn load(
+        raw: &'static [u8],
+        tables: &mut page_table::Collection,
+    ) -> Result<Self, Error> {
+        let image = Image::get_or_load(raw)?;
+
+        for s in &image.segments {
+            s.map(tables);
+        }
+
+        Ok(Self { image })
+    }
+
+    pub(super) fn entry(&self) -> VirtAddr {
+        self.image.entry
+    }
+}
+
+/// The loaded segments of an executable.
+#[derive(Debug)]
+struct Image {
+    entry: VirtAddr,
+    segments: Vec<LoadedSegment>,
+}
+impl Image {
+    fn get_or_load(raw: &'static [u8]) -> Result<Arc<Self>, Error> {
+        let key = raw.as_ptr() as usize;
+        let mut images = IMAGES.lock();
+
+        if let Some(i) = images.get(&key).and_then(Weak::upgrade) {
+            return Ok(i);
+        }
+
+        let i = Arc::new(Self::load(raw)?);
+        images.insert(key, Arc::downgrade(&i));
+        Ok(i)
+    }
+
+    fn load(raw: &[u8]) -> Result<Self, Error> {
+        let elf = match Elf::from_bytes(raw).map_err(Error::Parse)? {
+            Elf::Elf64(e) => e,
+            Elf::Elf32(_) => return Err(Error::Not64Bit),
+        };
+
+        let h = elf.header();
+        let entry = Self::check_header(h.elftype(), h.machine(), h.entry_point())?;
+
+        let segments = elf
+            .program_header_iter()
+            .filter(|ph| ph.ph.ph_type() == ProgramType::LOAD && ph.ph.memsz() > 0)
+            .map(|ph| Segment {
+                offset: ph.ph.offset(),
+                vaddr: ph.ph.vaddr(),
+                filesz: ph.ph.filesz(),
+                memsz: ph.ph.memsz(),
+                flags: ph.ph.flags(),
+            })
+            .collect();
+        let segments = Self::load_segments(segments, raw)?;
+
+        Ok(Self { entry, segments })
+    }
+
+    /// Loads `segments`. The segments sharing a page are loaded into the same pages, which have
+    /// the permissions of all of them.
+    fn load_segments(mut segments: Vec<Segment>, raw: &[u8]) -> Result<Vec<LoadedSegment>, Error> {
+        segments.sort_by_key(|s| s.vaddr);
+
+        let mut loaded = Vec::new();
+        let mut rest = &segments[..];
+
+        while !rest.is_empty() {
+            let (group, r) = rest.split_at(num_sharing_pages(rest)?);
+            loaded.push(LoadedSegment::load(group, raw)?);
+            rest = r;
+        }
+
+        Ok(loaded)
+    }
+
+    /// Returns the entry address.
+    fn check_header(ty: ElfType, machine: ElfMachine, entry: u64) -> Result<VirtAddr, Error> {
+        if ty != ElfType::ET_EXEC {
+            Err(Error::NotExecutable)
+        } else if machine == ElfMachine::x86_64 {
+            user_addr(entry)
+        } else {
+            Err(Error::UnsupportedMachine)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct LoadedSegment {
+    start: VirtAddr,
+    flags: PageTableFlags,
+    pages: PageBox<[u8]>,
+}
+impl LoadedSegment {
+    /// Loads `segments` into one set of pages. The segments must be sorted by their addresses.
+    fn load(segments: &[Segment], raw: &[u8]) -> Result<Self, Error> {
+        let start = user_addr(segments[0].vaddr)?.align_down(Size4KiB::SIZE);
+        let end = segments[segments.len() - 1].end()?.align_up(Size4KiB::SIZE);
+
+        let mut pages = PageBox::new_slice(0, usize::try_from(end - start).unwrap());
+        for s in segments {
+            let offset = usize::try_from(s.vaddr - start.as_u64()).unwrap();
+            s.copy_to(&mut pages[offset..], raw)?;
+        }
+
+        let elf_flags = segments.iter().fold(0, |f, s| f | s.flags);
+
+        Ok(Self {
+            start,
+            flags: page_table_flags(elf_flags),
+            pages,
+        })
+    }
+
+    fn map(&self, tables: &mut page_table::Collection) {
+        let to = Target::new(self.start, self.flags);
+
+        if self.flags.contains(PageTableFlags::WRITABLE) {
+            tables.map_copy_on_write(&self.pages, to);
+        } else {
+            tables.map_page_box_to(&self.pages, to);
+        }
+    }
+}
+
+struct Segment {
+    offset: u64,
+    vaddr: u64,
+    filesz: u64,
+    memsz: u64,
+    flags: u32,
+}
+impl Segment {
+    /// Copies the content of this segment to the beginning of `dst`.
+    fn copy_to(&self, dst: &mut [u8], raw: &[u8]) -> Result<(), Error> {
+        if self.memsz < self.filesz {
+            return Err(Error::InvalidSegment);
+        }
+
+        let content = self.content(raw)?;
+        dst[..content.len()].copy_from_slice(content);
+
+        Ok(())
+    }
+
+    /// Returns the address next to the last byte of this segment.
+    fn end(&self) -> Result<VirtAddr, Error> {
+        let end = self
+            .vaddr
+            .checked_add(self.memsz)
+            .ok_or(Error::InvalidSegment)?;
+        user_addr(end)
+    }
+
+    fn content<'a>(&self, raw: &'a [u8]) -> Result<&'a [u8], Error> {
+        let start: usize = self.offset.try_into().map_err(|_| Error::InvalidSegment)?;
+        let len: usize = self.filesz.try_into().map_err(|_| Error::InvalidSegment)?;
+        let end = start.checked_add(len).ok_or(Error::InvalidSegment)?;
+
+        raw.get(start..end).ok_or(Error::InvalidSegment)
+    }
+}
+
+/// Returns the number of the leading segments of `segments` which share pages with each other.
+/// `segments` must be sorted by their addresses. Segments which overlap each other are invalid.
+fn num_sharing_pages(segments: &[Segment]) -> Result<usize, Error> {
+    let mut end = segments[0].end()?;
+
+    for (i, s) in segments.iter().enumerate().skip(1) {
+        if s.vaddr < end.as_u64() {
+            return Err(Error::InvalidSegment);
+        }
+
+        if s.vaddr >= end.align_up(Size4KiB::SIZE).as_u64() {
+            return Ok(i);
+        }
+
+        end = s.end()?;
+    }
+
+    Ok(segments.len())
+}
+
+pub(super) struct StackWriter<'a> {
+    stack: &'a mut [u8],
+    base: VirtAddr,
+    offset: usize,
+}
+impl<'a> StackWriter<'a> {
+    /// `stack` is placed at `base` in the address space of the program.
+    pub(super) fn new(stack: &'a mut [u8], base: VirtAddr) -> Self {
+        let offset = stack.len();
+        Self {
+            stack,
+            base,
+            offset,
+        }
+    }
+
+    /// Writes `argc`, `argv`, `envp` and the auxiliary vector on the top of the stack as described
+    /// in the System V ABI, and returns the initial stack pointer.
+    pub(super) fn push_arguments(mut self, argv: &[String], envp: &[String]) -> VirtAddr {
+        let argv: Vec<u64> = argv.iter().map(|a| self.push_str(a)).collect();
+        let envp: Vec<u64> = envp.iter().map(|e| self.push_str(e)).collect();
+
+        // argc, argv, NULL, envp, NULL, AT_NULL and its value.
+        let num_words = 1 + argv.len() + 1 + envp.len() + 1 + 2;
+        self.align_down(16);
+        if num_words % 2 == 1 {
+            self.push_u64(0);
+        }
+
+        self.push_u64(0);
+        self.push_u64(0);
+        self.push_u64_array(&envp);
+        self.push_u64_array(&argv);
+        self.push_u64(argv.len().try_into().unwrap());
+
+        self.top()
+    }
+
+    /// Pushes a null-terminated string and returns its address.
+    fn push_str(&mut self, s: &str) -> u64 {
+        self.push_bytes(&[0]);
+        self.push_bytes(s.as_bytes());
+        self.top().as_u64()
+    }
+
+    /// Pushes a null-terminated array of `u64`.
+    fn push_u64_array(&mut self, a: &[u64]) {
+        self.push_u64(0);
+        for v in a.iter().rev() {
+            self.push_u64(*v);
+        }
+    }
+
+    fn push_u64(&mut self, v: u64) {
+        self.push_bytes(&v.to_le_bytes());
+    }
+
+    fn push_bytes(&mut self, b: &[u8]) {
+        self.offset = self
+            .offset
+            .checked_sub(b.len())
+            .expect("The arguments are too long.");
+        self.stack[self.offset..self.offset + b.len()].copy_from_slice(b);
+    }
+
+    fn align_down(&mut self, align: usize) {
+        // The bottom of the stack is page-aligned, so aligning the offset aligns the address.
+        self.offset -= self.offset % align;
+    }
+
+    fn top(&self) -> VirtAddr {
+        self.base + self.offset
+    }
+}
+
+fn user_addr(a: u64) -> Result<VirtAddr, Error> {
+    if a < USER_END {
+        Ok(VirtAddr::new(a))
+    } else {
+        Err(Error::KernelAddress)
+    }
+}
+
+fn page_table_flags(elf_flags: u32) -> PageTableFlags {
+    let mut f = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+
+    if elf_flags & PF_W != 0 {
+        f |= PageTableFlags::WRITABLE;
+    }
+
+    if elf_flags & PF_X == 0 {
+        f |= PageTableFlags::NO_EXECUTE;
+    }
+
+    f
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Parse(elf_rs::Error),
+    Not64Bit,
+    NotExecutable,
+    UnsupportedMachine,
+    InvalidSegment,
+    KernelAddress,
+}
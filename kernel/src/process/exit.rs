@@ -1,35 +1,38 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use super::{
-    collections,
-    manager::{self, Message},
-};
-use common::constant::INTERRUPT_STACK;
+use super::{collections, manager, switch};
+use crate::tss;
 
 // Do not define this as a function as the function cannot return.
 macro_rules! change_stack {
-    () => {
-        const RSP: u64 = INTERRUPT_STACK.as_u64();
+    () => {{
+        let rsp = tss::interrupt_stack().as_u64();
         unsafe {
             asm!("
             mov rsp, {}
-            ", const RSP);
+            ", in(reg) rsp);
         }
-    };
+    }};
 }
 
-pub fn exit() -> ! {
+/// Terminates the current process. The parent receives `status` with `wait`.
+///
+/// Interrupts must be disabled so that the timer does not switch back to this process after it is
+/// removed from the run queue.
+pub fn exit(status: i32) -> ! {
+    // Record the exit before changing the stack as `status` may be on the current stack.
+    record_exit(status);
     change_stack!();
     manager::set_temporary_stack_frame();
-    send_exit_message();
     cause_timer_interrupt();
 }
 
-fn send_exit_message() {
+/// The manager is told about the exit after the switch leaves the page tables of this process.
+fn record_exit(status: i32) {
     let id = collections::woken_pid::pop();
-    manager::send_message(Message::Exit(id));
+    switch::set_pending_exit(id, status);
 }
 
 fn cause_timer_interrupt() -> ! {
-    unsafe { asm!("int 0x20", options(noreturn)) }
+    unsafe { asm!("int 0x81", options(noreturn)) }
 }
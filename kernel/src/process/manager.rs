@@ -1,69 +1,614 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use super::{collections, collections::woken_pid, switch, Privilege, Process};
-use crate::tss::TSS;
-use common::constant::INTERRUPT_STACK;
-use conquer_once::spin::Lazy;
+use super::{
+    collections,
+    collections::{sleeping_pid, woken_pid},
+    shared_memory, switch, Capabilities, Fault, Privilege, Process, Resources, State,
+};
+use crate::{
+    initrd,
+    interrupt::{self, timer},
+    mem::{
+        self, allocator,
+        allocator::{
+            phys::{Recorder, FRAME_MANAGER},
+            virt,
+        },
+        paging,
+    },
+    smp, tss,
+};
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use common::mem::reserved::PhysRange;
+use conquer_once::spin::{Lazy, OnceCell};
 use crossbeam_queue::ArrayQueue;
+use os_units::{Bytes, NumOfPages};
+use syscalls::{Access, Error, Priority, INTERRUPT_PID, MAX_SHARED_MEMORY, MAX_STACK_LIMIT};
+use x86_64::{
+    instructions::interrupts,
+    structures::paging::{PageTableFlags, Size4KiB},
+    PhysAddr, VirtAddr,
+};
 
 pub use super::exit::exit;
-pub use switch::switch;
+pub use switch::{preempt, switch};
 
 const MAX_MESSAGE: usize = 128;
+// Requests from processes may fill only this many slots so that the exit messages always fit.
+const MAX_SPAWN_REQUESTS: usize = MAX_MESSAGE / 2;
 static MESSAGE: Lazy<ArrayQueue<Message>> = Lazy::new(|| ArrayQueue::new(MAX_MESSAGE));
+static MANAGER_PID: OnceCell<super::Id> = OnceCell::uninit();
 
 pub fn main() {
     loop {
         while let Some(m) = MESSAGE.pop() {
             match m {
-                Message::Add(f, p) => match p {
-                    Privilege::Kernel => push_process_to_queue(Process::kernel(f)),
-                    Privilege::User => push_process_to_queue(Process::user(f)),
-                },
-                Message::Exit(id) => collections::process::remove(id),
+                Message::Add(f) => start_function(f),
+                Message::Spawn(s) => spawn_binary(&s),
+                Message::Exit(id, status) => exit_process(id, status),
             }
         }
+
+        wait_for_manager_message();
     }
 }
 
 pub fn init() {
     set_temporary_stack_frame();
-    push_process_to_queue(Process::user(main));
+    add_idle_processes();
+    add_manager_process();
 }
 
 pub fn add(f: fn(), p: Privilege) {
-    send_message(Message::Add(f, p));
+    add_function(Function::new(f, p));
+}
+
+pub fn add_with_priority(f: fn(), p: Privilege, priority: Priority) {
+    add_function(Function {
+        priority,
+        ..Function::new(f, p)
+    });
+}
+
+pub fn add_function(f: Function) {
+    send_message(Message::Add(f)).expect("The manager is busy.");
+}
+
+/// Starts the program `name` in the initrd. `args` does not contain the program name.
+pub fn spawn(name: &str, args: &[&str]) {
+    spawn_with_priority(name, args, Priority::Normal);
+}
+
+pub fn spawn_with_priority(name: &str, args: &[&str], priority: Priority) {
+    send_message(Message::Spawn(Spawn {
+        name: name.to_string(),
+        args: args.iter().map(ToString::to_string).collect(),
+        priority,
+        env: Vec::new(),
+        id: super::Id::new(),
+        parent: None,
+    }))
+    .expect("The manager is busy.");
+}
+
+/// Starts the program at `path` as a child of the current process with the environment `env`,
+/// and returns its PID.
+///
+/// The program is loaded by the manager process later. If the loading fails, the child exits with
+/// `STATUS_KILLED`. This returns `Error::InboxFull` if the manager has too many requests to serve.
+pub fn spawn_child(path: &str, args: Vec<String>, env: Vec<String>) -> Result<i32, Error> {
+    check_spawn_request(path)?;
+
+    let id = super::Id::new();
+    let parent = collections::process::handle_running_mut(|p| {
+        p.children.add(id);
+        p.id
+    });
+
+    send_message(Message::Spawn(Spawn {
+        name: path.to_string(),
+        args,
+        priority: Priority::Normal,
+        env,
+        id,
+        parent: Some(parent),
+    }))
+    .map_err(|e| {
+        collections::process::handle_running_mut(|p| p.children.remove(id));
+        e
+    })?;
+
+    Ok(id.as_i32())
+}
+
+fn check_spawn_request(path: &str) -> Result<(), Error> {
+    if initrd::open(path).is_none() {
+        Err(Error::NoSuchFile)
+    } else if MESSAGE.len() >= MAX_SPAWN_REQUESTS {
+        Err(Error::InboxFull)
+    } else {
+        Ok(())
+    }
+}
+
+/// Blocks the current process until its child `pid` exits, and returns the exit status.
+///
+/// Interrupts must be disabled. Otherwise the child may exit between the check and blocking.
+pub fn wait(pid: i32) -> Result<i32, Error> {
+    let id = super::Id::from(pid);
+
+    loop {
+        if let Some(r) = take_or_block(State::Waiting(id), |p| {
+            p.children.take_status(id).transpose()
+        }) {
+            return r;
+        }
+    }
+}
+
+/// Sets the priority of the process `pid`. Without the capability for scheduling, the current
+/// process can only lower its own priority and those of its children.
+pub fn set_priority(pid: i32, priority: Priority) -> Result<(), Error> {
+    let current = collections::process::handle_running(|p| p.id);
+    let allowed = is_allowed(Capabilities::allows_scheduling);
+
+    collections::process::try_handle_mut(super::Id::from(pid), |p| {
+        let related = p.id == current || p.parent == Some(current);
+        let lowering = priority as u8 >= p.priority as u8;
+
+        if allowed || (related && lowering) {
+            p.priority = priority;
+            Ok(())
+        } else {
+            Err(Error::PermissionDenied)
+        }
+    })
+    .unwrap_or(Err(Error::NoSuchProcess))
+}
+
+/// Sets the time slice of the highest level of the scheduler. The time slice must not be shorter
+/// than a tick.
+pub fn set_quantum(milliseconds: u32) -> Result<(), Error> {
+    let ticks = u64::from(milliseconds) * u64::from(timer::TICK_HZ) / 1000;
+
+    if ticks == 0 {
+        Err(Error::InvalidArgument)
+    } else {
+        woken_pid::set_quantum(ticks);
+        Ok(())
+    }
+}
+
+/// Wakes the processes which sleep until the tick `now` or earlier.
+pub fn wake_sleeping(now: u64) {
+    for id in sleeping_pid::pop_expired(now) {
+        wake(id);
+    }
+}
+
+/// Blocks the current process for `ticks` timer interrupts.
+///
+/// Interrupts must be disabled. Otherwise the timer may try to wake this process before it
+/// sleeps.
+pub fn sleep(ticks: u64) {
+    sleep_until(timer::ticks().saturating_add(ticks));
+}
+
+/// Blocks the current process until the tick `tick`. This function returns immediately if the
+/// tick has already passed.
+///
+/// Interrupts must be disabled for the same reason as `sleep`.
+pub fn sleep_until(tick: u64) {
+    if tick <= timer::ticks() {
+        return;
+    }
+
+    take_or_block(State::Sleeping, |p| {
+        sleeping_pid::add(tick, p.id);
+        None::<()>
+    });
+}
+
+/// Returns `true` if `f` returns `true` for the capabilities of the current process. Processes
+/// with the kernel privilege are allowed to do anything.
+pub fn is_allowed(f: impl FnOnce(&Capabilities) -> bool) -> bool {
+    collections::process::handle_running(|p| {
+        matches!(p.privilege, Privilege::Kernel) || f(&p.capabilities)
+    })
 }
 
 pub fn getpid() -> i32 {
     collections::process::handle_running(|p| p.id.as_i32())
 }
 
-pub fn notify(pid: i32) {
-    let _ = collections::process::handle(super::Id::from(pid), |p| {
-        p.inbox.push(super::message::Message)
-    });
+/// Calls `f` with the resources of the current process. This function returns `None` if no
+/// process is running.
+pub fn with_resources<T>(f: impl FnOnce(&mut Resources) -> T) -> Option<T> {
+    let id = woken_pid::try_active_pid()?;
+    collections::process::try_handle_mut(id, |p| f(&mut p.resources))
+}
+
+/// Allocates pages in the address space of the current process. Before the first process runs,
+/// the pages are allocated in the current address space.
+pub fn allocate_pages(n: NumOfPages<Size4KiB>) -> Option<(VirtAddr, PhysAddr)> {
+    if is_any_process_running() {
+        collections::process::handle_running_mut(|p| p.tables.allocate_pages(n))
+    } else {
+        allocator::allocate_pages(n, &mut virt::KERNEL.lock(), &mut Recorder::default())
+    }
+}
+
+/// Reserves pages in the address space of the current process, which are mapped on the first
+/// access. This returns `None` if no process is running.
+pub fn allocate_lazy_pages(n: NumOfPages<Size4KiB>) -> Option<VirtAddr> {
+    let id = woken_pid::try_active_pid()?;
+    collections::process::try_handle_mut(id, |p| p.tables.allocate_lazy_pages(n)).flatten()
+}
+
+/// Frees the pages which `allocate_lazy_pages` returned, and returns `false` if the pages were not
+/// allocated so.
+pub fn deallocate_lazy_pages(v: VirtAddr, n: NumOfPages<Size4KiB>) -> bool {
+    woken_pid::try_active_pid().map_or(false, |id| {
+        collections::process::try_handle_mut(id, |p| p.tables.deallocate_lazy_pages(v, n))
+            == Some(true)
+    })
+}
+
+/// Maps the page containing `addr` if the current process allocated it lazily or shares it as
+/// copy-on-write, or if it is on the stack within the limit.
+pub fn handle_page_fault(addr: VirtAddr, write: bool) -> Fault {
+    woken_pid::try_active_pid()
+        .and_then(|id| {
+            collections::process::try_handle_mut(id, |p| p.tables.handle_page_fault(addr, write))
+        })
+        .unwrap_or(Fault::Invalid)
+}
+
+/// Sets the size up to which the stack of the current process grows.
+pub fn set_stack_limit(bytes: Bytes) -> Result<(), Error> {
+    if bytes.as_usize() == 0 || bytes > MAX_STACK_LIMIT {
+        return Err(Error::InvalidArgument);
+    }
+
+    collections::process::handle_running_mut(|p| p.tables.set_stack_limit(bytes));
+    Ok(())
+}
+
+/// Creates shared memory of `n` pages, which the current process owns, and returns its ID. The
+/// shared memory which a process owns is up to `MAX_SHARED_MEMORY` in total.
+pub fn create_shared_memory(n: NumOfPages<Size4KiB>) -> Result<u64, Error> {
+    let owned = collections::process::handle_running(|p| p.resources.shared_memory_pages());
+    if owned.as_usize() + n.as_usize() > MAX_SHARED_MEMORY.as_num_of_pages::<Size4KiB>().as_usize()
+    {
+        return Err(Error::OutOfMemory);
+    }
+
+    let id = shared_memory::create(n);
+    collections::process::handle_running_mut(|p| p.resources.add_shared_memory(id, n));
+    Ok(id)
+}
+
+/// Maps the shared memory `id`, which the current process owns, to the address space of the
+/// process `pid`, and returns the address in that address space.
+pub fn map_shared_memory(id: u64, pid: i32, access: Access) -> Result<VirtAddr, Error> {
+    let owns = collections::process::handle_running(|p| p.resources.owns_shared_memory(id));
+    let memory = shared_memory::get(id)
+        .filter(|_| owns)
+        .ok_or(Error::InvalidArgument)?;
+
+    let f = match access {
+        Access::ReadOnly => paging::USER_DATA - PageTableFlags::WRITABLE,
+        Access::ReadWrite => paging::USER_DATA,
+    };
+
+    collections::process::try_handle_mut(super::Id::from(pid), |p| {
+        p.tables.map_shared_memory(memory, f)
+    })
+    .ok_or(Error::NoSuchProcess)?
+    .ok_or(Error::OutOfMemory)
+}
+
+/// Unmaps the shared memory mapped at `v` in the address space of the current process.
+pub fn unmap_shared_memory(v: VirtAddr) -> Result<(), Error> {
+    if collections::process::handle_running_mut(|p| p.tables.unmap_shared_memory(v)) {
+        Ok(())
+    } else {
+        Err(Error::InvalidArgument)
+    }
+}
+
+/// Destroys the shared memory `id`, which the current process owns.
+pub fn destroy_shared_memory(id: u64) -> Result<(), Error> {
+    if collections::process::handle_running_mut(|p| p.resources.remove_shared_memory(id)) {
+        shared_memory::destroy(id);
+        Ok(())
+    } else {
+        Err(Error::InvalidArgument)
+    }
+}
+
+/// Returns the virtual addresses of the deallocated pages to the address space of the current
+/// process, or to the current address space before the first process runs.
+pub fn free_virt(v: VirtAddr, n: NumOfPages<Size4KiB>) {
+    if is_any_process_running() {
+        collections::process::handle_running_mut(|p| p.tables.free_virt(v, n));
+    } else {
+        virt::KERNEL.lock().free(v, n);
+    }
+}
+
+/// Maps the physical region to the address space of the current process. The region is
+/// user-accessible only if the process runs in the user privilege. Before the first process runs,
+/// the region is mapped to the current address space.
+pub fn map_pages(start: PhysAddr, bytes: Bytes) -> VirtAddr {
+    let range = PhysRange::new(start, bytes);
+
+    if is_any_process_running() {
+        collections::process::handle_running_mut(|p| {
+            let f = match p.privilege {
+                Privilege::Kernel => paging::KERNEL_DATA,
+                Privilege::User => paging::USER_DATA,
+            };
+            p.tables.map_pages(range, f)
+        })
+    } else {
+        mem::map_pages_with(range, paging::USER_DATA, &mut *FRAME_MANAGER.lock())
+    }
+}
+
+fn is_any_process_running() -> bool {
+    woken_pid::try_active_pid().is_some()
+}
+
+/// Returns the PID of the current process, or `None` if it is unknown.
+pub fn try_getpid() -> Option<i32> {
+    woken_pid::try_active_pid().map(super::Id::as_i32)
+}
+
+/// Notifies the process `pid` that the interrupt `vec` happened.
+pub fn notify(pid: i32, vec: u64) {
+    let m = syscalls::Message {
+        sender: INTERRUPT_PID,
+        body: [vec, 0, 0, 0],
+    };
+
+    if let Err(e) = deliver(super::Id::from(pid), m) {
+        warn!("Failed to notify PID {} of an interrupt: {:?}", pid, e);
+    }
 }
 
 pub fn notify_exists() -> bool {
-    collections::process::handle_running(|p| p.inbox.pop()).is_some()
+    collections::process::handle_running_mut(|p| p.inbox.pop(INTERRUPT_PID)).is_some()
 }
 
-pub(super) fn send_message(m: Message) {
-    MESSAGE.push(m).expect("`MESSAGE` is full.");
+pub fn send(to: i32, mut m: syscalls::Message) -> Result<(), Error> {
+    m.sender = getpid();
+    deliver(super::Id::from(to), m)
 }
 
+/// Receives a message from `from`. If there is no such message, the current process is blocked
+/// until one arrives.
+pub fn receive(from: i32) -> syscalls::Message {
+    loop {
+        if let Some(m) = take_or_block(State::Receiving(from), |p| p.inbox.pop(from)) {
+            return m;
+        }
+    }
+}
+
+/// Sends `m` to `to`, and waits for the reply which `to` sends with `reply`. Other messages from
+/// `to` stay in the inbox.
+pub fn call(to: i32, m: syscalls::Message) -> Result<syscalls::Message, Error> {
+    collections::process::handle_running_mut(|p| p.inbox.expect_reply(to));
+
+    if let Err(e) = send(to, m) {
+        collections::process::handle_running_mut(|p| p.inbox.cancel_reply());
+        return Err(e);
+    }
+
+    loop {
+        if let Some(r) = take_or_block(State::Calling(to), |p| p.inbox.pop_reply(to)) {
+            return Ok(r);
+        }
+    }
+}
+
+/// Sends `m` to `to` as the reply to the call which `to` made to the current process.
+pub fn reply(to: i32, mut m: syscalls::Message) -> Result<(), Error> {
+    m.sender = getpid();
+
+    let to = super::Id::from(to);
+    let waiting = collections::process::try_handle_mut(to, |p| p.deliver_reply(m))
+        .ok_or(Error::NoSuchProcess)??;
+
+    if waiting {
+        wake(to);
+    }
+
+    Ok(())
+}
+
+fn deliver(to: super::Id, m: syscalls::Message) -> Result<(), Error> {
+    let waiting = collections::process::try_handle_mut(to, |p| p.deliver(m))
+        .ok_or(Error::NoSuchProcess)??;
+
+    if waiting {
+        wake(to);
+    }
+
+    Ok(())
+}
+
+fn wait_for_manager_message() {
+    // Disable interrupts so that the timer does not switch processes while blocking.
+    interrupts::without_interrupts(|| {
+        take_or_block(State::Blocked, |_| (!MESSAGE.is_empty()).then(|| ()));
+    });
+}
+
+/// Calls `f` with the current process, and blocks the process in `state` until `wake` is called
+/// with it if `f` returns `None`.
+///
+/// The state is set before `f` is called with the process locked, so `wake` on another CPU after
+/// `f` finds nothing is not lost.
+fn take_or_block<T>(state: State, f: impl FnOnce(&mut Process) -> Option<T>) -> Option<T> {
+    let taken = collections::process::handle_running_mut(|p| {
+        p.state = state;
+
+        let taken = f(p);
+        if taken.is_some() {
+            p.state = State::Ready;
+        }
+
+        taken
+    });
+
+    if taken.is_none() {
+        switch_from_blocked();
+    }
+
+    taken
+}
+
+/// Removes the current process from the run queue until `wake` is called with it.
+fn switch_from_blocked() {
+    woken_pid::pop();
+
+    // The current context is saved on the stack frame of this process, and this process resumes
+    // from here when it is woken.
+    //
+    // SAFETY: This operation is safe as the handler of the vector 0x81 preserves all registers.
+    unsafe { asm!("int 0x81") }
+}
+
+/// Makes the process `id` ready if it is blocked or sleeping. Nothing happens if there is no such
+/// process.
+fn wake(id: super::Id) {
+    let woken = collections::process::try_handle_mut(id, |p| p.wake().then(|| (p.cpu, p.priority)));
+
+    if let Some(Some((cpu, priority))) = woken {
+        woken_pid::add(cpu, id, priority);
+    }
+}
+
+/// Passes `m` to the manager process. This returns `Error::InboxFull` if the manager has
+/// `MAX_MESSAGE` messages to handle.
+pub(super) fn send_message(m: Message) -> Result<(), Error> {
+    MESSAGE.push(m).map_err(|_| Error::InboxFull)?;
+
+    if let Some(id) = MANAGER_PID.get() {
+        wake(*id);
+    }
+
+    Ok(())
+}
+
+/// Makes the current CPU save the context to its interrupt stack on the next switch.
 pub(super) fn set_temporary_stack_frame() {
-    TSS.lock().interrupt_stack_table[0] = INTERRUPT_STACK;
+    tss::current().lock().interrupt_stack_table[0] = tss::interrupt_stack();
+}
+
+fn spawn_binary(s: &Spawn) {
+    let argv: Vec<String> = core::iter::once(&s.name).chain(&s.args).cloned().collect();
+
+    match initrd::open(&s.name).map(|raw| Process::binary(raw, &argv, &s.env)) {
+        Some(Ok(mut p)) => {
+            p.id = s.id;
+            p.parent = s.parent;
+            p.priority = s.priority;
+            push_process_to_queue(p);
+            return;
+        }
+        Some(Err(e)) => warn!("Failed to load {}: {:?}", s.name, e),
+        None => warn!("{} is not found in the initrd.", s.name),
+    }
+
+    interrupts::without_interrupts(|| notify_parent(s.parent, s.id, syscalls::STATUS_KILLED));
+}
+
+/// Releases everything the process `id` has. Its memory is freed when the process is dropped.
+fn exit_process(id: super::Id, status: i32) {
+    // The interrupt handlers lock the list of the registrations and the processes.
+    let p = interrupts::without_interrupts(|| {
+        let p = collections::process::remove(id);
+
+        interrupt::handler::forget(id.as_i32());
+        notify_parent(p.parent, id, status);
+        orphan_children(&p);
+
+        p
+    });
+
+    drop(p);
+}
+
+fn notify_parent(parent: Option<super::Id>, id: super::Id, status: i32) {
+    if let Some(parent) = parent {
+        let waiting = collections::process::try_handle_mut(parent, |p| p.child_exited(id, status));
+
+        if waiting == Some(true) {
+            wake(parent);
+        }
+    }
+}
+
+/// The children of `p` become orphans. The manager discards their exit statuses.
+fn orphan_children(p: &Process) {
+    for c in p.children.running() {
+        collections::process::try_handle_mut(c, |c| c.parent = None);
+    }
+}
+
+fn add_idle_processes() {
+    for cpu in 0..smp::count() {
+        let mut p = Process::kernel(idle);
+        p.cpu = cpu;
+        woken_pid::init_idle(cpu, p.id());
+        add_process(p);
+    }
+}
+
+fn add_manager_process() {
+    // The manager runs in the kernel privilege to disable interrupts while it blocks itself.
+    let p = Process::kernel(main);
+    MANAGER_PID
+        .try_init_once(|| p.id())
+        .expect("`MANAGER_PID` is initialized more than once.");
+    push_process_to_queue(p);
+}
+
+fn idle() {
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+fn start_function(f: Function) {
+    let mut p = match f.privilege {
+        Privilege::Kernel => Process::kernel(f.f),
+        Privilege::User => Process::user(f.f),
+    };
+    p.priority = f.priority;
+    p.capabilities = f.capabilities;
+    push_process_to_queue(p);
 }
 
 fn push_process_to_queue(p: Process) {
-    add_pid(p.id());
-    add_process(p);
+    // The timer handler locks the queue and the processes.
+    interrupts::without_interrupts(|| {
+        let (cpu, id, priority) = (p.cpu, p.id(), p.priority);
+
+        // Add the process first so that the CPU finds it when switching to it.
+        add_process(p);
+        add_pid(cpu, id, priority);
+    });
 }
 
-fn add_pid(id: super::Id) {
-    woken_pid::add(id);
+fn add_pid(cpu: usize, id: super::Id, priority: Priority) {
+    woken_pid::add(cpu, id, priority);
 }
 
 fn add_process(p: Process) {
@@ -77,6 +622,37 @@ pub(super) fn loader(f: fn()) -> ! {
 
 #[derive(Debug)]
 pub(super) enum Message {
-    Add(fn(), Privilege),
-    Exit(super::Id),
+    Add(Function),
+    Spawn(Spawn),
+    Exit(super::Id, i32),
+}
+
+/// A function which the manager runs as a process.
+#[derive(Debug)]
+pub struct Function {
+    pub f: fn(),
+    pub privilege: Privilege,
+    pub priority: Priority,
+    pub capabilities: Capabilities,
+}
+impl Function {
+    /// Runs `f` with the normal priority and no capabilities.
+    pub fn new(f: fn(), privilege: Privilege) -> Self {
+        Self {
+            f,
+            privilege,
+            priority: Priority::Normal,
+            capabilities: Capabilities::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(super) struct Spawn {
+    name: String,
+    args: Vec<String>,
+    priority: Priority,
+    env: Vec<String>,
+    id: super::Id,
+    parent: Option<super::Id>,
 }
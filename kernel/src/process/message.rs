@@ -1,4 +1,75 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-#[derive(Debug)]
-pub(super) struct Message;
+use alloc::collections::VecDeque;
+use syscalls::{Error, Message, ANY_PID};
+
+#[derive(Debug, Default)]
+pub(super) struct Inbox {
+    messages: VecDeque<(Kind, Message)>,
+
+    /// The process from which this process waits for a reply by `call`.
+    reply_from: Option<i32>,
+}
+impl Inbox {
+    const CAPACITY: usize = 128;
+
+    pub(super) fn push(&mut self, m: Message) -> Result<(), Error> {
+        self.push_kind(Kind::Request, m)
+    }
+
+    /// Adds the reply to the call which this process made to the sender. A reply which this
+    /// process does not wait for is refused, so that a later call does not take it.
+    pub(super) fn push_reply(&mut self, m: Message) -> Result<(), Error> {
+        if self.reply_from != Some(m.sender) {
+            return Err(Error::InvalidArgument);
+        }
+
+        self.push_kind(Kind::Reply, m)?;
+        self.reply_from = None;
+        Ok(())
+    }
+
+    /// Makes this inbox accept a reply from `from`. This must be called before sending the call,
+    /// as the reply may arrive before the caller blocks.
+    pub(super) fn expect_reply(&mut self, from: i32) {
+        self.reply_from = Some(from);
+    }
+
+    pub(super) fn cancel_reply(&mut self) {
+        self.reply_from = None;
+    }
+
+    /// Removes the oldest message which `from` matches. Replies are only taken by `pop_reply`.
+    pub(super) fn pop(&mut self, from: i32) -> Option<Message> {
+        self.pop_kind(Kind::Request, |m| matches(from, m))
+    }
+
+    pub(super) fn pop_reply(&mut self, from: i32) -> Option<Message> {
+        self.pop_kind(Kind::Reply, |m| m.sender == from)
+    }
+
+    fn push_kind(&mut self, kind: Kind, m: Message) -> Result<(), Error> {
+        if self.messages.len() >= Self::CAPACITY {
+            Err(Error::InboxFull)
+        } else {
+            self.messages.push_back((kind, m));
+            Ok(())
+        }
+    }
+
+    fn pop_kind(&mut self, kind: Kind, f: impl Fn(&Message) -> bool) -> Option<Message> {
+        let i = self.messages.iter().position(|(k, m)| *k == kind && f(m))?;
+        self.messages.remove(i).map(|(_, m)| m)
+    }
+}
+
+/// Returns `true` if a receiver waiting for a message from `from` accepts `m`.
+pub(super) fn matches(from: i32, m: &Message) -> bool {
+    from == ANY_PID || from == m.sender
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Kind {
+    Request,
+    Reply,
+}
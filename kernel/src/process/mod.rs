@@ -1,21 +1,36 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+mod capability;
+mod children;
 mod collections;
+mod elf;
 mod exit;
 pub mod manager;
 mod message;
 mod page_table;
+mod resource;
+mod shared_memory;
 mod stack_frame;
 mod switch;
 
+pub use capability::Capabilities;
+pub(crate) use collections::woken_pid::Queue as RunQueue;
+pub use page_table::Fault;
+pub use resource::Resources;
+pub use stack_frame::CODE_SEGMENT_OFFSET;
+
+use crate::fpu;
+use alloc::{string::String, vec};
+use children::Children;
 use core::{
-    convert::TryInto,
+    convert::TryFrom,
     sync::atomic::{AtomicI32, Ordering},
 };
-use crossbeam_queue::ArrayQueue;
-use message::Message;
+use message::Inbox;
+use os_units::Bytes;
 use page_box::PageBox;
 use stack_frame::StackFrame;
+use syscalls::{Error, Message, Priority};
 use x86_64::{
     structures::paging::{PageSize, Size4KiB},
     PhysAddr, VirtAddr,
@@ -24,18 +39,34 @@ use x86_64::{
 #[derive(Debug)]
 pub struct Process {
     id: Id,
-    f: fn(),
     tables: page_table::Collection,
     pml4_addr: PhysAddr,
-    stack: PageBox<[u8]>,
+    /// The mapped part of the stack. The rest is mapped on page faults.
+    stack: Option<PageBox<[u8]>>,
     stack_frame: PageBox<StackFrame>,
+    /// The stack on which the kernel handles the system calls from ring 3.
+    syscall_stack: PageBox<[u8]>,
+    fpu: fpu::Area,
     privilege: Privilege,
+    binary: Option<elf::Binary>,
+
+    inbox: Inbox,
+    state: State,
+    priority: Priority,
+    capabilities: Capabilities,
+    resources: Resources,
+
+    parent: Option<Id>,
+    children: Children,
 
-    inbox: ArrayQueue<Message>,
+    /// The CPU which runs this process.
+    cpu: usize,
 }
 impl Process {
-    const STACK_SIZE: u64 = Size4KiB::SIZE * 12;
-    const BOX_SIZE: usize = 128;
+    const STACK_SIZE: Bytes = Bytes::new(0x1000 * 12);
+
+    /// The size up to which the stack grows unless the process changes it.
+    const DEFAULT_STACK_LIMIT: Bytes = Bytes::new(0x10_0000);
 
     pub fn kernel(f: fn()) -> Self {
         Self::new(f, Privilege::Kernel)
@@ -45,29 +76,119 @@ impl Process {
         Self::new(f, Privilege::User)
     }
 
+    /// Creates a user process from an ELF executable. `argv` and `envp` are passed to the program
+    /// through its stack.
+    ///
+    /// The segments are shared with the other processes of the same executable, so `raw` must
+    /// live as long as the kernel, such as a file in the initrd.
+    pub fn binary(
+        raw: &'static [u8],
+        argv: &[String],
+        envp: &[String],
+    ) -> Result<Self, elf::Error> {
+        let mut tables = page_table::Collection::binary();
+        let binary = elf::Binary::load(raw, &mut tables)?;
+
+        let stack_bottom = tables.reserve_stack(Self::DEFAULT_STACK_LIMIT);
+        let (stack, stack_pointer) = Self::push_arguments(stack_bottom, argv, envp);
+        tables.map_stack(&stack, stack_bottom);
+
+        let stack_frame = PageBox::from(StackFrame::binary(binary.entry(), stack_pointer));
+        tables.map_page_box(&stack_frame);
+
+        Ok(Process {
+            binary: Some(binary),
+            ..Self::from_parts(tables, Some(stack), stack_frame)
+        })
+    }
+
+    /// A process of the kernel privilege may use its stack while holding the locks which resolving
+    /// a page fault takes, so its stack is mapped in advance.
     fn new(f: fn(), privilege: Privilege) -> Self {
         let mut tables = page_table::Collection::default();
-        let stack = PageBox::new_slice(0, Self::STACK_SIZE.try_into().unwrap());
-        let stack_bottom = stack.virt_addr() + stack.bytes().as_usize();
-        let stack_frame = PageBox::from(match privilege {
-            Privilege::Kernel => StackFrame::kernel(f, stack_bottom),
-            Privilege::User => StackFrame::user(f, stack_bottom),
-        });
-        tables.map_page_box(&stack);
+        let stack_bottom = tables.reserve_stack(Self::DEFAULT_STACK_LIMIT);
+
+        let stack = match privilege {
+            Privilege::Kernel => Some(PageBox::new_slice(0, Self::STACK_SIZE.as_usize())),
+            Privilege::User => None,
+        };
+        if let Some(s) = &stack {
+            tables.map_stack(s, stack_bottom);
+        }
+
+        let stack_frame = PageBox::from(Self::initial_stack_frame(f, privilege, stack_bottom));
         tables.map_page_box(&stack_frame);
 
-        let pml4_addr = tables.pml4_addr();
+        Process {
+            privilege,
+            ..Self::from_parts(tables, stack, stack_frame)
+        }
+    }
+
+    /// Creates a process of the user privilege from its memory.
+    fn from_parts(
+        mut tables: page_table::Collection,
+        stack: Option<PageBox<[u8]>>,
+        stack_frame: PageBox<StackFrame>,
+    ) -> Self {
+        let (fpu, syscall_stack) = Self::create_kernel_areas(&mut tables);
 
         Process {
             id: Id::new(),
-            f,
+            pml4_addr: tables.pml4_addr(),
             tables,
-            pml4_addr,
             stack,
             stack_frame,
-            privilege,
+            syscall_stack,
+            fpu,
+            privilege: Privilege::User,
+            binary: None,
+            inbox: Inbox::default(),
+            state: State::Ready,
+            priority: Priority::Normal,
+            capabilities: Capabilities::default(),
+            resources: Resources::default(),
+            parent: None,
+            children: Children::default(),
+            cpu: collections::woken_pid::assign_cpu(),
+        }
+    }
+
+    /// Creates the areas which the kernel uses while the address space of the process is loaded:
+    /// the one to which `switch` saves the FPU registers, and the stack for the system calls.
+    fn create_kernel_areas(tables: &mut page_table::Collection) -> (fpu::Area, PageBox<[u8]>) {
+        let fpu = fpu::Area::default();
+        tables.map_page_box(fpu.page_box());
 
-            inbox: ArrayQueue::new(Self::BOX_SIZE),
+        let syscall_stack = PageBox::new_slice(0, Self::STACK_SIZE.as_usize());
+        tables.map_page_box(&syscall_stack);
+
+        (fpu, syscall_stack)
+    }
+
+    /// Writes the arguments on the bottom of a stack which ends at `stack_bottom`, and returns the
+    /// pages which contain them and the initial stack pointer. The other pages of the stack are
+    /// mapped on page faults.
+    fn push_arguments(
+        stack_bottom: VirtAddr,
+        argv: &[String],
+        envp: &[String],
+    ) -> (PageBox<[u8]>, VirtAddr) {
+        let stack_top = stack_bottom - Self::STACK_SIZE.as_usize();
+
+        let mut image = vec![0; Self::STACK_SIZE.as_usize()];
+        let stack_pointer = elf::StackWriter::new(&mut image, stack_top).push_arguments(argv, envp);
+
+        let start = stack_pointer.align_down(Size4KiB::SIZE) - stack_top;
+        let stack = PageBox::from(&image[usize::try_from(start).unwrap()..]);
+
+        (stack, stack_pointer)
+    }
+
+    fn initial_stack_frame(f: fn(), privilege: Privilege, stack_bottom: VirtAddr) -> StackFrame {
+        match privilege {
+            Privilege::Kernel => StackFrame::kernel(f, stack_bottom),
+            Privilege::User => StackFrame::user(f, stack_bottom),
         }
     }
 
@@ -75,6 +196,36 @@ impl Process {
         self.id
     }
 
+    /// Puts `m` in the inbox, and returns `true` if this process is waiting for `m`.
+    fn deliver(&mut self, m: Message) -> Result<bool, Error> {
+        self.inbox.push(m)?;
+
+        Ok(match self.state {
+            State::Receiving(from) => message::matches(from, &m),
+            _ => false,
+        })
+    }
+
+    /// Adds the reply `m` to the inbox, and returns `true` if this process is waiting for it.
+    fn deliver_reply(&mut self, m: Message) -> Result<bool, Error> {
+        self.inbox.push_reply(m)?;
+        Ok(self.state == State::Calling(m.sender))
+    }
+
+    /// Records that the child `id` exited with `status`, and returns `true` if this process is
+    /// waiting for the child.
+    fn child_exited(&mut self, id: Id, status: i32) -> bool {
+        self.children.exit(id, status);
+        self.state == State::Waiting(id)
+    }
+
+    /// Makes this process ready, and returns `true` if it was blocked or sleeping.
+    fn wake(&mut self) -> bool {
+        let blocked = self.state != State::Ready;
+        self.state = State::Ready;
+        blocked
+    }
+
     fn stack_frame_top_addr(&self) -> VirtAddr {
         self.stack_frame.virt_addr()
     }
@@ -83,9 +234,26 @@ impl Process {
         let b = self.stack_frame.bytes();
         self.stack_frame_top_addr() + b.as_usize()
     }
+
+    fn syscall_stack_bottom_addr(&self) -> VirtAddr {
+        self.syscall_stack.virt_addr() + self.syscall_stack.bytes().as_usize()
+    }
 }
 
-#[derive(Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum State {
+    Ready,
+    Blocked,
+    /// Blocked until a message from the sender arrives.
+    Receiving(i32),
+    /// Blocked until the reply from the callee arrives.
+    Calling(i32),
+    Sleeping,
+    /// Blocked until the child exits.
+    Waiting(Id),
+}
+
+#[derive(Copy, Clone, Debug)]
 pub enum Privilege {
     Kernel,
     User,
@@ -1,55 +1,448 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use crate::mem::paging::pml4::PML4;
-use alloc::collections::BTreeMap;
-use core::convert::TryFrom;
+use super::shared_memory::SharedMemory;
+use crate::mem::{
+    self,
+    allocator::{
+        self,
+        phys::{Recorder, FRAME_MANAGER},
+        virt::AddressSpace,
+    },
+    paging::{self, pml4::PML4},
+};
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use common::{constant::RECUR_PML4_INDEX, mem::reserved::PhysRange};
+use core::{convert::TryFrom, ops::Range, ptr, slice};
+use os_units::{Bytes, NumOfPages};
 use page_box::PageBox;
+use syscalls::MAX_STACK_LIMIT;
 use x86_64::{
+    instructions::interrupts,
+    registers::control::Cr3,
     structures::paging::{
-        Page, PageSize, PageTable, PageTableFlags, PageTableIndex, PhysFrame, Size4KiB,
+        FrameAllocator, Mapper, Page, PageSize, PageTable, PageTableFlags, PageTableIndex,
+        PhysFrame, Size4KiB,
     },
-    PhysAddr,
+    PhysAddr, VirtAddr,
 };
 
+const PAGE_BYTES: usize = 0x1000;
+
+/// The page tables of an address space.
+///
+/// The lower half belongs to the process. The PML4 entries 510 and 511 are shared with the kernel,
+/// and the entry `RECUR_PML4_INDEX` is the recursive one. The shared entries are user-accessible
+/// unless the address space is of a binary, as the processes of the user privilege run the kernel
+/// image in ring 3.
 #[derive(Debug)]
 pub(super) struct Collection {
     pml4: PageBox<PageTable>,
+    /// Cached as `PageBox::phys_addr` uses a system call, which cannot be made while handling one.
+    pml4_addr: PhysAddr,
     pdpt: BTreeMap<PageTableIndex, PageBox<PageTable>>,
-    pd: BTreeMap<PageTableIndex, PageBox<PageTable>>,
-    pt: BTreeMap<PageTableIndex, PageBox<PageTable>>,
+    pd: BTreeMap<(PageTableIndex, PageTableIndex), PageBox<PageTable>>,
+    pt: BTreeMap<(PageTableIndex, PageTableIndex, PageTableIndex), PageBox<PageTable>>,
+
+    /// Page tables created through the recursive mapping while the process is running.
+    recursive: Vec<PhysFrame>,
+
+    space: AddressSpace,
+
+    /// The ranges whose pages are mapped on page faults, keyed by their start addresses.
+    areas: BTreeMap<VirtAddr, Area>,
+
+    /// The frames mapped on page faults.
+    faulted: BTreeMap<Page<Size4KiB>, PhysFrame>,
 }
 impl Collection {
+    /// Creates the page tables of a binary, which runs only its own code in ring 3. The kernel
+    /// image and the heaps are accessible only by ring 0, so that SMEP and SMAP can be enabled.
+    pub(super) fn binary() -> Self {
+        Self::with_pml4(Pml4Creator::default().create_isolated())
+    }
+
+    fn with_pml4(pml4: PageBox<PageTable>) -> Self {
+        Self {
+            pml4_addr: pml4.phys_addr(),
+            pml4,
+            pdpt: BTreeMap::default(),
+            pd: BTreeMap::default(),
+            pt: BTreeMap::default(),
+            recursive: Vec::new(),
+            space: AddressSpace::default(),
+            areas: BTreeMap::new(),
+            faulted: BTreeMap::new(),
+        }
+    }
+
     pub(super) fn pml4_addr(&self) -> PhysAddr {
-        self.pml4.phys_addr()
+        self.pml4_addr
+    }
+
+    /// Allocates pages and maps them to a free region of this address space.
+    ///
+    /// This address space must be the current one as the tables are edited through the recursive
+    /// mapping.
+    pub(super) fn allocate_pages(
+        &mut self,
+        n: NumOfPages<Size4KiB>,
+    ) -> Option<(VirtAddr, PhysAddr)> {
+        self.ensure_current();
+
+        let mut tables = Recorder::default();
+        let r = allocator::allocate_pages(n, &mut self.space, &mut tables);
+        self.recursive.extend(tables.into_frames());
+        r
+    }
+
+    /// Maps the physical region to a free region of this address space with the flags `f`.
+    ///
+    /// This address space must be the current one for the same reason as `allocate_pages`.
+    pub(super) fn map_pages(&mut self, range: PhysRange, f: PageTableFlags) -> VirtAddr {
+        self.ensure_current();
+
+        let mut tables = Recorder::default();
+        let v = mem::map_pages_with(range, f, &mut tables);
+        self.recursive.extend(tables.into_frames());
+        v
+    }
+
+    /// Reserves `n` pages in the heap region of this address space. Each page is mapped to a
+    /// zeroed frame when it is accessed first.
+    pub(super) fn allocate_lazy_pages(&mut self, n: NumOfPages<Size4KiB>) -> Option<VirtAddr> {
+        let v = self.space.allocate_heap(n)?;
+        self.add_area(pages_from(v, n), Kind::Heap);
+        Some(v)
+    }
+
+    /// Unmaps and frees the pages which `allocate_lazy_pages` returned. This returns `false` if
+    /// they were not allocated so.
+    ///
+    /// This address space must be the current one as the tables are edited through the recursive
+    /// mapping.
+    pub(super) fn deallocate_lazy_pages(&mut self, v: VirtAddr, n: NumOfPages<Size4KiB>) -> bool {
+        match self.areas.get(&v) {
+            Some(a) if matches!(a.kind, Kind::Heap) && a.end == v + n.as_bytes().as_usize() => {}
+            _ => return false,
+        }
+
+        self.remove_area(v);
+        true
+    }
+
+    /// Reserves a region of this address space for `memory`. Each page is mapped with the flags
+    /// `f` when it is accessed first.
+    ///
+    /// This only edits the records of this address space, so this can be called while another CPU
+    /// runs the process.
+    pub(super) fn map_shared_memory(
+        &mut self,
+        memory: Arc<SharedMemory>,
+        f: PageTableFlags,
+    ) -> Option<VirtAddr> {
+        let n = memory.pages();
+        let v = self.space.allocate_heap(n)?;
+        self.add_area(pages_from(v, n), Kind::Shared(memory, f));
+        Some(v)
+    }
+
+    /// Unmaps the shared memory which `map_shared_memory` mapped at `v`, and returns `false` if
+    /// there is no such memory.
+    ///
+    /// This address space must be the current one.
+    pub(super) fn unmap_shared_memory(&mut self, v: VirtAddr) -> bool {
+        let shared = matches!(self.areas.get(&v), Some(a) if matches!(a.kind, Kind::Shared(..)));
+
+        if shared {
+            self.remove_area(v);
+        }
+
+        shared
+    }
+
+    /// Maps the page containing `addr` if it belongs to an area which is mapped on page faults.
+    ///
+    /// This address space must be the current one.
+    pub(super) fn handle_page_fault(&mut self, addr: VirtAddr, write: bool) -> Fault {
+        let (start, area) = match self.areas.range(..=addr).next_back() {
+            Some((s, a)) if addr < a.end => (*s, a.clone()),
+            _ => return Fault::Invalid,
+        };
+        let page = Page::containing_address(addr);
+
+        let resolved = match area.kind {
+            Kind::Heap => self.map_zeroed(page, paging::USER_DATA),
+            Kind::Stack { limit } if addr < limit => return Fault::StackOverflow,
+            Kind::Stack { .. } => !is_mapped(page) && self.map_stack_to(page, area.end),
+            Kind::CopyOnWrite(f) => write && self.copy_on_write(page, f),
+            Kind::Shared(memory, flags) => {
+                let i = usize::try_from((addr - start) / Size4KiB::SIZE).unwrap();
+                memory.with_frame(i, |frame| self.map_shared(frame, Target { page, flags }))
+            }
+        };
+
+        if resolved {
+            Fault::Resolved
+        } else {
+            Fault::Invalid
+        }
+    }
+
+    /// Returns the virtual addresses of deallocated pages to this address space.
+    pub(super) fn free_virt(&mut self, v: VirtAddr, n: NumOfPages<Size4KiB>) {
+        self.space.free(v, n);
     }
 
+    fn ensure_current(&self) {
+        let (current, _) = Cr3::read();
+        assert_eq!(
+            current.start_address(),
+            self.pml4_addr(),
+            "The address space is not the current one."
+        );
+    }
+
+    /// Maps `b` to the same address as the current address space. Only the kernel mode can access
+    /// it, so this is for the structures which the kernel uses to run the process, such as the
+    /// stack frame.
     pub(super) fn map_page_box<T: ?Sized>(&mut self, b: &PageBox<T>) {
-        for i in 0..b.bytes().as_num_of_pages::<Size4KiB>().as_usize() {
-            let off = Size4KiB::SIZE * u64::try_from(i).unwrap();
-            let v = Page::from_start_address(b.virt_addr() + off).expect("Page is not aligned.");
-            let p =
-                PhysFrame::from_start_address(b.phys_addr() + off).expect("Frame is not aligned.");
-            self.map(v, p);
+        self.map_page_box_to(b, Target::new(b.virt_addr(), paging::KERNEL_DATA));
+    }
+
+    /// Reserves a stack and a guard page below it in the stack region, and returns the bottom of
+    /// the stack, which is the end of the reserved range. The pages are mapped on page faults.
+    ///
+    /// The stack grows up to `limit` bytes, which `set_stack_limit` changes up to
+    /// `MAX_STACK_LIMIT`. A page fault below that is a stack overflow.
+    ///
+    /// A page fault on the stack maps all the unmapped pages from the faulting one to the bottom,
+    /// so that reading the stack at an address maps the stack above it, which `probe_stack`
+    /// expects.
+    pub(super) fn reserve_stack(&mut self, limit: Bytes) -> VirtAddr {
+        let n = Bytes::new(MAX_STACK_LIMIT.as_usize() + PAGE_BYTES).as_num_of_pages();
+        let v = self
+            .space
+            .allocate_stack(n)
+            .expect("The stack region is full.");
+        let bottom = v + n.as_bytes().as_usize();
+
+        let limit = stack_limit(bottom, limit);
+        self.add_area(pages_from(v, n), Kind::Stack { limit });
+        bottom
+    }
+
+    /// Sets the size up to which the stack grows. `bytes` must not be larger than
+    /// `MAX_STACK_LIMIT`.
+    pub(super) fn set_stack_limit(&mut self, bytes: Bytes) {
+        for a in self.areas.values_mut() {
+            if let Kind::Stack { limit } = &mut a.kind {
+                *limit = stack_limit(a.end, bytes);
+            }
         }
     }
 
-    fn map(&mut self, v: Page<Size4KiB>, p: PhysFrame) {
-        let Self { pml4, pdpt, pd, pt } = self;
+    /// Maps `b` so that it ends at `bottom`, which `reserve_stack` returned.
+    pub(super) fn map_stack(&mut self, b: &PageBox<[u8]>, bottom: VirtAddr) {
+        let start = bottom - b.bytes().as_usize();
+        self.map_page_box_to(b, Target::new(start, paging::USER_DATA));
+    }
 
-        let pml4_i = v.p4_index();
-        let pdpt_i = v.p3_index();
-        let dir_i = v.p2_index();
-        let table_i = v.p1_index();
+    /// Maps `b` to the pages from `to` read-only. A page is replaced with its copy mapped with the
+    /// flags of `to` when it is written, so `b` can be shared with other address spaces.
+    pub(super) fn map_copy_on_write(&mut self, b: &PageBox<[u8]>, to: Target) {
+        let read_only = Target {
+            flags: to.flags - PageTableFlags::WRITABLE,
+            ..to
+        };
+        self.map_page_box_to(b, read_only);
+
+        let pages = pages_from(to.page.start_address(), b.bytes().as_num_of_pages());
+        self.add_area(pages, Kind::CopyOnWrite(to.flags));
+    }
+
+    /// Maps `b` to the pages from `to` of this address space. The pages are never allocated by
+    /// `allocate_pages` or `reserve_stack`.
+    pub(super) fn map_page_box_to<T: ?Sized>(&mut self, b: &PageBox<T>, to: Target) {
+        let pages = b.bytes().as_num_of_pages::<Size4KiB>();
+        self.space.reserve(to.page.start_address(), pages);
+
+        for i in 0..pages.as_usize() {
+            let i = u64::try_from(i).unwrap();
+            let p = PhysFrame::from_start_address(b.phys_addr() + Size4KiB::SIZE * i)
+                .expect("Frame is not aligned.");
+            self.map(
+                p,
+                Target {
+                    page: to.page + i,
+                    flags: to.flags,
+                },
+            );
+        }
+    }
+
+    fn map(&mut self, p: PhysFrame, to: Target) {
+        let Self {
+            pml4, pdpt, pd, pt, ..
+        } = self;
+
+        let pml4_i = to.page.p4_index();
+        let pdpt_i = to.page.p3_index();
+        let dir_i = to.page.p2_index();
+        let table_i = to.page.p1_index();
 
         let p3 = pdpt
             .entry(pml4_i)
             .or_insert_with(|| Self::create(pml4, pml4_i));
 
-        let p2 = pd.entry(pdpt_i).or_insert_with(|| Self::create(p3, pdpt_i));
+        let p2 = pd
+            .entry((pml4_i, pdpt_i))
+            .or_insert_with(|| Self::create(p3, pdpt_i));
+
+        let p1 = pt
+            .entry((pml4_i, pdpt_i, dir_i))
+            .or_insert_with(|| Self::create(p2, dir_i));
+
+        p1[table_i].set_addr(p.start_address(), to.flags);
+    }
+
+    fn add_area(&mut self, pages: Range<VirtAddr>, kind: Kind) {
+        let end = pages.end;
+        self.areas.insert(pages.start, Area { end, kind });
+    }
+
+    /// Removes the area which starts at `v`, and unmaps and frees its pages.
+    ///
+    /// This address space must be the current one.
+    fn remove_area(&mut self, v: VirtAddr) {
+        self.ensure_current();
+
+        let area = self.areas.remove(&v).expect("No such area.");
+        let n = NumOfPages::new(usize::try_from((area.end - v) / Size4KiB::SIZE).unwrap());
 
-        let p1 = pt.entry(dir_i).or_insert_with(|| Self::create(p2, dir_i));
+        for p in Page::range(
+            Page::containing_address(v),
+            Page::containing_address(area.end),
+        ) {
+            if let Some(f) = self.faulted.remove(&p) {
+                Self::unmap(p);
+                FRAME_MANAGER.lock().free(f.start_address());
+            }
+        }
+
+        self.space.free(v, n);
+    }
 
-        p1[table_i].set_addr(p.start_address(), Self::flags());
+    /// Maps zeroed frames to the unmapped pages from `page` to `bottom`, and returns `false` if it
+    /// fails.
+    fn map_stack_to(&mut self, page: Page<Size4KiB>, bottom: VirtAddr) -> bool {
+        Page::range(page, Page::containing_address(bottom))
+            .all(|p| is_mapped(p) || self.map_zeroed(p, paging::USER_DATA))
+    }
+
+    /// Maps a zeroed frame to `page` with the flags `f`, and returns `false` if it fails.
+    fn map_zeroed(&mut self, page: Page<Size4KiB>, f: PageTableFlags) -> bool {
+        let frame = match FRAME_MANAGER.lock().allocate_frame() {
+            Some(f) => f,
+            None => return false,
+        };
+
+        // The kernel cannot write to a read-only page either, so the page is writable until it is
+        // zeroed.
+        let writable = Target {
+            page,
+            flags: f | PageTableFlags::WRITABLE,
+        };
+        if !self.map_faulted(frame, writable) {
+            return false;
+        }
+
+        // SAFETY: The page is mapped to the new frame, which no one else uses.
+        unsafe { write_page(page, &[0; PAGE_BYTES]) };
+
+        if !f.contains(PageTableFlags::WRITABLE) {
+            // SAFETY: The page is mapped, and only its permission is changed.
+            let r = unsafe { PML4.lock().update_flags(page, f) };
+            r.expect("The page is not mapped.").flush();
+        }
+
+        true
+    }
+
+    /// Maps `frame`, a frame of shared memory, to `to`, and returns `false` if it fails or the page
+    /// is already mapped. A zeroed frame is allocated and set to `frame` if no process has accessed
+    /// the page.
+    fn map_shared(&mut self, frame: &mut Option<PhysFrame>, to: Target) -> bool {
+        if is_mapped(to.page) {
+            return false;
+        }
+
+        if let Some(frame) = frame {
+            FRAME_MANAGER.lock().add_ref(frame.start_address());
+            return self.map_faulted(*frame, to);
+        }
+
+        if !self.map_zeroed(to.page, to.flags) {
+            return false;
+        }
+
+        // This address space and the shared memory hold the frame.
+        let new = self.faulted[&to.page];
+        FRAME_MANAGER.lock().add_ref(new.start_address());
+        *frame = Some(new);
+        true
+    }
+
+    /// Replaces the shared frame of `page` with its copy mapped with the flags `f`, and returns
+    /// `false` if it fails or the page is already copied.
+    fn copy_on_write(&mut self, page: Page<Size4KiB>, f: PageTableFlags) -> bool {
+        if self.faulted.contains_key(&page) || !is_mapped(page) {
+            return false;
+        }
+
+        let frame = match FRAME_MANAGER.lock().allocate_frame() {
+            Some(f) => f,
+            None => return false,
+        };
+
+        let content = read_page(page);
+        Self::unmap(page);
+
+        if !self.map_faulted(frame, Target { page, flags: f }) {
+            return false;
+        }
+
+        // SAFETY: The page is mapped to the new frame, which no one else uses.
+        unsafe { write_page(page, &content) };
+        true
+    }
+
+    /// Maps `frame` to `to` through the recursive mapping, and records the frame to free it with
+    /// this address space. The frame is freed if this fails.
+    fn map_faulted(&mut self, frame: PhysFrame, to: Target) -> bool {
+        let mut tables = Recorder::default();
+
+        // SAFETY: No one else uses `frame`, and the page is not mapped.
+        let r = unsafe { PML4.lock().map_to(to.page, frame, to.flags, &mut tables) };
+        self.recursive.extend(tables.into_frames());
+
+        if let Ok(flush) = r {
+            flush.flush();
+            self.faulted.insert(to.page, frame);
+            true
+        } else {
+            FRAME_MANAGER.lock().free(frame.start_address());
+            false
+        }
+    }
+
+    /// Unmaps `page` of this address space, which is the current one.
+    ///
+    /// Only the current CPU caches the page, as a process runs on one CPU and no other CPU loads
+    /// its address space. So the TLB of the other CPUs need not be flushed.
+    fn unmap(page: Page<Size4KiB>) {
+        if let Ok((_, flush)) = PML4.lock().unmap(page) {
+            flush.flush();
+        }
     }
 
     fn create(parent: &mut PageTable, i: PageTableIndex) -> PageBox<PageTable> {
@@ -58,25 +451,122 @@ impl Collection {
         t
     }
 
+    /// The entries of the last level decide the permissions, so the upper levels allow everything.
     fn map_transition(from: &mut PageTable, to: &PageBox<PageTable>, i: PageTableIndex) {
-        from[i].set_addr(to.phys_addr(), Self::flags());
-    }
-
-    fn flags() -> PageTableFlags {
-        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE
+        let f =
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+        from[i].set_addr(to.phys_addr(), f);
     }
 }
 impl Default for Collection {
     fn default() -> Self {
+        Self::with_pml4(Pml4Creator::default().create())
+    }
+}
+impl Drop for Collection {
+    fn drop(&mut self) {
+        // System calls also lock `FRAME_MANAGER` with interrupts disabled, so the lock must not be
+        // held across a process switch.
+        interrupts::without_interrupts(|| {
+            // Dropping the areas may free shared memory, which locks `FRAME_MANAGER`.
+            self.areas.clear();
+
+            let mut m = FRAME_MANAGER.lock();
+
+            for f in self.recursive.iter().chain(self.faulted.values()) {
+                m.free(f.start_address());
+            }
+        });
+    }
+}
+
+/// A range whose pages are mapped on page faults.
+#[derive(Clone, Debug)]
+struct Area {
+    end: VirtAddr,
+    kind: Kind,
+}
+
+#[derive(Clone, Debug)]
+enum Kind {
+    /// Pages are mapped to zeroed frames.
+    Heap,
+
+    /// Same as `Heap`, but a page fault also maps the unmapped pages between the faulting one and
+    /// the bottom of the stack. The pages below `limit` are the guard region, which is never
+    /// mapped.
+    Stack { limit: VirtAddr },
+
+    /// Pages are shared read-only, and a written page is replaced with its copy mapped with these
+    /// flags.
+    CopyOnWrite(PageTableFlags),
+
+    /// Pages are mapped to the frames of the shared memory with these flags.
+    Shared(Arc<SharedMemory>, PageTableFlags),
+}
+
+/// The result of handling a page fault.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Fault {
+    /// The page is mapped, and the access can be retried.
+    Resolved,
+
+    /// The access is in the guard region below the stack.
+    StackOverflow,
+
+    /// The page is not allocated, or the access is not allowed.
+    Invalid,
+}
+
+/// A page to which a frame is mapped, and the flags of the mapping. For a range, the page is the
+/// first one.
+#[derive(Copy, Clone, Debug)]
+pub(super) struct Target {
+    page: Page<Size4KiB>,
+    flags: PageTableFlags,
+}
+impl Target {
+    /// `start` must be aligned to a page.
+    pub(super) fn new(start: VirtAddr, flags: PageTableFlags) -> Self {
         Self {
-            pml4: Pml4Creator::default().create(),
-            pdpt: BTreeMap::default(),
-            pd: BTreeMap::default(),
-            pt: BTreeMap::default(),
+            page: Page::from_start_address(start).expect("Page is not aligned."),
+            flags,
         }
     }
 }
 
+/// Returns the range of the `n` pages from `start`.
+fn pages_from(start: VirtAddr, n: NumOfPages<Size4KiB>) -> Range<VirtAddr> {
+    start..start + n.as_bytes().as_usize()
+}
+
+/// Returns the lowest address of a stack which ends at `bottom` and grows up to `bytes` bytes.
+fn stack_limit(bottom: VirtAddr, bytes: Bytes) -> VirtAddr {
+    (bottom - bytes.as_usize()).align_down(Size4KiB::SIZE)
+}
+
+fn read_page(page: Page<Size4KiB>) -> Vec<u8> {
+    // SAFETY: The page is mapped.
+    let content = unsafe { slice::from_raw_parts(page.start_address().as_ptr(), PAGE_BYTES) };
+    paging::with_user_access(|| content.to_vec())
+}
+
+/// SAFETY: This function is unsafe because `page` must be mapped, writable and unused by anyone
+/// else, and `content` must be `PAGE_BYTES` bytes.
+unsafe fn write_page(page: Page<Size4KiB>, content: &[u8]) {
+    paging::with_user_access(|| {
+        ptr::copy_nonoverlapping(
+            content.as_ptr(),
+            page.start_address().as_mut_ptr(),
+            PAGE_BYTES,
+        )
+    });
+}
+
+fn is_mapped(page: Page<Size4KiB>) -> bool {
+    paging::effective_flags(page.start_address()).is_some()
+}
+
 #[derive(Default)]
 struct Pml4Creator {
     pml4: PageBox<PageTable>,
@@ -88,14 +578,33 @@ impl Pml4Creator {
         self.pml4
     }
 
+    /// Same as `create`, but the kernel area is not user-accessible.
+    fn create_isolated(mut self) -> PageBox<PageTable> {
+        self.enable_recursive_paging();
+        self.map_kernel_area();
+        self.isolate_kernel_area();
+        self.pml4
+    }
+
+    /// Only the kernel mode edits the page tables, so the recursive entry is not user-accessible.
     fn enable_recursive_paging(&mut self) {
         let a = self.pml4.phys_addr();
-        let f =
-            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
-        self.pml4[511].set_addr(a, f);
+        let f = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        self.pml4[usize::from(RECUR_PML4_INDEX)].set_addr(a, f);
     }
 
     fn map_kernel_area(&mut self) {
-        self.pml4[510] = PML4.lock().level_4_table()[510].clone();
+        let mut pml4 = PML4.lock();
+        let kernel = pml4.level_4_table();
+
+        self.pml4[510] = kernel[510].clone();
+        self.pml4[511] = kernel[511].clone();
+    }
+
+    fn isolate_kernel_area(&mut self) {
+        for e in self.pml4.iter_mut().skip(510) {
+            let f = e.flags() - PageTableFlags::USER_ACCESSIBLE;
+            e.set_flags(f);
+        }
     }
 }
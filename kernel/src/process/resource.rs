@@ -0,0 +1,90 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use super::shared_memory;
+use crate::mem::{self, allocator::phys::FRAME_MANAGER};
+use alloc::collections::BTreeMap;
+use os_units::{Bytes, NumOfPages};
+use x86_64::{instructions::interrupts, structures::paging::Size4KiB, PhysAddr, VirtAddr};
+
+/// Memory which a process acquired through system calls. The frames are freed when this is
+/// dropped, that is, when the process exits.
+///
+/// The mappings themselves are not undone as the whole address space is discarded with its page
+/// tables, but the virtual addresses of `MapPages` are returned as they are shared by all address
+/// spaces.
+#[derive(Debug, Default)]
+pub struct Resources {
+    /// Pages allocated by `AllocatePages`.
+    pages: BTreeMap<VirtAddr, (PhysAddr, NumOfPages<Size4KiB>)>,
+    /// Regions mapped by `MapPages`. The frames belong to devices, so they are not freed.
+    mappings: BTreeMap<VirtAddr, Bytes>,
+    /// The IDs and the sizes of the shared memory which this process created and has not
+    /// destroyed.
+    shared_memory: BTreeMap<u64, NumOfPages<Size4KiB>>,
+}
+impl Resources {
+    /// Records that the `n` frames from `phys` are mapped from `virt`.
+    pub fn add_pages(&mut self, virt: VirtAddr, (phys, n): (PhysAddr, NumOfPages<Size4KiB>)) {
+        self.pages.insert(virt, (phys, n));
+    }
+
+    /// Returns `false` if the pages were not allocated by this process.
+    pub fn remove_pages(&mut self, virt: VirtAddr, n: NumOfPages<Size4KiB>) -> bool {
+        match self.pages.get(&virt) {
+            Some((_, allocated)) if *allocated == n => self.pages.remove(&virt).is_some(),
+            _ => false,
+        }
+    }
+
+    pub fn add_mapping(&mut self, virt: VirtAddr, bytes: Bytes) {
+        self.mappings.insert(virt, bytes);
+    }
+
+    /// Returns `false` if the region was not mapped by this process.
+    pub fn remove_mapping(&mut self, virt: VirtAddr, bytes: Bytes) -> bool {
+        match self.mappings.get(&virt) {
+            Some(mapped) if *mapped == bytes => self.mappings.remove(&virt).is_some(),
+            _ => false,
+        }
+    }
+
+    pub(super) fn add_shared_memory(&mut self, id: u64, n: NumOfPages<Size4KiB>) {
+        self.shared_memory.insert(id, n);
+    }
+
+    pub(super) fn owns_shared_memory(&self, id: u64) -> bool {
+        self.shared_memory.contains_key(&id)
+    }
+
+    /// Returns `false` if this process did not create the shared memory or has destroyed it.
+    pub(super) fn remove_shared_memory(&mut self, id: u64) -> bool {
+        self.shared_memory.remove(&id).is_some()
+    }
+
+    /// Returns the total size of the shared memory which this process owns.
+    pub(super) fn shared_memory_pages(&self) -> NumOfPages<Size4KiB> {
+        NumOfPages::new(self.shared_memory.values().map(|n| n.as_usize()).sum())
+    }
+}
+impl Drop for Resources {
+    fn drop(&mut self) {
+        // System calls also lock `FRAME_MANAGER` with interrupts disabled, so the lock must not be
+        // held across a process switch.
+        interrupts::without_interrupts(|| {
+            let mut m = FRAME_MANAGER.lock();
+
+            for (phys, _) in self.pages.values() {
+                m.free(*phys);
+            }
+            drop(m);
+
+            for (virt, bytes) in &self.mappings {
+                mem::forget_mapping(*virt, *bytes);
+            }
+
+            for id in self.shared_memory.keys() {
+                shared_memory::destroy(*id);
+            }
+        });
+    }
+}
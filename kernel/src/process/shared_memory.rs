@@ -0,0 +1,67 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::mem::allocator::phys::FRAME_MANAGER;
+use alloc::{collections::BTreeMap, sync::Arc, vec, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+use os_units::NumOfPages;
+use spinning_top::Spinlock;
+use x86_64::structures::paging::{PhysFrame, Size4KiB};
+
+/// The shared memory which can still be mapped, keyed by the IDs.
+static OBJECTS: Spinlock<BTreeMap<u64, Arc<SharedMemory>>> = Spinlock::new(BTreeMap::new());
+
+/// Memory which is mapped to the address spaces of several processes.
+///
+/// Each frame is allocated when a process accesses the page first. This object and each mapping of
+/// a frame hold a reference to it in `FRAME_MANAGER`, so a frame is freed after this is dropped
+/// and every address space unmaps it.
+#[derive(Debug)]
+pub(super) struct SharedMemory {
+    frames: Spinlock<Vec<Option<PhysFrame>>>,
+}
+impl SharedMemory {
+    pub(super) fn pages(&self) -> NumOfPages<Size4KiB> {
+        NumOfPages::new(self.frames.lock().len())
+    }
+
+    /// Calls `f` with the frame of the `i`th page, which is `None` if no process has accessed the
+    /// page. The other processes wait until `f` returns, so they see the frame which `f` sets.
+    pub(super) fn with_frame<T>(&self, i: usize, f: impl FnOnce(&mut Option<PhysFrame>) -> T) -> T {
+        f(&mut self.frames.lock()[i])
+    }
+}
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        let mut m = FRAME_MANAGER.lock();
+
+        for f in self.frames.get_mut().iter().flatten() {
+            m.free(f.start_address());
+        }
+    }
+}
+
+/// Creates shared memory of `n` pages, and returns its ID.
+pub(super) fn create(n: NumOfPages<Size4KiB>) -> u64 {
+    static ID: AtomicU64 = AtomicU64::new(0);
+
+    let id = ID.fetch_add(1, Ordering::Relaxed);
+    let m = SharedMemory {
+        frames: Spinlock::new(vec![None; n.as_usize()]),
+    };
+
+    OBJECTS.lock().insert(id, Arc::new(m));
+    id
+}
+
+pub(super) fn get(id: u64) -> Option<Arc<SharedMemory>> {
+    OBJECTS.lock().get(&id).cloned()
+}
+
+/// Makes the shared memory `id` unable to be mapped. The memory is freed after all the mappings
+/// are unmapped.
+pub(super) fn destroy(id: u64) {
+    // Drop the memory after releasing the lock, so that freeing the frames does not keep the other
+    // processes waiting.
+    let m = OBJECTS.lock().remove(&id);
+    drop(m);
+}
@@ -1,8 +1,8 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use core::convert::TryInto;
+use core::{convert::TryInto, mem};
 
-use crate::gdt::GDT;
+use crate::gdt;
 use rflags::RFlags;
 use x86_64::{
     registers::rflags,
@@ -10,6 +10,10 @@ use x86_64::{
     VirtAddr,
 };
 
+/// The offset of the saved code segment selector from the top of a stack frame. The handlers of the
+/// process switch read its RPL to tell whether they return to ring 3.
+pub const CODE_SEGMENT_OFFSET: usize = mem::size_of::<GeneralRegisters>() + 8;
+
 #[repr(C)]
 #[derive(Debug)]
 pub struct StackFrame {
@@ -25,20 +29,37 @@ impl StackFrame {
         Self::new(f, stack_pointer, &Selectors::user())
     }
 
+    /// Creates a stack frame which starts executing a user program from `entry`.
+    pub fn binary(entry: VirtAddr, stack_pointer: VirtAddr) -> Self {
+        Self {
+            regs: GeneralRegisters::default(),
+            interrupt: Self::interrupt_frame(entry, stack_pointer, &Selectors::user()),
+        }
+    }
+
     fn new(f: fn(), stack_pointer: VirtAddr, segs: &Selectors) -> Self {
-        let cpu_flags = (rflags::read() | RFlags::INTERRUPT_FLAG).bits();
         let instruction_pointer =
             VirtAddr::new((super::manager::loader as usize).try_into().unwrap());
 
         Self {
             regs: GeneralRegisters::new(f),
-            interrupt: InterruptStackFrameValue {
-                instruction_pointer,
-                code_segment: segs.code.0.into(),
-                cpu_flags,
-                stack_pointer,
-                stack_segment: segs.data.0.into(),
-            },
+            interrupt: Self::interrupt_frame(instruction_pointer, stack_pointer, segs),
+        }
+    }
+
+    fn interrupt_frame(
+        instruction_pointer: VirtAddr,
+        stack_pointer: VirtAddr,
+        segs: &Selectors,
+    ) -> InterruptStackFrameValue {
+        let cpu_flags = (rflags::read() | RFlags::INTERRUPT_FLAG).bits();
+
+        InterruptStackFrameValue {
+            instruction_pointer,
+            code_segment: segs.code.0.into(),
+            cpu_flags,
+            stack_pointer,
+            stack_segment: segs.data.0.into(),
         }
     }
 }
@@ -49,11 +70,13 @@ struct Selectors {
 }
 impl Selectors {
     fn kernel() -> Self {
-        Self::new(GDT.kernel_code, GDT.kernel_data)
+        let gdt = gdt::current();
+        Self::new(gdt.kernel_code, gdt.kernel_data)
     }
 
     fn user() -> Self {
-        Self::new(GDT.user_code, GDT.user_data)
+        let gdt = gdt::current();
+        Self::new(gdt.user_code, gdt.user_data)
     }
 
     fn new(code: SegmentSelector, user: SegmentSelector) -> Self {
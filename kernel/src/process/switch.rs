@@ -2,37 +2,118 @@
 
 use super::{
     collections::{self, woken_pid},
-    Process,
+    manager::{self, Message},
+    Id, Process,
 };
-use crate::{tests, tss::TSS};
+use crate::{
+    mem::paging,
+    smp::{self, percpu, MAX_CPUS},
+    tests, tss,
+};
+use spinning_top::Spinlock;
 use x86_64::{registers::control::Cr3, structures::paging::PhysFrame, VirtAddr};
 
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_OWNER: Spinlock<Option<Id>> = Spinlock::new(None);
+
+/// The process whose FPU and SIMD registers are in each CPU. This is not always the process which
+/// ran last, as a blocked or exited process is removed from the run queue before the switch.
+static FPU_OWNERS: [Spinlock<Option<Id>>; MAX_CPUS] = [NO_OWNER; MAX_CPUS];
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_EXIT: Spinlock<Option<(Id, i32)>> = Spinlock::new(None);
+
+/// The process which exited on each CPU and its status. The manager is told about it after the
+/// CPU leaves its page tables, as the manager on another CPU may free them at once.
+static PENDING_EXITS: [Spinlock<Option<(Id, i32)>>; MAX_CPUS] = [NO_EXIT; MAX_CPUS];
+
 pub fn switch() -> VirtAddr {
     if cfg!(feature = "qemu_test") {
         tests::process::count_switch();
     }
 
+    save_fpu_registers();
     change_current_process();
     switch_pml4();
+    send_pending_exit();
+    restore_fpu_registers();
     register_current_stack_frame_with_tss();
+    register_syscall_stack();
+    load_io_ports();
     current_stack_frame_top_addr()
 }
 
+/// Switches to another process only if the current process has used up its time slice or a
+/// process of a higher priority is ready.
+pub fn preempt() -> VirtAddr {
+    if woken_pid::tick() {
+        switch()
+    } else {
+        current_stack_frame_top_addr()
+    }
+}
+
+/// Makes the next `switch` on this CPU tell the manager that the process `id` exited.
+pub(super) fn set_pending_exit(id: Id, status: i32) {
+    *PENDING_EXITS[smp::id()].lock() = Some((id, status));
+}
+
 fn change_current_process() {
     woken_pid::change_active_pid();
 }
 
+/// SMEP and SMAP are enabled only in the address space of a binary. They are disabled before the
+/// switch, as ring 0 fetches the next instruction from the kernel image, which is user-accessible
+/// in the other address spaces.
 fn switch_pml4() {
     let (_, f) = Cr3::read();
-    let a = collections::process::handle_running(|p| p.pml4_addr);
+    let (a, binary) = collections::process::handle_running(|p| (p.pml4_addr, p.binary.is_some()));
     let a = PhysFrame::from_start_address(a).expect("PML4 is not aligned properly");
 
+    paging::disable_protections();
+
     // SAFETY: The PML4 frame is correct one and flags are unchanged.
     unsafe { Cr3::write(a, f) }
+
+    if binary {
+        paging::enable_protections();
+    }
+}
+
+fn send_pending_exit() {
+    if let Some((id, status)) = PENDING_EXITS[smp::id()].lock().take() {
+        manager::send_message(Message::Exit(id, status)).expect("The manager is busy.");
+    }
+}
+
+/// Saves the registers while the address space of their owner is still loaded.
+fn save_fpu_registers() {
+    if let Some(id) = *FPU_OWNERS[smp::id()].lock() {
+        // The owner may have exited.
+        collections::process::try_handle_mut(id, |p| p.fpu.save());
+    }
+}
+
+fn restore_fpu_registers() {
+    collections::process::handle_running(|p| p.fpu.restore());
+    *FPU_OWNERS[smp::id()].lock() = Some(collections::process::handle_running(Process::id));
 }
 
 fn register_current_stack_frame_with_tss() {
-    TSS.lock().interrupt_stack_table[0] = current_stack_frame_bottom_addr();
+    tss::current().lock().interrupt_stack_table[0] = current_stack_frame_bottom_addr();
+}
+
+fn register_syscall_stack() {
+    let top = collections::process::handle_running(Process::syscall_stack_bottom_addr);
+    percpu::current().set_syscall_stack_top(top);
+}
+
+fn load_io_ports() {
+    collections::process::handle_running(|p| {
+        tss::current()
+            .lock()
+            .load_io_ports(p.capabilities.io_port_ranges())
+    });
 }
 
 fn current_stack_frame_top_addr() -> VirtAddr {
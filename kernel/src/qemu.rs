@@ -1,11 +1,17 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use core::ops::RangeInclusive;
 use qemu_exit::QEMUExit;
 
+const EXIT_PORT: u16 = 0xf4;
+
+/// The ports which `exit_success` and `exit_failure` write a 32-bit value to.
+pub const EXIT_PORTS: RangeInclusive<u16> = EXIT_PORT..=EXIT_PORT + 3;
+
 pub fn exit_success() -> ! {
-    qemu_exit::X86::new(0xf4, 33).exit_success();
+    qemu_exit::X86::new(EXIT_PORT, 33).exit_success();
 }
 
 pub fn exit_failure() -> ! {
-    qemu_exit::X86::new(0xf4, 33).exit_failure();
+    qemu_exit::X86::new(EXIT_PORT, 33).exit_failure();
 }
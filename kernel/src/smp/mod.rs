@@ -0,0 +1,206 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// Application processors (APs) are started with the INIT-SIPI-SIPI sequence. Each AP starts in the
+// real mode at the trampoline, enters the long mode with the page tables of the BSP, and then
+// initializes its own GDT, TSS, and local APIC in `ap_main`.
+//
+// A CPU is identified by its index, which is the position of its local APIC ID in the list made
+// from the MADT. The BSP is always 0. The index is kept in the per-CPU block.
+
+pub mod percpu;
+pub mod tlb;
+mod trampoline;
+
+use crate::{
+    fpu, gdt,
+    interrupt::{apic, idt, timer},
+    mem::{self, allocator},
+    syscall, tss,
+};
+use acpi::{platform::ProcessorState, AcpiTables};
+use alloc::vec::Vec;
+use conquer_once::spin::OnceCell;
+use core::{
+    arch::x86_64::__cpuid,
+    convert::TryFrom,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+use trampoline::Trampoline;
+use x86_64::{instructions::interrupts, VirtAddr};
+
+/// The maximum number of CPUs. The rest are left halted.
+pub const MAX_CPUS: usize = 8;
+
+/// The local APIC IDs of the CPUs, indexed by the CPU index.
+static LOCAL_APIC_IDS: OnceCell<Vec<u8>> = OnceCell::uninit();
+
+/// The number of CPUs which finished the initialization, including the BSP.
+static ONLINE: AtomicUsize = AtomicUsize::new(1);
+
+/// APs wait for this before enabling interrupts as the scheduler needs their idle processes.
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+const AP_STACK_SIZE: usize = 4096 * 4;
+
+/// The stacks which APs use until their first process switch.
+static mut AP_STACKS: [[u8; AP_STACK_SIZE]; MAX_CPUS - 1] = [[0; AP_STACK_SIZE]; MAX_CPUS - 1];
+
+/// How long the BSP waits for an AP to start.
+const AP_TIMEOUT_MS: u32 = 100;
+
+/// The vector of the IPI which makes a CPU reschedule.
+pub const KICK_VECTOR: u8 = 0xf0;
+
+/// Sets up the per-CPU block of the BSP. This must be called before anything else.
+pub fn init_bsp() {
+    percpu::init(0, local_apic_id());
+}
+
+/// Starts the APs listed in the MADT. This must be called in the kernel privilege after the timer
+/// of the BSP is initialized.
+pub fn init(table: &AcpiTables<allocator::acpi::Mapper>) {
+    let ids = local_apic_ids(table);
+    let aps = ids.len() - 1;
+
+    LOCAL_APIC_IDS
+        .try_init_once(|| ids)
+        .expect("`LOCAL_APIC_IDS` is initialized more than once.");
+
+    if aps == 0 {
+        return;
+    }
+
+    let trampoline = if let Some(t) = Trampoline::new() {
+        t
+    } else {
+        warn!("No memory below 1 MiB for the AP trampoline. Only the BSP runs.");
+        return;
+    };
+
+    for cpu in 1..=aps {
+        boot_ap(&trampoline, table, cpu);
+    }
+
+    info!("{} CPUs are online.", count());
+}
+
+/// Lets the APs start running processes. This must be called after the idle processes are added.
+pub fn start() {
+    STARTED.store(true, Ordering::Release);
+}
+
+/// Returns the index of the current CPU.
+pub fn id() -> usize {
+    percpu::current().id()
+}
+
+/// Returns the number of the CPUs which run processes.
+pub fn count() -> usize {
+    ONLINE.load(Ordering::Acquire)
+}
+
+/// Makes `cpu` switch to the highest-priority process in its run queue. This is for a process
+/// added to the queue of an idle CPU, which otherwise waits for the next tick.
+pub fn kick(cpu: usize) {
+    apic::local::send_ipi(percpu::get(cpu).local_apic_id(), KICK_VECTOR);
+}
+
+/// Returns the local APIC ID of the current CPU with `cpuid`. Use `percpu::current` after the
+/// per-CPU block is set up.
+pub fn local_apic_id() -> u8 {
+    // SAFETY: Every x86_64 CPU supports the leaf 1.
+    let ebx = unsafe { __cpuid(1) }.ebx;
+
+    u8::try_from(ebx >> 24).unwrap()
+}
+
+fn local_apic_ids(table: &AcpiTables<allocator::acpi::Mapper>) -> Vec<u8> {
+    let bsp = local_apic_id();
+    let info = table.platform_info().unwrap().processor_info;
+
+    let aps = info.iter().flat_map(|i| {
+        i.application_processors
+            .iter()
+            .filter(|p| p.state != ProcessorState::Disabled)
+            .map(|p| p.local_apic_id)
+    });
+
+    let ids: Vec<_> = core::iter::once(bsp).chain(aps).collect();
+    if ids.len() > MAX_CPUS {
+        warn!("Only {} of {} CPUs are used.", MAX_CPUS, ids.len());
+    }
+
+    ids.into_iter().take(MAX_CPUS).collect()
+}
+
+fn boot_ap(trampoline: &Trampoline, table: &AcpiTables<allocator::acpi::Mapper>, cpu: usize) {
+    let local_apic_id = LOCAL_APIC_IDS.get().unwrap()[cpu];
+    trampoline.prepare(ap_stack_bottom(cpu), ap_main);
+
+    apic::local::send_init(local_apic_id);
+    timer::wait_milliseconds(table, 10);
+
+    for _ in 0..2 {
+        apic::local::send_startup(local_apic_id, trampoline.start_page());
+        timer::wait_milliseconds(table, 1);
+    }
+
+    for _ in 0..AP_TIMEOUT_MS {
+        if count() > cpu {
+            return;
+        }
+
+        timer::wait_milliseconds(table, 1);
+    }
+
+    // The trampoline cannot be reused for the next AP as this AP may still run it.
+    panic!(
+        "The CPU {} (local APIC ID {}) did not start.",
+        cpu, local_apic_id
+    );
+}
+
+fn ap_stack_bottom(cpu: usize) -> VirtAddr {
+    // SAFETY: Only the AP `cpu` uses this stack.
+    let stack = unsafe { &AP_STACKS[cpu - 1] };
+
+    VirtAddr::from_ptr(stack) + AP_STACK_SIZE
+}
+
+/// The trampoline jumps to this function in the long mode with the page tables of the BSP.
+extern "C" fn ap_main() -> ! {
+    percpu::init(ap_index(), local_apic_id());
+    tss::init();
+    gdt::init();
+    idt::init();
+    mem::paging::init();
+    fpu::init();
+    syscall::init();
+    apic::local::enable();
+    timer::init_ap();
+
+    ONLINE.fetch_add(1, Ordering::Release);
+
+    // Interrupts are still disabled, so serve TLB shootdowns here.
+    while !STARTED.load(Ordering::Acquire) {
+        tlb::serve();
+        core::hint::spin_loop();
+    }
+
+    // This context is discarded on the first timer interrupt, as is that of the BSP.
+    interrupts::enable();
+    loop {
+        x86_64::instructions::hlt();
+    }
+}
+
+fn ap_index() -> usize {
+    let local_apic_id = local_apic_id();
+
+    LOCAL_APIC_IDS
+        .get()
+        .unwrap()
+        .iter()
+        .position(|id| *id == local_apic_id)
+        .expect("Unknown local APIC ID.")
+}
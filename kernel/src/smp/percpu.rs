@@ -0,0 +1,143 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// Each CPU has a block of its own data, and the GS base of the CPU points to it in ring 0. Ring 3
+// can load GS, so it runs with a GS base of its own, and `swapgs` exchanges the two bases on each
+// entry from and exit to ring 3. The block is reachable in ring 0 even before the handlers of the
+// process switch leave the stack frame of the process.
+
+use super::MAX_CPUS;
+use crate::process::RunQueue;
+use conquer_once::spin::Lazy;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use spinning_top::Spinlock;
+use x86_64::{
+    instructions::segmentation,
+    registers::model_specific::{GsBase, KernelGsBase},
+    structures::{gdt::SegmentSelector, idt::InterruptStackFrame},
+    PrivilegeLevel, VirtAddr,
+};
+
+static BLOCKS: [PerCpu; MAX_CPUS] = [BLOCK_INIT; MAX_CPUS];
+
+#[allow(clippy::declare_interior_mutable_const)]
+const BLOCK_INIT: PerCpu = PerCpu {
+    interrupt_stack_top: AtomicU64::new(0),
+    this: AtomicUsize::new(0),
+    syscall_stack_top: AtomicU64::new(0),
+    id: AtomicUsize::new(0),
+    local_apic_id: AtomicU8::new(0),
+    run_queue: Lazy::new(|| Spinlock::new(RunQueue::default())),
+    tlb_flush_pending: AtomicBool::new(false),
+};
+
+/// The data of a CPU.
+#[repr(C)]
+pub struct PerCpu {
+    /// The handlers of the process switch read this with `gs:0`, so this must be the first field.
+    interrupt_stack_top: AtomicU64,
+
+    /// The address of this block, read with `gs:8`, which is faster than reading the GS base MSR.
+    this: AtomicUsize,
+
+    /// The entry of the system calls reads this with `gs:16` to leave the stack of a caller in
+    /// ring 3.
+    syscall_stack_top: AtomicU64,
+
+    id: AtomicUsize,
+    local_apic_id: AtomicU8,
+
+    /// The processes which run on this CPU, including the current one.
+    run_queue: Lazy<Spinlock<RunQueue>>,
+
+    /// Set while another CPU waits for this CPU to flush the TLB.
+    tlb_flush_pending: AtomicBool,
+}
+impl PerCpu {
+    /// Returns the index of the CPU.
+    pub fn id(&self) -> usize {
+        self.id.load(Ordering::Relaxed)
+    }
+
+    pub fn local_apic_id(&self) -> u8 {
+        self.local_apic_id.load(Ordering::Relaxed)
+    }
+
+    pub fn set_interrupt_stack_top(&self, top: VirtAddr) {
+        self.interrupt_stack_top
+            .store(top.as_u64(), Ordering::Relaxed);
+    }
+
+    /// Sets the stack on which the system calls of the current process are handled.
+    pub fn set_syscall_stack_top(&self, top: VirtAddr) {
+        self.syscall_stack_top
+            .store(top.as_u64(), Ordering::Relaxed);
+    }
+
+    pub(crate) fn run_queue(&self) -> &Spinlock<RunQueue> {
+        &self.run_queue
+    }
+
+    pub(super) fn tlb_flush_pending(&self) -> &AtomicBool {
+        &self.tlb_flush_pending
+    }
+}
+
+/// Makes the GS base of the current CPU point to the block of `cpu`. This must be called before
+/// anything else on the CPU, as `smp::id` reads the block.
+pub fn init(cpu: usize, local_apic_id: u8) {
+    let b = get(cpu);
+    b.id.store(cpu, Ordering::Relaxed);
+    b.local_apic_id.store(local_apic_id, Ordering::Relaxed);
+    b.this.store(b as *const PerCpu as usize, Ordering::Relaxed);
+
+    // SAFETY: The null selector is always valid for GS, and the block is never moved.
+    unsafe {
+        segmentation::load_gs(SegmentSelector::new(0, PrivilegeLevel::Ring0));
+    }
+    GsBase::write(VirtAddr::from_ptr(b));
+    KernelGsBase::write(VirtAddr::zero());
+}
+
+/// Returns the block of the current CPU. This must be called in ring 0.
+pub fn current() -> &'static PerCpu {
+    let b: usize;
+
+    // SAFETY: `init` sets the GS base to the block of this CPU, whose `this` is at the offset 8.
+    unsafe {
+        asm!("mov {}, gs:[8]", out(reg) b, options(nostack, readonly, preserves_flags));
+        &*(b as *const PerCpu)
+    }
+}
+
+/// Returns the block of `cpu`.
+pub fn get(cpu: usize) -> &'static PerCpu {
+    &BLOCKS[cpu]
+}
+
+/// Makes the GS base point to the per-CPU block while a handler of an interrupt from ring 3 runs,
+/// and restores the GS base of ring 3 when dropped.
+///
+/// The handlers of the process switch do this in their assembly instead, as they return to
+/// another process.
+pub struct KernelGs {
+    swapped: bool,
+}
+impl KernelGs {
+    pub fn enter(f: &InterruptStackFrame) -> Self {
+        let swapped = f.code_segment & 3 == 3;
+        if swapped {
+            // SAFETY: The interrupted code ran in ring 3, so the GS base is that of ring 3.
+            unsafe { segmentation::swap_gs() }
+        }
+
+        Self { swapped }
+    }
+}
+impl Drop for KernelGs {
+    fn drop(&mut self) {
+        if self.swapped {
+            // SAFETY: The handler returns to ring 3.
+            unsafe { segmentation::swap_gs() }
+        }
+    }
+}
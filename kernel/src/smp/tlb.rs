@@ -0,0 +1,100 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// `invlpg` flushes only the TLB of the CPU which runs it. After unmapping pages, the CPU sends an
+// IPI to the other CPUs and waits until all of them flush the pages, so that no CPU accesses the
+// frames through a stale entry after they are freed.
+//
+// Only one shootdown runs at a time. A CPU waiting for its turn serves the current one, as it may
+// wait with interrupts disabled.
+
+use super::percpu;
+use crate::interrupt::apic;
+use core::{
+    convert::TryFrom,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+use os_units::NumOfPages;
+use spinning_top::{Spinlock, SpinlockGuard};
+use x86_64::{
+    instructions::tlb,
+    structures::paging::{Page, PageSize, Size4KiB},
+    VirtAddr,
+};
+
+/// The vector of the IPI which requests a shootdown.
+pub const VECTOR: u8 = 0xf1;
+
+static LOCK: Spinlock<()> = Spinlock::new(());
+
+/// The first page and the number of the pages of the current shootdown.
+static START: AtomicU64 = AtomicU64::new(0);
+static NUM_OF_PAGES: AtomicU64 = AtomicU64::new(0);
+
+/// The number of the CPUs which have not flushed the pages yet.
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+
+/// Flushes the `n` pages from `start` out of the TLBs of all CPUs. This must not be called with
+/// a lock which other CPUs may wait for with interrupts disabled.
+pub fn shootdown(start: Page<Size4KiB>, n: NumOfPages<Size4KiB>) {
+    let n = u64::try_from(n.as_usize()).unwrap();
+    flush_local(start.start_address(), n);
+
+    let others: usize = super::count() - 1;
+    if others == 0 {
+        return;
+    }
+
+    let _lock = lock();
+
+    START.store(start.start_address().as_u64(), Ordering::Relaxed);
+    NUM_OF_PAGES.store(n, Ordering::Relaxed);
+    PENDING.store(others, Ordering::Relaxed);
+
+    let me = super::id();
+    for cpu in (0..super::count()).filter(|c| *c != me) {
+        let b = percpu::get(cpu);
+        b.tlb_flush_pending().store(true, Ordering::Release);
+        apic::local::send_ipi(b.local_apic_id(), VECTOR);
+    }
+
+    while PENDING.load(Ordering::Acquire) > 0 {
+        core::hint::spin_loop();
+    }
+}
+
+/// Called on the IPI of `VECTOR`.
+pub fn handle_ipi() {
+    serve();
+    apic::local::end_of_interrupt();
+}
+
+/// Flushes the pages of the current shootdown if it waits for this CPU. The IPI may arrive after
+/// this, and then the handler finds nothing to do.
+pub fn serve() {
+    if percpu::current()
+        .tlb_flush_pending()
+        .swap(false, Ordering::Acquire)
+    {
+        let start = VirtAddr::new(START.load(Ordering::Relaxed));
+        flush_local(start, NUM_OF_PAGES.load(Ordering::Relaxed));
+
+        PENDING.fetch_sub(1, Ordering::Release);
+    }
+}
+
+fn lock() -> SpinlockGuard<'static, ()> {
+    loop {
+        if let Some(l) = LOCK.try_lock() {
+            return l;
+        }
+
+        serve();
+        core::hint::spin_loop();
+    }
+}
+
+fn flush_local(start: VirtAddr, n: u64) {
+    for i in 0..n {
+        tlb::flush(start + Size4KiB::SIZE * i);
+    }
+}
@@ -0,0 +1,281 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// An AP starts in the real mode at the physical address `SIPI vector * 0x1000`, so the trampoline
+// is copied to a page below 1 MiB. The page is identity-mapped so that the code continues after
+// the paging is enabled.
+//
+// The real mode can load only 32 bits to CR3, so the trampoline first loads a copy of the PML4 of
+// the BSP placed in the next page, and then the original one in the long mode.
+
+use super::tlb;
+use crate::mem::{allocator::phys::FRAME_MANAGER, paging::pml4::PML4};
+use common::constant::RECUR_PML4_ADDR;
+use core::{
+    convert::{TryFrom, TryInto},
+    mem, ptr,
+};
+use os_units::NumOfPages;
+use x86_64::{
+    registers::{
+        control::Cr3,
+        model_specific::{Efer, EferFlags},
+    },
+    structures::{
+        gdt::DescriptorFlags,
+        paging::{Mapper, Page, PageSize, PageTable, PageTableFlags, PhysFrame, Size4KiB},
+    },
+    PhysAddr, VirtAddr,
+};
+
+global_asm!(
+    "
+    .intel_syntax noprefix
+    .set AP_GDTR_OFFSET, ap_gdtr - ap_trampoline
+    .set AP_LONG_MODE_OFFSET, ap_long_mode_ptr - ap_trampoline
+    .set AP_BOOT_CR3_OFFSET, ap_boot_cr3 - ap_trampoline
+    .set AP_EFER_OFFSET, ap_efer - ap_trampoline
+
+    .code16
+    .global ap_trampoline
+ap_trampoline:
+    cli
+    cld
+    mov ax, cs
+    mov ds, ax
+
+    lgdt [AP_GDTR_OFFSET]
+
+    # Enable PAE.
+    mov eax, cr4
+    or eax, 1 << 5
+    mov cr4, eax
+
+    mov eax, [AP_BOOT_CR3_OFFSET]
+    mov cr3, eax
+
+    mov ecx, 0xc0000080
+    mov eax, [AP_EFER_OFFSET]
+    xor edx, edx
+    wrmsr
+
+    # Enable the protected mode and the paging at once to enter the long mode.
+    mov eax, cr0
+    or eax, 0x80000001
+    mov cr0, eax
+
+    jmp fword ptr ds:[AP_LONG_MODE_OFFSET]
+
+    .code64
+    .global ap_long_mode
+ap_long_mode:
+    mov ax, 0x10
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+    xor ax, ax
+    mov fs, ax
+    mov gs, ax
+
+    mov rax, [rip + ap_cr3]
+    mov cr3, rax
+    mov rsp, [rip + ap_stack]
+    call [rip + ap_entry]
+    ud2
+
+    # The BSP writes `Parameters` here.
+    .balign 8
+    .global ap_trampoline_parameters
+ap_trampoline_parameters:
+ap_gdt:
+    .quad 0, 0, 0
+ap_gdtr:
+    .word 0
+    .long 0
+ap_long_mode_ptr:
+    .long 0
+    .word 0
+ap_boot_cr3:
+    .long 0
+ap_efer:
+    .long 0
+    .balign 8
+ap_cr3:
+    .quad 0
+ap_stack:
+    .quad 0
+ap_entry:
+    .quad 0
+    .att_syntax
+"
+);
+
+extern "C" {
+    fn ap_trampoline();
+    fn ap_long_mode();
+    fn ap_trampoline_parameters();
+}
+
+/// The selector of the code segment in the GDT of `Parameters`.
+const CODE_SELECTOR: u16 = 0x08;
+
+/// The trampoline copied to low memory. It is unmapped and freed when this is dropped.
+pub(super) struct Trampoline {
+    start: PhysAddr,
+}
+impl Trampoline {
+    /// The trampoline and the copy of the PML4.
+    const PAGES: usize = 2;
+
+    /// Real-mode code can jump only below 1 `MiB`.
+    const END: PhysAddr = PhysAddr::new_truncate(0x10_0000);
+
+    /// Returns `None` if there is no free memory below 1 `MiB`.
+    pub(super) fn new() -> Option<Self> {
+        let start = FRAME_MANAGER
+            .lock()
+            .alloc_below(NumOfPages::new(Self::PAGES), Self::END)?;
+
+        let t = Self { start };
+        t.map();
+        t.copy_code();
+        t.copy_pml4();
+
+        Some(t)
+    }
+
+    /// Sets the stack and the function which the next AP uses.
+    pub(super) fn prepare(&self, stack: VirtAddr, entry: extern "C" fn() -> !) {
+        let code = self.start.as_u64();
+
+        let p = Parameters {
+            gdt: [
+                0,
+                DescriptorFlags::KERNEL_CODE64.bits(),
+                DescriptorFlags::KERNEL_DATA.bits(),
+            ],
+            gdtr_limit: (mem::size_of::<[u64; 3]>() - 1).try_into().unwrap(),
+            gdtr_base: (code + Self::parameters_offset()).try_into().unwrap(),
+            long_mode_addr: (code + offset_of(ap_long_mode)).try_into().unwrap(),
+            long_mode_selector: CODE_SELECTOR,
+            boot_cr3: self.pml4_copy().as_u64().try_into().unwrap(),
+            efer: Self::efer(),
+            _padding: 0,
+            cr3: Cr3::read().0.start_address().as_u64(),
+            stack: stack.as_u64(),
+            entry: (entry as usize).try_into().unwrap(),
+        };
+
+        // SAFETY: The parameters are in the trampoline page, which is identity-mapped, and no AP
+        // runs the trampoline now.
+        unsafe { ptr::write_volatile((code + Self::parameters_offset()) as *mut Parameters, p) }
+    }
+
+    /// Returns the page number which the SIPI takes.
+    pub(super) fn start_page(&self) -> u8 {
+        (self.start.as_u64() / Size4KiB::SIZE).try_into().unwrap()
+    }
+
+    fn map(&self) {
+        let mut pml4 = PML4.lock();
+        let mut frames = FRAME_MANAGER.lock();
+
+        for i in 0..Self::PAGES {
+            let a = self.start + Size4KiB::SIZE * u64::try_from(i).unwrap();
+            let page = Page::<Size4KiB>::containing_address(VirtAddr::new(a.as_u64()));
+            let frame = PhysFrame::containing_address(a);
+            let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+            // SAFETY: The frame is allocated for the trampoline, and the lower half of the
+            // address space before processes run is used only by accessors.
+            unsafe {
+                pml4.map_to(page, frame, flags, &mut *frames)
+                    .expect("Failed to map the trampoline.")
+                    .flush()
+            }
+        }
+    }
+
+    fn copy_code(&self) {
+        let len = Self::parameters_offset() + mem::size_of::<Parameters>() as u64;
+        assert!(len <= Size4KiB::SIZE, "The trampoline is too large.");
+
+        // SAFETY: The destination is identity-mapped and large enough.
+        unsafe {
+            ptr::copy_nonoverlapping(
+                ap_trampoline as *const u8,
+                self.start.as_u64() as *mut u8,
+                len.try_into().unwrap(),
+            )
+        }
+    }
+
+    /// Copies the current PML4, which now maps the trampoline.
+    fn copy_pml4(&self) {
+        // SAFETY: The recursive entry maps the current PML4 there, and the copy is
+        // identity-mapped.
+        unsafe {
+            let pml4 = &*RECUR_PML4_ADDR.as_ptr::<PageTable>();
+            let copy = self.pml4_copy().as_u64() as *mut PageTable;
+
+            ptr::copy_nonoverlapping(pml4, copy, 1);
+        }
+    }
+
+    fn pml4_copy(&self) -> PhysAddr {
+        self.start + Size4KiB::SIZE
+    }
+
+    fn parameters_offset() -> u64 {
+        offset_of(ap_trampoline_parameters)
+    }
+
+    /// Returns EFER of the BSP so that the AP understands the same page tables.
+    fn efer() -> u32 {
+        let mut e = Efer::read();
+        e.remove(EferFlags::LONG_MODE_ACTIVE);
+
+        e.bits().try_into().unwrap()
+    }
+}
+impl Drop for Trampoline {
+    fn drop(&mut self) {
+        let start = Page::<Size4KiB>::containing_address(VirtAddr::new(self.start.as_u64()));
+
+        for page in Page::range(start, start + u64::try_from(Self::PAGES).unwrap()) {
+            let (_, flush) = PML4
+                .lock()
+                .unmap(page)
+                .expect("Failed to unmap the trampoline.");
+            flush.ignore();
+        }
+
+        // The APs ran the trampoline.
+        tlb::shootdown(start, NumOfPages::new(Self::PAGES));
+
+        // The page tables made for the trampoline are kept as the PML4 still points to them.
+        FRAME_MANAGER.lock().free(self.start);
+    }
+}
+
+/// The layout must match `ap_trampoline_parameters` in the assembly.
+#[repr(C, packed)]
+struct Parameters {
+    gdt: [u64; 3],
+    gdtr_limit: u16,
+    gdtr_base: u32,
+    long_mode_addr: u32,
+    long_mode_selector: u16,
+    boot_cr3: u32,
+    efer: u32,
+    _padding: u32,
+    cr3: u64,
+    stack: u64,
+    entry: u64,
+}
+
+/// Returns the offset of `label` from the start of the trampoline.
+fn offset_of(label: unsafe extern "C" fn()) -> u64 {
+    (label as usize - ap_trampoline as usize)
+        .try_into()
+        .unwrap()
+}
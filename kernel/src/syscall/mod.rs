@@ -0,0 +1,555 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+mod user_ptr;
+
+pub use user_ptr::{UserPtr, UserSlice};
+
+use crate::{
+    interrupt::{self, timer},
+    mem::{
+        allocator,
+        paging::{self, pml4::PML4},
+    },
+    process::{self, Capabilities},
+};
+use alloc::{string::String, vec::Vec};
+use core::convert::{TryFrom, TryInto};
+use num_traits::FromPrimitive;
+use os_units::{Bytes, NumOfPages};
+use syscalls::{Error, Result, StrRef};
+use x86_64::{
+    instructions::{
+        self, interrupts,
+        port::{PortReadOnly, PortWriteOnly},
+    },
+    registers::{
+        model_specific::{Efer, EferFlags, LStar, SFMask},
+        rflags::RFlags,
+    },
+    structures::paging::{PageSize, Size4KiB, Translate},
+    PhysAddr, VirtAddr,
+};
+
+/// The GS base MSR, which the entry reads to tell the ring of the caller.
+const GS_BASE_MSR: u32 = 0xc000_0101;
+
+pub fn init() {
+    enable();
+    register();
+}
+
+fn enable() {
+    // SAFETY: This operation is safe as this does not touch any unsafe things.
+    unsafe { Efer::update(|e| *e |= EferFlags::SYSTEM_CALL_EXTENSIONS) }
+}
+
+fn register() {
+    let addr = save_rip_and_rflags as usize;
+
+    LStar::write(VirtAddr::new(addr.try_into().unwrap()));
+    SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::ALIGNMENT_CHECK);
+}
+
+/// `syscall` instruction calls this function.
+///
+/// RAX: system call index
+/// RDI: 1st argument
+/// RSI: 2nd argument
+/// RDX: 3rd argument
+///
+/// The callers clobber R8 to R10, so this uses them before saving anything. `SFMASK` clears the
+/// interrupt flag, so no interrupt comes before the GS base and the stack are switched, and the
+/// alignment check flag, so that ring 3 cannot lift SMAP.
+///
+/// A caller in ring 3 may pass any stack pointer, and its stack is user-accessible, so the kernel
+/// handles the call on the stack of the current process for the system calls instead.
+#[naked]
+#[allow(clippy::too_many_lines)]
+extern "C" fn save_rip_and_rflags() -> u64 {
+    unsafe {
+        asm!(
+            "
+        # A caller in ring 3 has a GS base below 4 GiB, as ring 3 loads it only from a selector.
+        # The GS base of the kernel mode points to the per-CPU block in the higher half.
+        mov r8, rax
+        mov r9, rdx
+        mov r10, rcx
+        mov ecx, {}
+        rdmsr
+        sar edx, 31 # Non-zero if the caller is in ring 0
+        mov rax, r8
+        mov rcx, r10
+        xchg rdx, r9
+        mov r8, rsp
+        test r9, r9
+        jnz 2f
+        swapgs
+        mov rsp, gs:[16]
+    2:
+        push r8     # Save rsp
+        push rcx    # Save rip
+        push r11    # Save rflags
+        push r9     # Save whether the caller is in ring 0
+
+        # A caller in ring 0 keeps its stack. Map it in advance, as a stack may be mapped on page
+        # faults, which must not happen while a lock is held. The stack for the system calls is
+        # larger than this.
+        .global syscall_stack_probe
+    syscall_stack_probe:
+        cmp byte ptr [rsp - {}], 0
+
+        call prepare_arguments
+
+        # A system call may enable interrupts, which must not come while the stack and the GS base
+        # are those of ring 3.
+        cli
+        pop r9
+        pop r11     # Restore rflags
+        pop rcx     # Restore rip
+        pop rsp     # Restore rsp
+        test r9, r9
+        jnz 1f
+        swapgs
+        sti
+        sysretq
+
+        # `sysretq` always returns to ring 3, so return to a caller in ring 0 with a jump.
+    1:
+        push r11
+        popfq
+        jmp rcx
+        ",
+            const GS_BASE_MSR,
+            const paging::STACK_PROBE_BYTES,
+            options(noreturn)
+        );
+    }
+}
+
+/// Returns `true` if `rip` is the instruction which probes the stack on the entry of the system
+/// calls. A page fault there is caused by the caller, whose stack is too small.
+pub fn is_stack_probe(rip: VirtAddr) -> bool {
+    extern "C" {
+        fn syscall_stack_probe();
+    }
+
+    rip.as_u64() == syscall_stack_probe as usize as u64
+}
+
+/// SAFETY: This function is unsafe because invalid values in registers may break memory safety.
+#[no_mangle]
+unsafe fn prepare_arguments() {
+    let syscall_index: u64;
+    let a1: u64;
+    let a2: u64;
+    let a3: u64;
+
+    asm!("", out("rax") syscall_index, out("rdi") a1, out("rsi") a2,out("rdx") a3);
+    asm!("", in("rax") select_proper_syscall(syscall_index, [a1, a2, a3]))
+}
+
+/// SAFETY: This function is unsafe because invalid arguments may break memory safety.
+unsafe fn select_proper_syscall(idx: u64, args: [u64; 3]) -> u64 {
+    let r = match FromPrimitive::from_u64(idx) {
+        Some(ty) => handle_syscall(ty, args),
+        None => Err(Error::InvalidSyscall),
+    };
+
+    Error::encode(r)
+}
+
+/// SAFETY: This function is unsafe because invalid arguments may break memory safety.
+#[allow(clippy::too_many_lines)]
+unsafe fn handle_syscall(ty: syscalls::Ty, [a1, a2, a3]: [u64; 3]) -> Result<u64> {
+    match ty {
+        syscalls::Ty::Inb => sys_inb(arg(a1)?).map(u64::from),
+        syscalls::Ty::Outb => sys_outb(arg(a1)?, arg(a2)?),
+        syscalls::Ty::Inl => sys_inl(arg(a1)?).map(u64::from),
+        syscalls::Ty::Outl => sys_outl(arg(a1)?, arg(a2)?),
+        syscalls::Ty::Halt => sys_halt(),
+        syscalls::Ty::DisableInterrupt => sys_disable_interrupt(),
+        syscalls::Ty::EnableInterrupt => sys_enable_interrupt(),
+        syscalls::Ty::EnableInterruptAndHalt => Ok(sys_enable_interrupt_and_halt()),
+        syscalls::Ty::AllocatePages => {
+            sys_allocate_pages(NumOfPages::new(arg(a1)?)).map(VirtAddr::as_u64)
+        }
+        syscalls::Ty::DeallocatePages => {
+            sys_deallocate_pages(virt_addr(a1)?, NumOfPages::new(arg(a2)?))
+        }
+        syscalls::Ty::MapPages => {
+            sys_map_pages(phys_addr(a1)?, Bytes::new(arg(a2)?)).map(VirtAddr::as_u64)
+        }
+        syscalls::Ty::UnmapPages => sys_unmap_pages(virt_addr(a1)?, Bytes::new(arg(a2)?)),
+        syscalls::Ty::GetPid => Ok(sys_getpid().try_into().unwrap()),
+        syscalls::Ty::Exit => sys_exit(as_i32(a1)),
+        syscalls::Ty::TranslateAddress => {
+            sys_translate_address(virt_addr(a1)?).map(PhysAddr::as_u64)
+        }
+        syscalls::Ty::Write => sys_write(as_i32(a1), &UserSlice::new(a2, arg(a3)?)),
+        syscalls::Ty::NotifyExists => Ok(sys_notify_exists().into()),
+        syscalls::Ty::NotifyOnInterrupt => sys_notify_on_interrupt(arg(a1)?, as_i32(a2)),
+        syscalls::Ty::Send => sys_send(as_i32(a1), &UserPtr::new(a2)),
+        syscalls::Ty::Receive => sys_receive(as_i32(a1), &UserPtr::new(a2)),
+        syscalls::Ty::Call => sys_call(as_i32(a1), &UserPtr::new(a2)),
+        syscalls::Ty::Reply => sys_reply(as_i32(a1), &UserPtr::new(a2)),
+        syscalls::Ty::SetPriority => sys_set_priority(as_i32(a1), a2),
+        syscalls::Ty::SetQuantum => sys_set_quantum(arg(a1)?),
+        syscalls::Ty::Sleep => Ok(sys_sleep(a1)),
+        syscalls::Ty::SleepUntil => Ok(sys_sleep_until(a1)),
+        syscalls::Ty::GetUptime => Ok(sys_get_uptime()),
+        syscalls::Ty::Spawn => sys_spawn(a1, arg(a2)?, arg(a3)?).map(|pid| pid.try_into().unwrap()),
+        syscalls::Ty::Wait => sys_wait(as_i32(a1)).map(status_as_u64),
+        syscalls::Ty::AllocateLazyPages => {
+            sys_allocate_lazy_pages(NumOfPages::new(arg(a1)?)).map(VirtAddr::as_u64)
+        }
+        syscalls::Ty::SetStackLimit => sys_set_stack_limit(Bytes::new(arg(a1)?)),
+        syscalls::Ty::CreateSharedMemory => sys_create_shared_memory(Bytes::new(arg(a1)?)),
+        syscalls::Ty::MapSharedMemory => {
+            sys_map_shared_memory(a1, as_i32(a2), a3).map(VirtAddr::as_u64)
+        }
+        syscalls::Ty::UnmapSharedMemory => sys_unmap_shared_memory(virt_addr(a1)?),
+        syscalls::Ty::DestroySharedMemory => sys_destroy_shared_memory(a1),
+    }
+}
+
+/// SAFETY: This function is unsafe because reading from I/O port may have side effects which
+/// violate memory safety.
+unsafe fn sys_inb(port: u16) -> Result<u8> {
+    ensure_io_ports_allowed(port, 1)?;
+
+    let mut p = PortReadOnly::new(port);
+    Ok(p.read())
+}
+
+/// SAFETY: This function is unsafe because writing to I/O port may have side effects which violate
+/// memory safety.
+unsafe fn sys_outb(port: u16, v: u8) -> Result<u64> {
+    ensure_io_ports_allowed(port, 1)?;
+
+    let mut p = PortWriteOnly::new(port);
+    p.write(v);
+    Ok(0)
+}
+
+/// SAFETY: This function is unsafe because reading from I/O port may have side effects which
+/// violate memory safety.
+unsafe fn sys_inl(port: u16) -> Result<u32> {
+    ensure_io_ports_allowed(port, 4)?;
+
+    let mut p = PortReadOnly::new(port);
+    Ok(p.read())
+}
+
+/// SAFETY: This function is unsafe because writing to I/O port may have side effects which violate
+/// memory safety.
+unsafe fn sys_outl(port: u16, v: u32) -> Result<u64> {
+    ensure_io_ports_allowed(port, 4)?;
+
+    let mut p = PortWriteOnly::new(port);
+    p.write(v);
+    Ok(0)
+}
+
+fn sys_halt() -> Result<u64> {
+    ensure_interrupts_allowed()?;
+
+    instructions::hlt();
+    Ok(0)
+}
+
+fn sys_disable_interrupt() -> Result<u64> {
+    ensure_interrupts_allowed()?;
+
+    interrupts::disable();
+    Ok(0)
+}
+
+fn sys_enable_interrupt() -> Result<u64> {
+    ensure_interrupts_allowed()?;
+
+    interrupts::enable();
+    Ok(0)
+}
+
+fn sys_enable_interrupt_and_halt() -> u64 {
+    if process::manager::try_getpid().is_some() {
+        // Sleep instead of halting the CPU so that other processes can run until the next tick.
+        process::manager::sleep(1);
+    } else {
+        // The context before the first switch is discarded on the first timer interrupt.
+        interrupts::enable_and_hlt();
+    }
+
+    0
+}
+
+fn sys_allocate_pages(num_of_pages: NumOfPages<Size4KiB>) -> Result<VirtAddr> {
+    if num_of_pages.as_usize() == 0 {
+        return Err(Error::InvalidArgument);
+    }
+
+    let (virt, phys) = process::manager::allocate_pages(num_of_pages).ok_or(Error::OutOfMemory)?;
+
+    process::manager::with_resources(|r| r.add_pages(virt, (phys, num_of_pages)));
+
+    Ok(virt)
+}
+
+fn sys_allocate_lazy_pages(num_of_pages: NumOfPages<Size4KiB>) -> Result<VirtAddr> {
+    if num_of_pages.as_usize() == 0 {
+        return Err(Error::InvalidArgument);
+    }
+
+    process::manager::allocate_lazy_pages(num_of_pages).ok_or(Error::OutOfMemory)
+}
+
+fn sys_set_stack_limit(bytes: Bytes) -> Result<u64> {
+    process::manager::set_stack_limit(bytes).map(|_| 0)
+}
+
+fn sys_create_shared_memory(bytes: Bytes) -> Result<u64> {
+    if bytes.as_usize() == 0 || bytes > syscalls::MAX_SHARED_MEMORY {
+        return Err(Error::InvalidArgument);
+    }
+
+    process::manager::create_shared_memory(bytes.as_num_of_pages())
+}
+
+fn sys_map_shared_memory(id: u64, pid: i32, access: u64) -> Result<VirtAddr> {
+    let access = FromPrimitive::from_u64(access).ok_or(Error::InvalidArgument)?;
+    process::manager::map_shared_memory(id, pid, access)
+}
+
+fn sys_unmap_shared_memory(start: VirtAddr) -> Result<u64> {
+    process::manager::unmap_shared_memory(start).map(|_| 0)
+}
+
+fn sys_destroy_shared_memory(id: u64) -> Result<u64> {
+    process::manager::destroy_shared_memory(id).map(|_| 0)
+}
+
+fn sys_deallocate_pages(virt: VirtAddr, pages: NumOfPages<Size4KiB>) -> Result<u64> {
+    if !virt.is_aligned(Size4KiB::SIZE) {
+        return Err(Error::InvalidArgument);
+    }
+
+    if process::manager::deallocate_lazy_pages(virt, pages) {
+        return Ok(0);
+    }
+
+    ensure_mapped(virt, pages.as_bytes())?;
+
+    // Processes must not free pages which they did not allocate, such as their stacks.
+    if process::manager::with_resources(|r| r.remove_pages(virt, pages)) == Some(false) {
+        return Err(Error::InvalidArgument);
+    }
+
+    allocator::deallocate_pages(virt, pages);
+    process::manager::free_virt(virt, pages);
+    Ok(0)
+}
+
+fn sys_map_pages(start: PhysAddr, bytes: Bytes) -> Result<VirtAddr> {
+    let end = start
+        .as_u64()
+        .checked_add(bytes.as_usize().try_into().unwrap())
+        .ok_or(Error::InvalidArgument)?;
+    let end = phys_addr(end)?;
+
+    if !process::manager::is_allowed(|c| c.allows_mmio(&(start..end))) {
+        return Err(Error::PermissionDenied);
+    }
+
+    let virt = process::manager::map_pages(start, bytes);
+
+    process::manager::with_resources(|r| r.add_mapping(virt, bytes));
+
+    Ok(virt)
+}
+
+fn sys_unmap_pages(start: VirtAddr, bytes: Bytes) -> Result<u64> {
+    ensure_mapped(start, bytes)?;
+
+    if process::manager::with_resources(|r| r.remove_mapping(start, bytes)) == Some(false) {
+        return Err(Error::InvalidArgument);
+    }
+
+    crate::mem::unmap_pages(start, bytes);
+    Ok(0)
+}
+
+fn sys_getpid() -> i32 {
+    process::manager::getpid()
+}
+
+fn sys_exit(status: i32) -> ! {
+    process::manager::exit(status);
+}
+
+fn sys_translate_address(v: VirtAddr) -> Result<PhysAddr> {
+    PML4.lock().translate_addr(v).ok_or(Error::NotMapped)
+}
+
+fn sys_write(fildes: i32, buf: &UserSlice<u8>) -> Result<u64> {
+    if fildes != 1 {
+        return Err(Error::BadFileDescriptor);
+    }
+
+    let s = buf.read_to_vec()?;
+    let s = core::str::from_utf8(&s).map_err(|_| Error::InvalidArgument)?;
+
+    // TODO: rewrite with `write` macro.
+    info!("{}", s);
+
+    Ok(s.len().try_into().unwrap())
+}
+
+fn sys_notify_exists() -> bool {
+    process::manager::notify_exists()
+}
+
+fn sys_notify_on_interrupt(vec: u8, pid: i32) -> Result<u64> {
+    ensure_interrupts_allowed()?;
+
+    interrupt::handler::notify_on_interrupt(vec.into(), pid);
+    Ok(0)
+}
+
+fn sys_send(to: i32, m: &UserPtr<syscalls::Message>) -> Result<u64> {
+    process::manager::send(to, m.read()?).map(|_| 0)
+}
+
+fn sys_receive(from: i32, m: &UserPtr<syscalls::Message>) -> Result<u64> {
+    m.ensure_writable()?;
+
+    let r = process::manager::receive(from);
+    m.write(r).map(|_| 0)
+}
+
+fn sys_call(to: i32, m: &UserPtr<syscalls::Message>) -> Result<u64> {
+    m.ensure_writable()?;
+
+    let r = process::manager::call(to, m.read()?)?;
+    m.write(r).map(|_| 0)
+}
+
+fn sys_reply(to: i32, m: &UserPtr<syscalls::Message>) -> Result<u64> {
+    process::manager::reply(to, m.read()?).map(|_| 0)
+}
+
+fn sys_set_priority(pid: i32, priority: u64) -> Result<u64> {
+    let priority = FromPrimitive::from_u64(priority).ok_or(Error::InvalidArgument)?;
+    process::manager::set_priority(pid, priority).map(|_| 0)
+}
+
+fn sys_set_quantum(milliseconds: u32) -> Result<u64> {
+    if !process::manager::is_allowed(Capabilities::allows_scheduling) {
+        return Err(Error::PermissionDenied);
+    }
+
+    process::manager::set_quantum(milliseconds).map(|_| 0)
+}
+
+fn sys_sleep(milliseconds: u64) -> u64 {
+    process::manager::sleep(timer::milliseconds_to_ticks(milliseconds));
+    0
+}
+
+fn sys_sleep_until(uptime_milliseconds: u64) -> u64 {
+    process::manager::sleep_until(timer::milliseconds_to_ticks(uptime_milliseconds));
+    0
+}
+
+fn sys_get_uptime() -> u64 {
+    timer::uptime_milliseconds()
+}
+
+/// `strings` points to `num_args` arguments including the path of the program, followed by
+/// `num_env` elements of the environment.
+fn sys_spawn(strings: u64, num_args: usize, num_env: usize) -> Result<i32> {
+    if num_args == 0 || num_args > syscalls::MAX_ARGS + 1 || num_env > syscalls::MAX_ENV {
+        return Err(Error::InvalidArgument);
+    }
+
+    let mut args = UserSlice::<StrRef>::new(strings, num_args + num_env)
+        .read_to_vec()?
+        .into_iter()
+        .map(read_string)
+        .collect::<Result<Vec<_>>>()?;
+
+    let env = args.split_off(num_args);
+    let path = args.remove(0);
+    process::manager::spawn_child(&path, args, env)
+}
+
+fn sys_wait(pid: i32) -> Result<i32> {
+    process::manager::wait(pid)
+}
+
+/// Returns `Error::PermissionDenied` if the current process cannot access the `bytes` ports from
+/// `port`.
+fn ensure_io_ports_allowed(port: u16, bytes: u16) -> Result<()> {
+    let last = port.checked_add(bytes - 1).ok_or(Error::InvalidArgument)?;
+
+    if process::manager::is_allowed(|c| c.allows_io_ports(&(port..=last))) {
+        Ok(())
+    } else {
+        Err(Error::PermissionDenied)
+    }
+}
+
+fn ensure_interrupts_allowed() -> Result<()> {
+    if process::manager::is_allowed(Capabilities::allows_interrupts) {
+        Ok(())
+    } else {
+        Err(Error::PermissionDenied)
+    }
+}
+
+/// Returns `Error::NotMapped` if any page in the range is not mapped.
+fn ensure_mapped(start: VirtAddr, bytes: Bytes) -> Result<()> {
+    let end = start
+        .as_u64()
+        .checked_add(bytes.as_usize().try_into().unwrap())
+        .ok_or(Error::InvalidArgument)?;
+    let pml4 = PML4.lock();
+
+    let mut page = start.align_down(Size4KiB::SIZE).as_u64();
+    while page < end {
+        pml4.translate_addr(virt_addr(page)?)
+            .ok_or(Error::NotMapped)?;
+        page += Size4KiB::SIZE;
+    }
+
+    Ok(())
+}
+
+fn arg<T: TryFrom<u64>>(a: u64) -> Result<T> {
+    a.try_into().map_err(|_| Error::InvalidArgument)
+}
+
+fn virt_addr(a: u64) -> Result<VirtAddr> {
+    VirtAddr::try_new(a).map_err(|_| Error::InvalidArgument)
+}
+
+fn phys_addr(a: u64) -> Result<PhysAddr> {
+    PhysAddr::try_new(a).map_err(|_| Error::InvalidArgument)
+}
+
+fn read_string(s: StrRef) -> Result<String> {
+    let bytes = UserSlice::new(s.ptr, arg(s.len)?).read_to_vec()?;
+    String::from_utf8(bytes).map_err(|_| Error::InvalidArgument)
+}
+
+/// Zero-extends the bit pattern of `status` so that a negative status is not taken as an error.
+// Use `as` to keep the bit pattern.
+#[allow(clippy::cast_sign_loss)]
+fn status_as_u64(status: i32) -> u64 {
+    u64::from(status as u32)
+}
+
+// Use `as` to keep the bit pattern of negative values such as PIDs.
+#[allow(clippy::cast_possible_truncation)]
+fn as_i32(a: u64) -> i32 {
+    a as i32
+}
@@ -0,0 +1,140 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::{
+    mem::paging,
+    process::{self, Fault},
+};
+use alloc::vec::Vec;
+use core::{convert::TryInto, marker::PhantomData, mem, ptr};
+use syscalls::{Error, Result};
+use x86_64::{
+    structures::paging::{PageSize, PageTableFlags, Size4KiB},
+    VirtAddr,
+};
+
+/// The end of the lower half. Addresses above this belong to the kernel.
+const USER_END: u64 = 0x8000_0000_0000;
+
+/// A pointer to a `T` passed from a user process.
+///
+/// The pointer is verified against the page tables of the current process every time it is
+/// accessed, so an invalid pointer results in `Error::BadAddress` instead of a page fault.
+pub struct UserPtr<T> {
+    addr: u64,
+    _marker: PhantomData<T>,
+}
+impl<T: Copy> UserPtr<T> {
+    pub fn new(addr: u64) -> Self {
+        Self {
+            addr,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Copies the value from the user memory.
+    pub fn read(&self) -> Result<T> {
+        let p = validate(self.addr, mem::size_of::<T>(), Access::Read)?;
+
+        // SAFETY: `validate` ensures that the range is mapped and accessible.
+        Ok(unsafe { paging::with_user_access(|| ptr::read_unaligned(p.as_ptr())) })
+    }
+
+    /// Copies `v` to the user memory.
+    pub fn write(&self, v: T) -> Result<()> {
+        let p = validate(self.addr, mem::size_of::<T>(), Access::Write)?;
+
+        // SAFETY: `validate` ensures that the range is mapped and writable.
+        unsafe { paging::with_user_access(|| ptr::write_unaligned(p.as_mut_ptr(), v)) };
+        Ok(())
+    }
+
+    /// Returns `Error::BadAddress` if the value cannot be written. This is used to fail before
+    /// doing something which cannot be undone, such as taking a message from the inbox.
+    pub fn ensure_writable(&self) -> Result<()> {
+        validate(self.addr, mem::size_of::<T>(), Access::Write).map(|_| ())
+    }
+}
+
+/// A slice of `T`s passed from a user process.
+pub struct UserSlice<T> {
+    addr: u64,
+    len: usize,
+    _marker: PhantomData<T>,
+}
+impl<T: Copy> UserSlice<T> {
+    pub fn new(addr: u64, len: usize) -> Self {
+        Self {
+            addr,
+            len,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Copies the elements from the user memory.
+    pub fn read_to_vec(&self) -> Result<Vec<T>> {
+        let p = validate(self.addr, self.bytes()?, Access::Read)?;
+        let bytes = self.bytes()?;
+        let mut v: Vec<T> = Vec::with_capacity(self.len);
+
+        // SAFETY: `validate` ensures that the range is mapped and accessible, and `v` has the
+        // capacity for `self.len` elements.
+        unsafe {
+            paging::with_user_access(|| {
+                ptr::copy_nonoverlapping(p.as_ptr::<u8>(), v.as_mut_ptr().cast(), bytes)
+            });
+            v.set_len(self.len);
+        }
+
+        Ok(v)
+    }
+
+    fn bytes(&self) -> Result<usize> {
+        self.len
+            .checked_mul(mem::size_of::<T>())
+            .ok_or(Error::BadAddress)
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Access {
+    Read,
+    Write,
+}
+
+/// Ensures that the range `addr..addr + bytes` is in the lower half and that every page of it is
+/// mapped as accessible from ring 3, mapping the pages which are allocated lazily.
+fn validate(addr: u64, bytes: usize, access: Access) -> Result<VirtAddr> {
+    let end = addr
+        .checked_add(bytes.try_into().unwrap())
+        .ok_or(Error::BadAddress)?;
+
+    if addr == 0 || end > USER_END {
+        return Err(Error::BadAddress);
+    }
+
+    let mut required = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+    if let Access::Write = access {
+        required |= PageTableFlags::WRITABLE;
+    }
+
+    let mut page = VirtAddr::new(addr).align_down(Size4KiB::SIZE).as_u64();
+    while page < end {
+        if !is_accessible(VirtAddr::new(page), required, access) {
+            return Err(Error::BadAddress);
+        }
+
+        page += Size4KiB::SIZE;
+    }
+
+    Ok(VirtAddr::new(addr))
+}
+
+/// Returns `true` if the page at `page` has the `required` flags. A page which is mapped lazily or
+/// copied on write is mapped here, as the kernel must not cause a page fault while accessing it.
+fn is_accessible(page: VirtAddr, required: PageTableFlags, access: Access) -> bool {
+    let has_flags = || paging::effective_flags(page).map_or(false, |f| f.contains(required));
+    let write = matches!(access, Access::Write);
+
+    has_flags()
+        || (process::manager::handle_page_fault(page, write) == Fault::Resolved && has_flags())
+}
@@ -0,0 +1,43 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use os_units::Bytes;
+use syscalls::{Error, Priority};
+use x86_64::PhysAddr;
+
+pub(super) fn main() {
+    test_port_io_without_capability();
+    test_map_pages_without_capability();
+    test_interrupt_control_without_capability();
+    test_scheduling_without_capability();
+}
+
+fn test_port_io_without_capability() {
+    // SAFETY: The kernel must refuse to read the port.
+    let r = unsafe { syscalls::inb(0x60) };
+    assert_eq!(r, Err(Error::PermissionDenied));
+
+    // SAFETY: The kernel must refuse to write to the port.
+    let r = unsafe { syscalls::outl(0xcf8, 0) };
+    assert_eq!(r, Err(Error::PermissionDenied));
+}
+
+fn test_map_pages_without_capability() {
+    let r = syscalls::map_pages(PhysAddr::zero(), Bytes::new(1));
+    assert_eq!(r, Err(Error::PermissionDenied));
+}
+
+fn test_interrupt_control_without_capability() {
+    assert_eq!(syscalls::disable_interrupt(), Err(Error::PermissionDenied));
+    assert_eq!(
+        syscalls::notify_on_interrupt(0x20, syscalls::getpid()),
+        Err(Error::PermissionDenied)
+    );
+}
+
+fn test_scheduling_without_capability() {
+    assert_eq!(syscalls::set_quantum(20), Err(Error::PermissionDenied));
+    assert_eq!(
+        syscalls::set_priority(super::ipc::server_pid(), Priority::Low),
+        Err(Error::PermissionDenied)
+    );
+}
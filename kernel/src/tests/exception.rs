@@ -0,0 +1,56 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use core::sync::atomic::{AtomicI32, Ordering};
+use os_units::Bytes;
+use syscalls::{Error, Message};
+
+static FAULTING_PID: AtomicI32 = AtomicI32::new(-1);
+static OVERFLOWING_PID: AtomicI32 = AtomicI32::new(-1);
+
+/// Reads the null address. The kernel must kill this process instead of stopping the system.
+pub fn page_fault() {
+    FAULTING_PID.store(syscalls::getpid(), Ordering::Relaxed);
+
+    // SAFETY: This is not safe at all, but the page fault must kill only this process.
+    unsafe { asm!("mov {}, [0]", out(reg) _) }
+
+    unreachable!("Read the null address without a page fault.");
+}
+
+/// Uses more stack than its limit. The kernel must kill this process instead of mapping the guard
+/// region.
+pub fn stack_overflow() {
+    OVERFLOWING_PID.store(syscalls::getpid(), Ordering::Relaxed);
+
+    syscalls::set_stack_limit(Bytes::new(0x1_0000)).expect("Failed to set the stack limit.");
+    super::mem::use_stack(0x20);
+    super::mem::use_stack(0x80);
+
+    unreachable!("Used more stack than the limit without a stack overflow.");
+}
+
+pub(super) fn main() {
+    test_page_fault_kills_process();
+    test_stack_overflow_kills_process();
+}
+
+fn test_page_fault_kills_process() {
+    wait_until_killed(&FAULTING_PID);
+}
+
+fn test_stack_overflow_kills_process() {
+    wait_until_killed(&OVERFLOWING_PID);
+}
+
+fn wait_until_killed(pid: &AtomicI32) {
+    let pid = loop {
+        let pid = pid.load(Ordering::Relaxed);
+        if pid >= 0 {
+            break pid;
+        }
+    };
+
+    while syscalls::send(pid, &Message::default()) != Err(Error::NoSuchProcess) {
+        syscalls::sleep_ms(1);
+    }
+}
@@ -0,0 +1,41 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+// The kernel is built without SSE, so the tests access the registers only with inline assembly.
+
+const PATTERN: u64 = 0x0123_4567_89ab_cdef;
+const CLOBBER_PATTERN: u64 = 0xdead_beef_dead_beef;
+
+/// Keeps overwriting `xmm0` so that the tests notice if the register is shared between processes.
+pub fn clobber() {
+    loop {
+        write_xmm0(CLOBBER_PATTERN);
+        syscalls::sleep_ms(1);
+    }
+}
+
+pub(super) fn main() {
+    test_xmm_registers_are_preserved();
+}
+
+fn test_xmm_registers_are_preserved() {
+    write_xmm0(PATTERN);
+
+    for _ in 0..10 {
+        syscalls::sleep_ms(5);
+
+        assert_eq!(read_xmm0(), PATTERN, "Another process overwrote `xmm0`.");
+    }
+}
+
+fn write_xmm0(v: u64) {
+    // SAFETY: The kernel does not use `xmm0`.
+    unsafe { asm!("movq xmm0, {}", in(reg) v) }
+}
+
+fn read_xmm0() -> u64 {
+    let v;
+
+    // SAFETY: Reading a register does not break anything.
+    unsafe { asm!("movq {}, xmm0", out(reg) v) }
+    v
+}
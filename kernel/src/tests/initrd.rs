@@ -0,0 +1,19 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::initrd;
+
+pub(super) fn main() {
+    test_get_file();
+    test_get_nonexistent_file();
+}
+
+fn test_get_file() {
+    assert_eq!(
+        initrd::get("hello.txt"),
+        Some(&b"Hello from the initrd!\n"[..])
+    );
+}
+
+fn test_get_nonexistent_file() {
+    assert_eq!(initrd::get("nonexistent"), None);
+}
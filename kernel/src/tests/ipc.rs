@@ -0,0 +1,96 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use core::sync::atomic::{AtomicI32, Ordering};
+use syscalls::{Error, Message};
+
+static SERVER_PID: AtomicI32 = AtomicI32::new(-1);
+
+/// A request to the server to send a message before the reply.
+const SEND_FIRST: u64 = 1;
+
+/// Replies to each message with its first word incremented.
+pub fn server() {
+    SERVER_PID.store(syscalls::getpid(), Ordering::Relaxed);
+
+    loop {
+        let m = syscalls::receive();
+        if m.body[1] == SEND_FIRST {
+            syscalls::send(m.sender, &Message::default()).expect("Failed to send.");
+        }
+
+        let r = Message::new([m.body[0] + 1, 0, 0, 0]);
+        syscalls::reply(m.sender, &r).expect("Failed to reply.");
+    }
+}
+
+pub(super) fn main() {
+    test_call();
+    test_call_skips_other_messages();
+    test_reply_without_call();
+    test_send_to_nonexistent_process();
+    test_inbox_full();
+}
+
+fn test_call() {
+    let pid = server_pid();
+    let r = syscalls::call(pid, &Message::new([41, 0, 0, 0])).expect("Failed to call the server.");
+
+    assert_eq!(r.sender, pid);
+    assert_eq!(r.body[0], 42);
+}
+
+fn test_call_skips_other_messages() {
+    let pid = server_pid();
+    let r = syscalls::call(pid, &Message::new([41, SEND_FIRST, 0, 0]));
+
+    assert_eq!(r.map(|r| r.body[0]), Ok(42));
+    assert_eq!(
+        syscalls::receive_from(pid),
+        Message {
+            sender: pid,
+            ..Message::default()
+        }
+    );
+}
+
+fn test_reply_without_call() {
+    assert_eq!(
+        syscalls::reply(server_pid(), &Message::default()),
+        Err(Error::InvalidArgument)
+    );
+}
+
+fn test_send_to_nonexistent_process() {
+    assert_eq!(
+        syscalls::send(i32::MAX, &Message::default()),
+        Err(Error::NoSuchProcess)
+    );
+}
+
+fn test_inbox_full() {
+    const CAPACITY: u64 = 128;
+
+    let me = syscalls::getpid();
+
+    for i in 0..CAPACITY {
+        assert_eq!(syscalls::send(me, &Message::new([i, 0, 0, 0])), Ok(()));
+    }
+
+    assert_eq!(
+        syscalls::send(me, &Message::default()),
+        Err(Error::InboxFull)
+    );
+
+    for i in 0..CAPACITY {
+        assert_eq!(syscalls::receive_from(me).body[0], i);
+    }
+}
+
+pub(super) fn server_pid() -> i32 {
+    loop {
+        let pid = SERVER_PID.load(Ordering::Relaxed);
+        if pid >= 0 {
+            return pid;
+        }
+    }
+}
@@ -1,10 +1,99 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use crate::mem::{allocator::heap, paging};
+use alloc::{boxed::Box, vec, vec::Vec};
+use common::constant::{HEAP_REGION_END, HEAP_REGION_START, STACK_REGION_END, STACK_REGION_START};
+use core::{
+    convert::TryFrom,
+    sync::atomic::{AtomicBool, AtomicI32, Ordering},
+};
+use os_units::{Bytes, NumOfPages};
 use page_box::PageBox;
+use syscalls::{Access, Error, Message, MAX_SHARED_MEMORY};
+use x86_64::{
+    structures::paging::{PageSize, PageTableFlags, Size2MiB, Size4KiB},
+    PhysAddr, VirtAddr,
+};
+
+pub static MAPPING_TEST_SUCCESS: AtomicBool = AtomicBool::new(false);
+
+static PEER_PID: AtomicI32 = AtomicI32::new(-1);
+
+/// A request to `peer` to reply the `u64` at the virtual address in the message.
+const PEER_READ: u64 = 1;
+
+/// Replies whether the virtual address in a message reaches the physical address in the same
+/// message in the address space of this process, or the value at the address if the third element
+/// is `PEER_READ`.
+pub fn peer() {
+    PEER_PID.store(syscalls::getpid(), Ordering::Relaxed);
+
+    loop {
+        let m = syscalls::receive();
+        let v = VirtAddr::new(m.body[0]);
+        let p = PhysAddr::new(m.body[1]);
+
+        let r = if m.body[2] == PEER_READ {
+            // SAFETY: The sender mapped the address to this process.
+            unsafe { v.as_ptr::<u64>().read_volatile() }
+        } else {
+            (syscalls::translate_address(v) == Ok(p)).into()
+        };
+        syscalls::reply(m.sender, &Message::new([r, 0, 0, 0])).expect("Failed to reply.");
+    }
+}
+
+/// Maps physical regions of odd sizes and offsets, and checks that exactly the pages they span are
+/// mapped and unmapped.
+///
+/// This runs in the kernel privilege to map any physical region.
+pub fn mapping_test() {
+    check_mapping(PhysAddr::new(0x1000), Bytes::new(0x1000), 1);
+    check_mapping(PhysAddr::new(0x1ff0), Bytes::new(0x20), 2);
+    check_mapping(PhysAddr::new(0x1234), Bytes::new(0x2000), 3);
+    check_mapping(PhysAddr::new(0x1fff), Bytes::new(1), 1);
+    check_mapping(PhysAddr::new(0x5000), Bytes::new(0), 1);
+    check_mapping(PhysAddr::new(0x20_0000), Bytes::new(0x40_0000), 0x400);
+    check_mapping(PhysAddr::new(0x1f_f000), Bytes::new(0x40_2000), 0x402);
+    check_huge_page_alignment();
+    check_mapping_is_not_user_accessible();
+    check_kernel_heap_is_not_user_accessible();
+
+    MAPPING_TEST_SUCCESS.store(true, Ordering::Relaxed);
+}
 
 pub(super) fn main() {
     test_page_box_clone();
     test_page_box_from_slice();
+    test_address_spaces_are_isolated();
+    test_pages_are_in_heap_region();
+    test_stack_is_in_stack_region();
+    test_odd_sized_allocation();
+    test_large_allocation_is_aligned();
+    test_heap_grows();
+    test_small_objects_are_in_slabs();
+    test_lazy_pages_are_mapped_on_access();
+    test_kernel_maps_lazy_pages();
+    test_stack_grows();
+    test_invalid_stack_limit();
+    test_shared_memory_is_shared();
+    test_shared_memory_outlives_destruction();
+    test_invalid_shared_memory();
+    test_shared_memory_is_limited();
+    test_shared_memory_is_released_once();
+}
+
+/// Uses about `kilobytes` kilobytes of the stack.
+pub(super) fn use_stack(kilobytes: usize) {
+    let a = [0_u8; 0x400];
+    touch(&a);
+
+    if kilobytes > 1 {
+        use_stack(kilobytes - 1);
+    }
+
+    // Keeps `a` alive across the call, so that the recursion is not turned into a loop.
+    touch(&a);
 }
 
 fn test_page_box_clone() {
@@ -25,3 +114,347 @@ fn test_page_box_from_slice() {
 
     assert_eq!(*b, *s);
 }
+
+fn test_address_spaces_are_isolated() {
+    let b = PageBox::from(0x1234_u64);
+    let m = Message::new([b.virt_addr().as_u64(), b.phys_addr().as_u64(), 0, 0]);
+
+    let r = syscalls::call(peer_pid(), &m).expect("Failed to call the peer.");
+
+    assert_eq!(r.body[0], 0, "Another process can read the page.");
+}
+
+fn test_pages_are_in_heap_region() {
+    let n = NumOfPages::new(2);
+    let mut pages = Vec::new();
+
+    for _ in 0..512 {
+        let v = syscalls::allocate_pages(n).expect("Failed to allocate pages.");
+
+        assert!((HEAP_REGION_START..HEAP_REGION_END).contains(&v));
+        pages.push(v);
+    }
+
+    for v in pages {
+        syscalls::deallocate_pages(v, n).expect("Failed to deallocate pages.");
+    }
+}
+
+fn test_stack_is_in_stack_region() {
+    let x = 0;
+    let v = VirtAddr::from_ptr(&x);
+
+    assert!((STACK_REGION_START..STACK_REGION_END).contains(&v));
+}
+
+fn test_odd_sized_allocation() {
+    let n = NumOfPages::<Size4KiB>::new(9);
+    let v = syscalls::allocate_pages(n).expect("Failed to allocate pages.");
+    let pages = || (0..n.as_usize()).map(|i| v + Size4KiB::SIZE * u64::try_from(i).unwrap());
+
+    for p in pages() {
+        // SAFETY: The page is allocated.
+        unsafe { p.as_mut_ptr::<u64>().write_volatile(0x1234) };
+    }
+
+    syscalls::deallocate_pages(v, n).expect("Failed to deallocate pages.");
+
+    for p in pages() {
+        assert_eq!(syscalls::translate_address(p), Err(Error::NotMapped));
+    }
+}
+
+/// A large allocation is aligned like its frames, so that huge pages map it.
+fn test_large_allocation_is_aligned() {
+    let n = NumOfPages::<Size4KiB>::new(0x200);
+    let v = syscalls::allocate_pages(n).expect("Failed to allocate pages.");
+    let last = v + (n.as_bytes().as_usize() - 8);
+
+    assert!(v.is_aligned(Size2MiB::SIZE));
+
+    // SAFETY: The pages are allocated.
+    unsafe {
+        v.as_mut_ptr::<u64>().write_volatile(0x1234);
+        last.as_mut_ptr::<u64>().write_volatile(0x5678);
+    }
+
+    let p = syscalls::translate_address(v).expect("The page is not mapped.");
+    assert_eq!(syscalls::translate_address(last), Ok(p + (last - v)));
+
+    syscalls::deallocate_pages(v, n).expect("Failed to deallocate pages.");
+
+    assert_eq!(syscalls::translate_address(last), Err(Error::NotMapped));
+}
+
+fn test_heap_grows() {
+    // Larger than the heap in the kernel image.
+    let v = vec![1_u8; 0x40_0000];
+
+    assert!(heap::stats().grown >= v.len());
+    assert!(v.iter().all(|x| *x == 1));
+}
+
+fn test_small_objects_are_in_slabs() {
+    const N: usize = 1000;
+
+    let objects: Vec<_> = (0..N).map(|i| Box::new([i; 6])).collect();
+
+    let c = heap::stats()
+        .caches
+        .iter()
+        .find(|c| c.object_size == 64)
+        .copied()
+        .expect("No cache for 64 bytes.");
+    assert!(c.in_use >= N);
+
+    for (i, o) in objects.iter().enumerate() {
+        assert_eq!(**o, [i; 6]);
+    }
+}
+
+fn test_lazy_pages_are_mapped_on_access() {
+    let n = NumOfPages::<Size4KiB>::new(3);
+    let v = syscalls::allocate_lazy_pages(n).expect("Failed to allocate pages.");
+    let pages = || (0..n.as_usize()).map(|i| v + Size4KiB::SIZE * u64::try_from(i).unwrap());
+
+    assert!((HEAP_REGION_START..HEAP_REGION_END).contains(&v));
+
+    for p in pages() {
+        assert_eq!(syscalls::translate_address(p), Err(Error::NotMapped));
+    }
+
+    for p in pages() {
+        // SAFETY: The page is allocated, and is mapped on the access.
+        unsafe {
+            assert_eq!(p.as_ptr::<u64>().read_volatile(), 0);
+            p.as_mut_ptr::<u64>().write_volatile(0x1234);
+        }
+
+        assert!(syscalls::translate_address(p).is_ok());
+    }
+
+    syscalls::deallocate_pages(v, n).expect("Failed to deallocate pages.");
+
+    for p in pages() {
+        assert_eq!(syscalls::translate_address(p), Err(Error::NotMapped));
+    }
+}
+
+/// The kernel maps a lazily allocated page when a system call reads it, instead of failing.
+fn test_kernel_maps_lazy_pages() {
+    let n = NumOfPages::<Size4KiB>::new(1);
+    let v = syscalls::allocate_lazy_pages(n).expect("Failed to allocate pages.");
+
+    // SAFETY: The page is allocated, and the kernel maps it when reading the message.
+    let m = unsafe { &*v.as_ptr::<Message>() };
+    syscalls::send(peer_pid(), m).expect("Failed to send a message in a lazy page.");
+    let r = syscalls::receive_from(peer_pid());
+
+    assert_eq!(r.body[0], 0, "The message is not zeroed.");
+    assert!(syscalls::translate_address(v).is_ok());
+
+    syscalls::deallocate_pages(v, n).expect("Failed to deallocate pages.");
+}
+
+fn test_stack_grows() {
+    use_stack(0x100);
+}
+
+fn test_invalid_stack_limit() {
+    let too_large = Bytes::new(syscalls::MAX_STACK_LIMIT.as_usize() + 1);
+
+    assert_eq!(
+        syscalls::set_stack_limit(Bytes::new(0)),
+        Err(Error::InvalidArgument)
+    );
+    assert_eq!(
+        syscalls::set_stack_limit(too_large),
+        Err(Error::InvalidArgument)
+    );
+}
+
+fn test_shared_memory_is_shared() {
+    let id = syscalls::create_shared_memory(Bytes::new(0x2000)).expect("Failed to create.");
+    let v = map_shared_memory(id, syscalls::getpid(), Access::ReadWrite);
+    let v2 = map_shared_memory(id, syscalls::getpid(), Access::ReadOnly);
+    let peer = map_shared_memory(id, peer_pid(), Access::ReadOnly);
+
+    assert_eq!(syscalls::translate_address(v), Err(Error::NotMapped));
+
+    // SAFETY: The memory is mapped on the access.
+    unsafe {
+        assert_eq!((v2 + 0x1000_u64).as_ptr::<u64>().read_volatile(), 0);
+        (v + 0x1000_u64).as_mut_ptr::<u64>().write_volatile(0x1234);
+        assert_eq!((v2 + 0x1000_u64).as_ptr::<u64>().read_volatile(), 0x1234);
+    }
+
+    assert_eq!(peer_read(peer + 0x1000_u64), 0x1234);
+
+    syscalls::unmap_shared_memory(v).expect("Failed to unmap.");
+    syscalls::unmap_shared_memory(v2).expect("Failed to unmap.");
+    syscalls::destroy_shared_memory(id).expect("Failed to destroy.");
+
+    assert_eq!(
+        syscalls::translate_address(v + 0x1000_u64),
+        Err(Error::NotMapped)
+    );
+}
+
+/// The mappings keep the frames after the memory is destroyed.
+fn test_shared_memory_outlives_destruction() {
+    let id = syscalls::create_shared_memory(Bytes::new(8)).expect("Failed to create.");
+    let v = map_shared_memory(id, syscalls::getpid(), Access::ReadWrite);
+    let peer = map_shared_memory(id, peer_pid(), Access::ReadWrite);
+
+    // SAFETY: The memory is mapped on the access.
+    unsafe { v.as_mut_ptr::<u64>().write_volatile(0x5678) };
+
+    syscalls::destroy_shared_memory(id).expect("Failed to destroy.");
+
+    assert_eq!(
+        syscalls::map_shared_memory(id, syscalls::getpid(), Access::ReadWrite),
+        Err(Error::InvalidArgument)
+    );
+    assert_eq!(peer_read(peer), 0x5678);
+
+    syscalls::unmap_shared_memory(v).expect("Failed to unmap.");
+
+    assert_eq!(peer_read(peer), 0x5678);
+}
+
+fn test_invalid_shared_memory() {
+    let id = syscalls::create_shared_memory(Bytes::new(8)).expect("Failed to create.");
+
+    assert_eq!(
+        syscalls::create_shared_memory(Bytes::new(0)),
+        Err(Error::InvalidArgument)
+    );
+    assert_eq!(
+        syscalls::map_shared_memory(id, -3, Access::ReadWrite),
+        Err(Error::NoSuchProcess)
+    );
+    assert_eq!(
+        syscalls::unmap_shared_memory(HEAP_REGION_START),
+        Err(Error::InvalidArgument)
+    );
+
+    syscalls::destroy_shared_memory(id).expect("Failed to destroy.");
+}
+
+fn test_shared_memory_is_limited() {
+    let id = syscalls::create_shared_memory(Bytes::new(8)).expect("Failed to create.");
+
+    assert_eq!(
+        syscalls::create_shared_memory(Bytes::new(MAX_SHARED_MEMORY.as_usize() + 1)),
+        Err(Error::InvalidArgument)
+    );
+    // `id` is also counted.
+    assert_eq!(
+        syscalls::create_shared_memory(MAX_SHARED_MEMORY),
+        Err(Error::OutOfMemory)
+    );
+
+    syscalls::destroy_shared_memory(id).expect("Failed to destroy.");
+    let largest = syscalls::create_shared_memory(MAX_SHARED_MEMORY).expect("Failed to create.");
+    syscalls::destroy_shared_memory(largest).expect("Failed to destroy.");
+}
+
+fn test_shared_memory_is_released_once() {
+    let id = syscalls::create_shared_memory(Bytes::new(8)).expect("Failed to create.");
+    let v = map_shared_memory(id, syscalls::getpid(), Access::ReadWrite);
+
+    syscalls::destroy_shared_memory(id).expect("Failed to destroy.");
+
+    assert_eq!(
+        syscalls::destroy_shared_memory(id),
+        Err(Error::InvalidArgument)
+    );
+
+    syscalls::unmap_shared_memory(v).expect("Failed to unmap.");
+
+    assert_eq!(
+        syscalls::unmap_shared_memory(v),
+        Err(Error::InvalidArgument)
+    );
+}
+
+fn check_mapping(start: PhysAddr, bytes: Bytes, pages: u64) {
+    let v = syscalls::map_pages(start, bytes).expect("Failed to map.");
+    let page = v.align_down(Size4KiB::SIZE);
+    let frame = start.align_down(Size4KiB::SIZE);
+
+    assert_eq!(v - page, start - frame, "The offset in the page differs.");
+
+    for i in 0..pages {
+        let p = syscalls::translate_address(page + Size4KiB::SIZE * i);
+        assert_eq!(p, Ok(frame + Size4KiB::SIZE * i));
+    }
+
+    let after = page + Size4KiB::SIZE * pages;
+    assert_eq!(syscalls::translate_address(after), Err(Error::NotMapped));
+
+    syscalls::unmap_pages(v, bytes).expect("Failed to unmap.");
+
+    for i in 0..pages {
+        let p = syscalls::translate_address(page + Size4KiB::SIZE * i);
+        assert_eq!(p, Err(Error::NotMapped));
+    }
+}
+
+/// A region which contains an aligned huge frame is mapped to an address of the same offset from
+/// the boundary of a huge page, so that a huge page maps the frame.
+fn check_huge_page_alignment() {
+    let start = PhysAddr::new(0x1f_f000);
+    let bytes = Bytes::new(0x40_2000);
+    let v = syscalls::map_pages(start, bytes).expect("Failed to map.");
+
+    assert_eq!(v.as_u64() % Size2MiB::SIZE, start.as_u64() % Size2MiB::SIZE);
+
+    syscalls::unmap_pages(v, bytes).expect("Failed to unmap.");
+}
+
+/// This runs in the kernel privilege, so the region is mapped only for ring 0.
+fn check_mapping_is_not_user_accessible() {
+    let bytes = Bytes::new(0x1000);
+    let v = syscalls::map_pages(PhysAddr::new(0x1000), bytes).expect("Failed to map.");
+
+    let f = paging::effective_flags(v).expect("Not mapped.");
+    assert!(!f.contains(PageTableFlags::USER_ACCESSIBLE));
+
+    syscalls::unmap_pages(v, bytes).expect("Failed to unmap.");
+}
+
+/// This runs in the kernel privilege, so the allocations are in the kernel heap.
+fn check_kernel_heap_is_not_user_accessible() {
+    let small = Box::new(0_u8);
+    let large = vec![0_u8; 0x40_0000];
+
+    for p in &[&*small as *const u8, large.as_ptr()] {
+        let f = paging::effective_flags(VirtAddr::from_ptr(*p)).expect("Not mapped.");
+        assert!(!f.contains(PageTableFlags::USER_ACCESSIBLE));
+    }
+}
+
+fn touch(a: &[u8]) {
+    // SAFETY: This does nothing. It only makes the compiler place `a` on the stack.
+    unsafe { asm!("/* {} */", in(reg) a.as_ptr(), options(nostack, readonly)) }
+}
+
+fn map_shared_memory(id: u64, pid: i32, access: Access) -> VirtAddr {
+    syscalls::map_shared_memory(id, pid, access).expect("Failed to map the shared memory.")
+}
+
+fn peer_read(v: VirtAddr) -> u64 {
+    let m = Message::new([v.as_u64(), 0, PEER_READ, 0]);
+    let r = syscalls::call(peer_pid(), &m).expect("Failed to call the peer.");
+    r.body[0]
+}
+
+fn peer_pid() -> i32 {
+    loop {
+        let pid = PEER_PID.load(Ordering::Relaxed);
+        if pid >= 0 {
+            return pid;
+        }
+    }
+}
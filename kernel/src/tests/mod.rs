@@ -3,15 +3,37 @@
 use crate::qemu;
 use core::sync::atomic::Ordering;
 
-mod mem;
+mod capability;
+pub mod exception;
+pub mod fpu;
+mod initrd;
+pub mod ipc;
+pub mod mem;
 pub mod process;
+pub mod scheduler;
+mod sleep;
+pub mod smp;
+mod spawn;
 mod syscall;
 
 pub fn main() {
     self::syscall::main();
+    self::capability::main();
+    self::exception::main();
+    self::fpu::main();
     self::mem::main();
+    self::initrd::main();
+    self::ipc::main();
+    self::scheduler::main();
+    self::sleep::main();
+    self::smp::main();
+    self::spawn::main();
 
     while !process::SWITCH_TEST_SUCCESS.load(Ordering::Relaxed) {}
+    while !process::EXIT_STRESS_TEST_SUCCESS.load(Ordering::Relaxed) {}
+    while !mem::MAPPING_TEST_SUCCESS.load(Ordering::Relaxed) {}
+    while !scheduler::STARVATION_TEST_SUCCESS.load(Ordering::Relaxed) {}
 
+    crate::mem::allocator::heap::log_stats();
     qemu::exit_success();
 }
@@ -1,8 +1,20 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use crate::{
+    mem::allocator::phys::FRAME_MANAGER,
+    process::{self, manager::Function, Capabilities, Privilege},
+};
+use common::constant::LOCAL_APIC_ID_REGISTER_ADDR;
 use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use os_units::{Bytes, NumOfPages};
+use x86_64::{instructions::interrupts, structures::paging::Size4KiB};
 
 pub static SWITCH_TEST_SUCCESS: AtomicBool = AtomicBool::new(false);
+pub static EXIT_STRESS_TEST_SUCCESS: AtomicBool = AtomicBool::new(false);
+
+static STARTED: AtomicUsize = AtomicUsize::new(0);
+static RELEASE: AtomicBool = AtomicBool::new(false);
+static RETURNED: AtomicUsize = AtomicUsize::new(0);
 
 pub fn count_switch() {
     const EXIT_GOAL: usize = 1000;
@@ -25,3 +37,121 @@ pub fn exit_test() {
 }
 
 pub fn do_nothing() {}
+
+/// Spawns thousands of processes which exit without freeing their memory, and checks that all
+/// the frames are freed anyway.
+///
+/// This runs in the kernel privilege to read `FRAME_MANAGER` with interrupts disabled.
+pub fn exit_stress_test() {
+    const ROUNDS: usize = 40;
+    const BATCH: usize = 50;
+
+    // The manager allocates page tables of its own to create processes, and they are never freed.
+    // Create more processes at once than in the rounds so that the baseline includes them.
+    run_round(BATCH * 2);
+    let baseline = wait_until_exited(BATCH * 2);
+
+    for _ in 0..ROUNDS {
+        run_round(BATCH);
+    }
+
+    wait_until_freed(baseline);
+    EXIT_STRESS_TEST_SUCCESS.store(true, Ordering::Relaxed);
+}
+
+/// Creates `n` processes which are all alive at the same time, and lets them exit.
+fn run_round(n: usize) {
+    RELEASE.store(false, Ordering::Relaxed);
+    STARTED.store(0, Ordering::Relaxed);
+
+    let apic = LOCAL_APIC_ID_REGISTER_ADDR;
+    let c = Capabilities::default()
+        .interrupts()
+        .mmio(apic..apic + 4_u64);
+
+    for _ in 0..n {
+        interrupts::without_interrupts(|| {
+            process::manager::add_function(Function {
+                capabilities: c.clone(),
+                ..Function::new(leak_resources, Privilege::User)
+            })
+        });
+    }
+
+    while STARTED.load(Ordering::Relaxed) < n {
+        syscalls::sleep_ms(1);
+    }
+
+    RELEASE.store(true, Ordering::Relaxed);
+}
+
+fn leak_resources() {
+    syscalls::allocate_pages(NumOfPages::new(3)).expect("Failed to allocate pages.");
+    syscalls::map_pages(LOCAL_APIC_ID_REGISTER_ADDR, Bytes::new(4)).expect("Failed to map.");
+    syscalls::notify_on_interrupt(0x21, syscalls::getpid()).expect("Failed to register.");
+
+    STARTED.fetch_add(1, Ordering::Relaxed);
+
+    while !RELEASE.load(Ordering::Relaxed) {
+        syscalls::sleep_ms(1);
+    }
+
+    RETURNED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Waits until `n` processes have returned in total and the frames freed by their exits stop
+/// increasing, and returns the number of the free pages then.
+fn wait_until_exited(n: usize) -> NumOfPages<Size4KiB> {
+    const INTERVAL_MS: u64 = 100;
+
+    while RETURNED.load(Ordering::Relaxed) < n {
+        syscalls::sleep_ms(1);
+    }
+
+    let mut last = free_pages();
+    loop {
+        syscalls::sleep_ms(INTERVAL_MS);
+
+        let now = free_pages();
+        if now <= last {
+            return last;
+        }
+
+        last = now;
+    }
+}
+
+fn wait_until_freed(baseline: NumOfPages<Size4KiB>) {
+    const TIMEOUT_MS: u64 = 10_000;
+
+    let deadline = syscalls::get_uptime() + TIMEOUT_MS;
+
+    // Other processes allocate and free memory at the same time, so the free memory may exceed
+    // the baseline.
+    while free_pages() < baseline {
+        assert!(
+            syscalls::get_uptime() < deadline,
+            "Leaked {} pages.",
+            baseline.as_usize() - free_pages().as_usize()
+        );
+
+        syscalls::sleep_ms(10);
+    }
+}
+
+/// Also checks that the statistics of the frame manager agree with the number of the free pages.
+fn free_pages() -> NumOfPages<Size4KiB> {
+    interrupts::without_interrupts(|| {
+        let m = FRAME_MANAGER.lock();
+        let counted: usize = m
+            .stats()
+            .free
+            .iter()
+            .enumerate()
+            .map(|(order, n)| n << order)
+            .sum();
+
+        assert_eq!(counted, m.free_pages().as_usize());
+        m.free_pages()
+    })
+}
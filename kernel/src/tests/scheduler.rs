@@ -0,0 +1,114 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::{
+    process::{self, manager::Function, Capabilities, Privilege},
+    smp,
+};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use syscalls::{Error, Priority};
+use x86_64::instructions::interrupts;
+
+pub static STARVATION_TEST_SUCCESS: AtomicBool = AtomicBool::new(false);
+
+static SET_WITH_CAPABILITY: AtomicBool = AtomicBool::new(false);
+static DEMOTED: AtomicBool = AtomicBool::new(false);
+static YIELDERS: AtomicUsize = AtomicUsize::new(0);
+static DEMOTED_RAN: AtomicBool = AtomicBool::new(false);
+
+pub(super) fn main() {
+    test_set_own_priority();
+    test_set_priority_of_nonexistent_process();
+}
+
+/// Without the capability for scheduling, a process can keep or lower its priority but cannot raise
+/// it.
+fn test_set_own_priority() {
+    assert_eq!(
+        syscalls::set_priority(syscalls::getpid(), Priority::Normal),
+        Ok(())
+    );
+    assert_eq!(
+        syscalls::set_priority(syscalls::getpid(), Priority::High),
+        Err(Error::PermissionDenied)
+    );
+}
+
+fn test_set_priority_of_nonexistent_process() {
+    assert_eq!(
+        syscalls::set_priority(i32::MAX, Priority::High),
+        Err(Error::NoSuchProcess)
+    );
+}
+
+/// Checks that a process demoted to a lower level runs even while processes of the highest level
+/// are always ready on every CPU, and that the capability for scheduling allows changing the
+/// scheduler.
+///
+/// This runs in the kernel privilege to add processes.
+pub fn starvation_test() {
+    interrupts::without_interrupts(|| {
+        process::manager::add_function(Function {
+            capabilities: Capabilities::default().scheduling(),
+            ..Function::new(set_with_capability, Privilege::User)
+        });
+    });
+    add(use_up_time_slices, Privilege::User, Priority::Normal);
+
+    wait_for(&DEMOTED);
+
+    for _ in 0..smp::count() {
+        add(yield_until_demoted_runs, Privilege::Kernel, Priority::High);
+    }
+
+    wait_for(&DEMOTED_RAN);
+    wait_for(&SET_WITH_CAPABILITY);
+
+    STARVATION_TEST_SUCCESS.store(true, Ordering::Relaxed);
+}
+
+fn add(f: fn(), p: Privilege, priority: Priority) {
+    interrupts::without_interrupts(|| process::manager::add_with_priority(f, p, priority));
+}
+
+fn wait_for(flag: &AtomicBool) {
+    while !flag.load(Ordering::Relaxed) {
+        syscalls::sleep_ms(1);
+    }
+}
+
+fn set_with_capability() {
+    // The same as the default.
+    assert_eq!(syscalls::set_quantum(10), Ok(()));
+    assert_eq!(
+        syscalls::set_priority(super::ipc::server_pid(), Priority::Normal),
+        Ok(())
+    );
+
+    SET_WITH_CAPABILITY.store(true, Ordering::Relaxed);
+}
+
+/// Keeps the CPU busy until it goes down to the lowest level, and then waits for the processes of
+/// the highest level.
+fn use_up_time_slices() {
+    const BUSY_MS: u64 = 200;
+
+    let end = syscalls::get_uptime() + BUSY_MS;
+    while syscalls::get_uptime() < end {}
+
+    DEMOTED.store(true, Ordering::Relaxed);
+
+    while YIELDERS.load(Ordering::Relaxed) < smp::count() {}
+
+    DEMOTED_RAN.store(true, Ordering::Relaxed);
+}
+
+/// Gives up the CPU before using up the time slice, so that this process stays at the highest
+/// level.
+fn yield_until_demoted_runs() {
+    YIELDERS.fetch_add(1, Ordering::Relaxed);
+
+    while !DEMOTED_RAN.load(Ordering::Relaxed) {
+        // SAFETY: This operation is safe as the handler of the vector 0x81 preserves all registers.
+        unsafe { asm!("int 0x81") }
+    }
+}
@@ -0,0 +1,25 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+pub(super) fn main() {
+    test_sleep_ms();
+    test_sleep_until_past();
+}
+
+fn test_sleep_ms() {
+    let start = syscalls::get_uptime();
+    syscalls::sleep_ms(10);
+    assert!(syscalls::get_uptime() >= start + 10);
+}
+
+/// Sleeping until a past time returns at once. If each call waited for a tick, the calls would take
+/// at least `N` milliseconds, while preemption alone takes far less.
+fn test_sleep_until_past() {
+    const N: u64 = 1000;
+
+    let start = syscalls::get_uptime();
+    for _ in 0..N {
+        syscalls::sleep_until(0);
+    }
+
+    assert!(syscalls::get_uptime() < start + N);
+}
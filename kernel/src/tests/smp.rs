@@ -0,0 +1,46 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use crate::smp;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use os_units::NumOfPages;
+
+/// The bit `n` is set once a process runs on the CPU `n`.
+static SEEN_CPUS: AtomicUsize = AtomicUsize::new(0);
+
+const TIMEOUT_MS: u64 = 1000;
+
+pub(super) fn main() {
+    test_all_cpus_run_processes();
+    test_tlb_shootdown_finishes();
+}
+
+/// Processes are assigned to the CPUs in turn, so `MAX_CPUS` of these run on every CPU.
+///
+/// This runs in the kernel privilege, as ring 3 cannot reach the per-CPU block.
+pub fn record_cpu() {
+    SEEN_CPUS.fetch_or(1 << smp::id(), Ordering::Relaxed);
+}
+
+fn test_all_cpus_run_processes() {
+    let all = (1 << smp::count()) - 1;
+    let start = syscalls::get_uptime();
+
+    while SEEN_CPUS.load(Ordering::Relaxed) & all != all {
+        assert!(
+            syscalls::get_uptime() < start + TIMEOUT_MS,
+            "Some CPUs did not run any processes."
+        );
+
+        syscalls::sleep_ms(10);
+    }
+}
+
+/// Deallocating pages waits until every CPU flushes them from its TLB.
+fn test_tlb_shootdown_finishes() {
+    for _ in 0..100 {
+        let n = NumOfPages::new(3);
+        let v = syscalls::allocate_pages(n).expect("Failed to allocate pages.");
+
+        syscalls::deallocate_pages(v, n).expect("Failed to deallocate pages.");
+    }
+}
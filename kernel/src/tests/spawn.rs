@@ -0,0 +1,63 @@
+// SPDX-License-Identifier: GPL-3.0-or-later
+
+use syscalls::{Error, STATUS_KILLED};
+
+pub(super) fn main() {
+    test_spawn_and_wait();
+    test_spawn_by_path();
+    test_spawn_with_env();
+    test_spawn_nonexistent_file();
+    test_spawn_broken_executable();
+    test_wait_for_non_child();
+    test_writable_data_is_copied_on_write();
+}
+
+fn test_spawn_and_wait() {
+    let pid = syscalls::spawn("hello", &["from", "a", "test"]).expect("Failed to spawn.");
+
+    assert_eq!(syscalls::wait(pid), Ok(0));
+    assert_eq!(syscalls::wait(pid), Err(Error::NoSuchProcess));
+}
+
+fn test_spawn_by_path() {
+    let pid = syscalls::spawn("/hello", &[]).expect("Failed to spawn.");
+
+    assert_eq!(syscalls::wait(pid), Ok(0));
+}
+
+fn test_spawn_with_env() {
+    let pid = syscalls::spawn_with_env("hello", &["env"], &["HOME=/", "LANG=C"])
+        .expect("Failed to spawn.");
+
+    assert_eq!(syscalls::wait(pid), Ok(2));
+}
+
+fn test_spawn_nonexistent_file() {
+    assert_eq!(syscalls::spawn("nonexistent", &[]), Err(Error::NoSuchFile));
+}
+
+fn test_spawn_broken_executable() {
+    let pid = syscalls::spawn("hello.txt", &[]).expect("Failed to spawn.");
+
+    assert_eq!(syscalls::wait(pid), Ok(STATUS_KILLED));
+}
+
+fn test_wait_for_non_child() {
+    assert_eq!(
+        syscalls::wait(syscalls::getpid()),
+        Err(Error::NoSuchProcess)
+    );
+}
+
+/// Both processes share the image of `hello`, and each writes to its data. The writes must not be
+/// visible to the other.
+fn test_writable_data_is_copied_on_write() {
+    let pids = [
+        syscalls::spawn("hello", &["count"]).expect("Failed to spawn."),
+        syscalls::spawn("hello", &["count"]).expect("Failed to spawn."),
+    ];
+
+    for pid in &pids {
+        assert_eq!(syscalls::wait(*pid), Ok(1));
+    }
+}
@@ -1,12 +1,71 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
+use core::ffi::c_void;
 use page_box::PageBox;
+use syscalls::Error;
+use x86_64::VirtAddr;
 
 pub(super) fn main() {
     test_translate_address();
+    test_translate_unmapped_address();
+    test_write_to_unsupported_file();
+    test_write_from_user_buffer();
+    test_write_from_kernel_address();
+    test_write_from_null();
+    test_write_from_freed_page();
 }
 
 fn test_translate_address() {
     let p = PageBox::from(0_i32);
-    assert_eq!(p.phys_addr(), syscalls::translate_address(p.virt_addr()));
+    assert_eq!(
+        Ok(p.phys_addr()),
+        syscalls::translate_address(p.virt_addr())
+    );
+}
+
+fn test_translate_unmapped_address() {
+    assert_eq!(
+        syscalls::translate_address(VirtAddr::zero()),
+        Err(Error::NotMapped)
+    );
+}
+
+fn test_write_to_unsupported_file() {
+    let s = "stderr";
+
+    // SAFETY: `s` is a valid string.
+    let r = unsafe { syscalls::write(2, s.as_ptr().cast::<c_void>(), 6) };
+    assert_eq!(r, Err(Error::BadFileDescriptor));
+}
+
+fn test_write_from_user_buffer() {
+    let s = PageBox::from(*b"user buffer");
+
+    // SAFETY: `s` is a valid string.
+    let r = unsafe { syscalls::write(1, s.virt_addr().as_ptr(), 11) };
+    assert_eq!(r, Ok(11));
+}
+
+fn test_write_from_kernel_address() {
+    let s = "kernel";
+
+    // SAFETY: The kernel must reject the address instead of reading it.
+    let r = unsafe { syscalls::write(1, s.as_ptr().cast::<c_void>(), 6) };
+    assert_eq!(r, Err(Error::BadAddress));
+}
+
+fn test_write_from_null() {
+    // SAFETY: The kernel must reject the address instead of reading it.
+    let r = unsafe { syscalls::write(1, core::ptr::null(), 1) };
+    assert_eq!(r, Err(Error::BadAddress));
+}
+
+fn test_write_from_freed_page() {
+    let p = PageBox::from(*b"freed");
+    let a = p.virt_addr();
+    drop(p);
+
+    // SAFETY: The kernel must reject the address as the page is already unmapped.
+    let r = unsafe { syscalls::write(1, a.as_ptr(), 5) };
+    assert_eq!(r, Err(Error::BadAddress));
 }
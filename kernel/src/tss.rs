@@ -1,11 +1,213 @@
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use common::constant::INTERRUPT_STACK;
+use crate::{
+    mem::paging::pml4::PML4,
+    smp::{self, percpu, MAX_CPUS},
+};
+use alloc::vec::Vec;
+use bit_field::BitField;
+use core::{
+    convert::TryInto,
+    mem,
+    ops::{Deref, DerefMut, RangeInclusive},
+};
 use spinning_top::Spinlock;
-use x86_64::structures::tss::TaskStateSegment;
+use x86_64::{
+    structures::{
+        gdt::{Descriptor, DescriptorFlags},
+        paging::{Mapper, Page, Size4KiB},
+        tss::TaskStateSegment,
+    },
+    VirtAddr,
+};
+
+/// The TSS of each CPU.
+pub static TSS: [Spinlock<Tss>; MAX_CPUS] = [TSS_INIT; MAX_CPUS];
+
+#[allow(clippy::declare_interior_mutable_const)]
+const TSS_INIT: Spinlock<Tss> = Spinlock::new(Tss::new());
+
+/// The index of the interrupt stack table for the double fault. The index 0 is used for the
+/// process switch.
+pub const DOUBLE_FAULT_STACK_INDEX: u16 = 1;
+
+/// The index of the interrupt stack table for the page fault. A page fault may happen on an
+/// unmapped page of the stack, and the handler maps it.
+pub const PAGE_FAULT_STACK_INDEX: u16 = 2;
+
+const DOUBLE_FAULT_STACK_SIZE: usize = 4096 * 5;
+
+const PAGE_FAULT_STACK_SIZE: usize = 4096 * 5;
+
+const INTERRUPT_STACK_SIZE: usize = 4096 * 8;
 
-pub static TSS: Spinlock<TaskStateSegment> = {
-    let mut tss = TaskStateSegment::new();
-    tss.privilege_stack_table[0] = INTERRUPT_STACK;
-    Spinlock::new(tss)
+// These are in the kernel image so that every address space maps them.
+static mut DOUBLE_FAULT_STACKS: [[u8; DOUBLE_FAULT_STACK_SIZE]; MAX_CPUS] =
+    [[0; DOUBLE_FAULT_STACK_SIZE]; MAX_CPUS];
+static mut PAGE_FAULT_STACKS: [[u8; PAGE_FAULT_STACK_SIZE]; MAX_CPUS] =
+    [[0; PAGE_FAULT_STACK_SIZE]; MAX_CPUS];
+static mut INTERRUPT_STACKS: [InterruptStack; MAX_CPUS] = [INTERRUPT_STACK_INIT; MAX_CPUS];
+
+const INTERRUPT_STACK_INIT: InterruptStack = InterruptStack {
+    guard: [0; GUARD_BYTES],
+    stack: [0; INTERRUPT_STACK_SIZE],
 };
+
+const GUARD_BYTES: usize = 4096;
+
+const NUM_OF_PORTS: usize = 0x1_0000;
+
+/// The CPU reads two bytes of the bitmap at once, so the bitmap must end with a byte of `0xff`.
+const IO_BITMAP_BYTES: usize = NUM_OF_PORTS / 8 + 1;
+
+/// Sets the stacks of the current CPU.
+pub fn init() {
+    let cpu = smp::id();
+
+    // SAFETY: Only the CPU accesses these stacks, when a double fault or a page fault happens.
+    let (double_fault, page_fault) = unsafe {
+        (
+            VirtAddr::from_ptr(&DOUBLE_FAULT_STACKS[cpu]) + DOUBLE_FAULT_STACK_SIZE,
+            VirtAddr::from_ptr(&PAGE_FAULT_STACKS[cpu]) + PAGE_FAULT_STACK_SIZE,
+        )
+    };
+
+    unmap_interrupt_stack_guard(cpu);
+
+    let interrupt = interrupt_stack();
+    // The handlers of the process switch read this as they cannot call a function before leaving
+    // the stack frame of the process.
+    percpu::current().set_interrupt_stack_top(interrupt);
+
+    let mut tss = current().lock();
+    tss.interrupt_stack_table[usize::from(DOUBLE_FAULT_STACK_INDEX)] = double_fault;
+    tss.interrupt_stack_table[usize::from(PAGE_FAULT_STACK_INDEX)] = page_fault;
+    tss.interrupt_stack_table[0] = interrupt;
+    tss.privilege_stack_table[0] = interrupt;
+}
+
+/// Returns the TSS of the current CPU.
+pub fn current() -> &'static Spinlock<Tss> {
+    &TSS[smp::id()]
+}
+
+/// Returns the top of the stack which the current CPU uses to handle interrupts and to switch
+/// processes.
+pub fn interrupt_stack() -> VirtAddr {
+    // SAFETY: Only the current CPU uses this stack.
+    let stack = unsafe { &INTERRUPT_STACKS[smp::id()].stack };
+
+    VirtAddr::from_ptr(stack) + INTERRUPT_STACK_SIZE
+}
+
+/// Returns `true` if `addr` is in the guard page below the interrupt stack of any CPU.
+pub fn is_interrupt_stack_guard(addr: VirtAddr) -> bool {
+    (0..MAX_CPUS).any(|cpu| guard_page(cpu) == Page::containing_address(addr))
+}
+
+/// Unmaps the guard page of the CPU, so that overflowing the interrupt stack causes a page fault
+/// instead of breaking the memory below it.
+///
+/// The page tables of the kernel image are shared by all address spaces, and other CPUs never
+/// access the page, so flushing the TLB of the current CPU is enough.
+fn unmap_interrupt_stack_guard(cpu: usize) {
+    if let Ok((_, flush)) = PML4.lock().unmap(guard_page(cpu)) {
+        flush.flush();
+    }
+}
+
+fn guard_page(cpu: usize) -> Page<Size4KiB> {
+    // SAFETY: Only the address is taken.
+    let guard = unsafe { &INTERRUPT_STACKS[cpu].guard };
+
+    Page::containing_address(VirtAddr::from_ptr(guard))
+}
+
+/// A stack to handle interrupts, with an unmapped page below it.
+#[repr(C, align(4096))]
+struct InterruptStack {
+    guard: [u8; GUARD_BYTES],
+    stack: [u8; INTERRUPT_STACK_SIZE],
+}
+
+/// The TSS followed by the I/O permission bitmap.
+///
+/// A clear bit in the bitmap allows ring 3 to access the port with `in` and `out` instructions.
+#[repr(C)]
+pub struct Tss {
+    tss: TaskStateSegment,
+    io_bitmap: [u8; IO_BITMAP_BYTES],
+
+    /// The ports which are allowed in `io_bitmap`.
+    allowed: Vec<RangeInclusive<u16>>,
+}
+impl Tss {
+    // `TaskStateSegment` is about 100 bytes, so the cast never truncates.
+    #[allow(clippy::cast_possible_truncation)]
+    const fn new() -> Self {
+        let mut tss = TaskStateSegment::new();
+        tss.iomap_base = mem::size_of::<TaskStateSegment>() as u16;
+
+        Self {
+            tss,
+            io_bitmap: [0xff; IO_BITMAP_BYTES],
+            allowed: Vec::new(),
+        }
+    }
+
+    /// Allows ring 3 to access only `ports`.
+    pub fn load_io_ports(&mut self, ports: &[RangeInclusive<u16>]) {
+        if self.allowed == ports {
+            return;
+        }
+
+        for r in mem::take(&mut self.allowed) {
+            self.set_ports(r, true);
+        }
+
+        for r in ports {
+            self.set_ports(r.clone(), false);
+        }
+
+        self.allowed = ports.to_vec();
+    }
+
+    fn set_ports(&mut self, ports: RangeInclusive<u16>, denied: bool) {
+        for p in ports {
+            let p = usize::from(p);
+            self.io_bitmap[p / 8].set_bit(p % 8, denied);
+        }
+    }
+}
+impl Deref for Tss {
+    type Target = TaskStateSegment;
+
+    fn deref(&self) -> &Self::Target {
+        &self.tss
+    }
+}
+impl DerefMut for Tss {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.tss
+    }
+}
+
+/// Creates a TSS descriptor whose limit covers the I/O permission bitmap.
+///
+/// `Descriptor::tss_segment` cannot be used as its limit covers only `TaskStateSegment`.
+pub fn descriptor(tss: &'static Tss) -> Descriptor {
+    let ptr = tss as *const _ as u64;
+    let limit = mem::size_of::<TaskStateSegment>() + IO_BITMAP_BYTES - 1;
+
+    let mut low = DescriptorFlags::PRESENT.bits();
+    low.set_bits(16..40, ptr.get_bits(0..24));
+    low.set_bits(56..64, ptr.get_bits(24..32));
+    low.set_bits(0..16, limit.try_into().unwrap());
+    // Available 64-bit TSS.
+    low.set_bits(40..44, 0b1001);
+
+    let mut high = 0;
+    high.set_bits(0..32, ptr.get_bits(32..64));
+
+    Descriptor::SystemSegment(low, high)
+}
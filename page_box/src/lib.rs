@@ -177,13 +177,8 @@ impl<T: ?Sized> PageBox<T> {
     /// This method panics if the `PageBox` is not mapped.
     #[must_use]
     pub fn phys_addr(&self) -> PhysAddr {
-        let a = syscalls::translate_address(self.virt);
-
-        if a.is_null() {
-            unreachable!("Address: {:?} is not mapped.", self.virt);
-        }
-
-        a
+        syscalls::translate_address(self.virt)
+            .unwrap_or_else(|_| unreachable!("Address: {:?} is not mapped.", self.virt))
     }
 
     #[must_use]
@@ -192,11 +187,8 @@ impl<T: ?Sized> PageBox<T> {
     }
 
     fn from_bytes(bytes: Bytes) -> Self {
-        let virt = syscalls::allocate_pages(bytes.as_num_of_pages());
-
-        if virt.is_null() {
-            panic!("Failed to allocate pages.");
-        }
+        let virt = syscalls::allocate_pages(bytes.as_num_of_pages())
+            .unwrap_or_else(|e| panic!("Failed to allocate pages: {:?}", e));
 
         Self {
             virt,
@@ -208,6 +200,7 @@ impl<T: ?Sized> PageBox<T> {
 impl<T: ?Sized> Drop for PageBox<T> {
     fn drop(&mut self) {
         let num_of_pages = self.bytes.as_num_of_pages::<Size4KiB>();
-        syscalls::deallocate_pages(self.virt, num_of_pages);
+        syscalls::deallocate_pages(self.virt, num_of_pages)
+            .unwrap_or_else(|e| unreachable!("Failed to deallocate pages: {:?}", e));
     }
 }
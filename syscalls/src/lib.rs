@@ -6,6 +6,7 @@
 
 use core::{convert::TryInto, ffi::c_void};
 use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
 use os_units::{Bytes, NumOfPages};
 use x86_64::{structures::paging::Size4KiB, PhysAddr, VirtAddr};
 
@@ -13,120 +14,221 @@ use x86_64::{structures::paging::Size4KiB, PhysAddr, VirtAddr};
 ///
 /// This function is unsafe because reading a value from I/O port may have side effects which
 /// violate memory safety.
-#[must_use]
-pub unsafe fn inb(port: u16) -> u8 {
-    general_syscall(Ty::Inb, port.into(), 0, 0)
-        .try_into()
-        .unwrap()
+///
+/// # Errors
+///
+/// This function returns an error if the process is not allowed to access the port.
+pub unsafe fn inb(port: u16) -> Result<u8> {
+    checked_syscall(Ty::Inb, port.into(), 0, 0).map(|v| {
+        v.try_into()
+            .unwrap_or_else(|_| unreachable!("Inb system call returns a value out of `u8`."))
+    })
 }
 
 /// # Safety
 ///
 /// This function is unsafe because reading a value from I/O port may have side effects which violate memory safety.
-#[must_use]
-pub unsafe fn inl(port: u16) -> u32 {
-    general_syscall(Ty::Inl, port.into(), 0, 0)
-        .try_into()
-        .unwrap_or_else(|_| {
+///
+/// # Errors
+///
+/// This function returns an error if the process is not allowed to access the port.
+pub unsafe fn inl(port: u16) -> Result<u32> {
+    checked_syscall(Ty::Inl, port.into(), 0, 0).map(|v| {
+        v.try_into().unwrap_or_else(|_| {
             unreachable!("Inl system call returns a value which is out of the ramge of `u32`.")
         })
+    })
 }
 
 /// # Safety
 ///
 /// This function is unsafe because writing a value from I/O port may have side effects which
 /// violate memory safety.
-pub unsafe fn outb(port: u16, value: u8) {
-    general_syscall(Ty::Outb, port.into(), value.into(), 0);
+///
+/// # Errors
+///
+/// This function returns an error if the process is not allowed to access the port.
+pub unsafe fn outb(port: u16, value: u8) -> Result<()> {
+    checked_syscall(Ty::Outb, port.into(), value.into(), 0).map(|_| ())
 }
 
 /// # Safety
 ///
 /// This function is unsafe because writing a value via I/O port may have side effects
 /// which violate memory safety.
-pub unsafe fn outl(port: u16, value: u32) {
-    general_syscall(Ty::Outl, port.into(), value.into(), 0);
+///
+/// # Errors
+///
+/// This function returns an error if the process is not allowed to access the port.
+pub unsafe fn outl(port: u16, value: u32) -> Result<()> {
+    checked_syscall(Ty::Outl, port.into(), value.into(), 0).map(|_| ())
 }
 
-pub fn halt() {
+/// # Errors
+///
+/// This function returns `Error::PermissionDenied` if the process is not allowed to manage
+/// interrupts.
+pub fn halt() -> Result<()> {
     // SAFETY: This operation is safe as it does not touch any unsafe things.
-    unsafe { general_syscall(Ty::Halt, 0, 0, 0) };
+    unsafe { checked_syscall(Ty::Halt, 0, 0, 0) }.map(|_| ())
 }
 
-pub fn disable_interrupt() {
+/// # Errors
+///
+/// This function returns `Error::PermissionDenied` if the process is not allowed to manage
+/// interrupts.
+pub fn disable_interrupt() -> Result<()> {
     // SAFETY: This operation is safe as it does not touch any unsafe things.
-    unsafe { general_syscall(Ty::DisableInterrupt, 0, 0, 0) };
+    unsafe { checked_syscall(Ty::DisableInterrupt, 0, 0, 0) }.map(|_| ())
 }
 
-pub fn enable_interrupt() {
+/// # Errors
+///
+/// This function returns `Error::PermissionDenied` if the process is not allowed to manage
+/// interrupts.
+pub fn enable_interrupt() -> Result<()> {
     // SAFETY: This operation is safe as it does not touch any unsafe things.
-    unsafe { general_syscall(Ty::EnableInterrupt, 0, 0, 0) };
+    unsafe { checked_syscall(Ty::EnableInterrupt, 0, 0, 0) }.map(|_| ())
 }
 
+/// Gives up the CPU until the next timer interrupt.
 pub fn enable_interrupt_and_halt() {
     // SAFETY: This operation is safe as it does not touch any unsafe things.
     unsafe { general_syscall(Ty::EnableInterruptAndHalt, 0, 0, 0) };
 }
 
-#[must_use]
-pub fn allocate_pages(pages: NumOfPages<Size4KiB>) -> VirtAddr {
+/// # Errors
+///
+/// This function returns `Error::OutOfMemory` if there are not enough free pages.
+pub fn allocate_pages(pages: NumOfPages<Size4KiB>) -> Result<VirtAddr> {
     // SAFETY: This operation is safe as the arguments are propertly passed.
-    VirtAddr::new(unsafe {
-        general_syscall(
-            Ty::AllocatePages,
-            pages
-                .as_usize()
-                .try_into()
-                .unwrap_or_else(|_| unreachable!("On x86_64 architecture, `u64` == `usize`.")),
-            0,
-            0,
-        )
-    })
+    unsafe { checked_syscall(Ty::AllocatePages, usize_as_u64(pages.as_usize()), 0, 0) }
+        .map(VirtAddr::new)
 }
 
-pub fn deallocate_pages(virt: VirtAddr, pages: NumOfPages<Size4KiB>) {
+/// Reserves pages without mapping them. Each page is mapped to a zeroed frame when it is accessed
+/// first, so the pages have no physical address until then. Free them with `deallocate_pages`.
+///
+/// # Errors
+///
+/// This function returns `Error::OutOfMemory` if there is no free region large enough.
+pub fn allocate_lazy_pages(pages: NumOfPages<Size4KiB>) -> Result<VirtAddr> {
+    // SAFETY: This operation is safe as the arguments are propertly passed.
+    unsafe { checked_syscall(Ty::AllocateLazyPages, usize_as_u64(pages.as_usize()), 0, 0) }
+        .map(VirtAddr::new)
+}
+
+/// Sets the size up to which the stack of the current process grows. The process is killed with a
+/// stack overflow report when it grows beyond this. The default is a megabyte.
+///
+/// # Errors
+///
+/// This function returns `Error::InvalidArgument` if `bytes` is 0 or larger than
+/// `MAX_STACK_LIMIT`.
+pub fn set_stack_limit(bytes: Bytes) -> Result<()> {
+    // SAFETY: The arguments are passed properly.
+    unsafe { checked_syscall(Ty::SetStackLimit, usize_as_u64(bytes.as_usize()), 0, 0) }.map(|_| ())
+}
+
+/// # Errors
+///
+/// This function returns an error if the pages are not mapped.
+pub fn deallocate_pages(virt: VirtAddr, pages: NumOfPages<Size4KiB>) -> Result<()> {
     // SAFETY: This operation is safe as the all arguments are propertly passed.
     unsafe {
-        general_syscall(
+        checked_syscall(
             Ty::DeallocatePages,
             virt.as_u64(),
-            pages
-                .as_usize()
-                .try_into()
-                .unwrap_or_else(|_| unreachable!("On x86_64 architecture, `u64` == `usize`.")),
+            usize_as_u64(pages.as_usize()),
             0,
         )
-    };
+    }
+    .map(|_| ())
 }
 
-#[must_use]
-pub fn map_pages(start: PhysAddr, bytes: Bytes) -> VirtAddr {
+/// # Errors
+///
+/// This function returns an error if the range is invalid, the process is not allowed to map it,
+/// or there is no free virtual memory.
+pub fn map_pages(start: PhysAddr, bytes: Bytes) -> Result<VirtAddr> {
     // SAFETY: This operation is safe as the all arguments are propertly passed.
-    VirtAddr::new(unsafe {
-        general_syscall(
+    unsafe {
+        checked_syscall(
             Ty::MapPages,
             start.as_u64(),
-            bytes
-                .as_usize()
-                .try_into()
-                .unwrap_or_else(|_| unreachable!("On x86_64 architecture, `u64` == `usize`.")),
+            usize_as_u64(bytes.as_usize()),
             0,
         )
-    })
+    }
+    .map(VirtAddr::new)
 }
 
-pub fn unmap_pages(start: VirtAddr, bytes: Bytes) {
+/// # Errors
+///
+/// This function returns an error if the pages are not mapped.
+pub fn unmap_pages(start: VirtAddr, bytes: Bytes) -> Result<()> {
+    // SAFETY: This operation is safe as the all arguments are propertly passed.
     unsafe {
-        general_syscall(
+        checked_syscall(
             Ty::UnmapPages,
             start.as_u64(),
-            bytes
-                .as_usize()
-                .try_into()
-                .unwrap_or_else(|_| unreachable!("On x86_64 architecture, `usize` == `u64`.")),
+            usize_as_u64(bytes.as_usize()),
             0,
-        );
+        )
     }
+    .map(|_| ())
+}
+
+/// Creates shared memory of at least `bytes` bytes, and returns its ID. The memory is zeroed, and
+/// no process maps it until `map_shared_memory` is called.
+///
+/// # Errors
+///
+/// This function returns `Error::InvalidArgument` if `bytes` is 0 or larger than
+/// `MAX_SHARED_MEMORY`, and `Error::OutOfMemory` if the shared memory which the current process
+/// owns would exceed `MAX_SHARED_MEMORY` in total.
+pub fn create_shared_memory(bytes: Bytes) -> Result<u64> {
+    // SAFETY: The arguments are passed properly.
+    unsafe { checked_syscall(Ty::CreateSharedMemory, usize_as_u64(bytes.as_usize()), 0, 0) }
+}
+
+/// Maps the shared memory `id` to the address space of the process `pid`, and returns the address
+/// in that address space. The pages are mapped on the first access. `pid` may be the current
+/// process, and the memory may be mapped more than once.
+///
+/// # Errors
+///
+/// This function returns `Error::InvalidArgument` if the current process did not create the
+/// memory or has destroyed it, `Error::NoSuchProcess` if there is no process `pid`, and
+/// `Error::OutOfMemory` if the address space of the process has no free region large enough.
+pub fn map_shared_memory(id: u64, pid: i32, access: Access) -> Result<VirtAddr> {
+    // SAFETY: The arguments are passed properly.
+    unsafe { checked_syscall(Ty::MapSharedMemory, id, pid_as_u64(pid), access as u64) }
+        .map(VirtAddr::new)
+}
+
+/// Unmaps the shared memory which `map_shared_memory` mapped at `start` in the address space of
+/// the current process.
+///
+/// # Errors
+///
+/// This function returns `Error::InvalidArgument` if no shared memory is mapped at `start`.
+pub fn unmap_shared_memory(start: VirtAddr) -> Result<()> {
+    // SAFETY: The arguments are passed properly.
+    unsafe { checked_syscall(Ty::UnmapSharedMemory, start.as_u64(), 0, 0) }.map(|_| ())
+}
+
+/// Destroys the shared memory `id` so that it cannot be mapped anymore. The memory is freed when
+/// all the mappings are unmapped. The memory which a process created is destroyed when the process
+/// exits.
+///
+/// # Errors
+///
+/// This function returns `Error::InvalidArgument` if the current process did not create the
+/// memory or has already destroyed it.
+pub fn destroy_shared_memory(id: u64) -> Result<()> {
+    // SAFETY: The arguments are passed properly.
+    unsafe { checked_syscall(Ty::DestroySharedMemory, id, 0, 0) }.map(|_| ())
 }
 
 #[must_use]
@@ -139,32 +241,103 @@ pub fn getpid() -> i32 {
     }
 }
 
+/// Exits with the status 0.
 pub fn exit() -> ! {
+    exit_with_status(0);
+}
+
+/// Exits with `status`, which the parent receives with `wait`.
+pub fn exit_with_status(status: i32) -> ! {
     let ty = Ty::Exit as u64;
-    unsafe { asm!("syscall", in("rax") ty, options(noreturn)) }
+    let status = pid_as_u64(status);
+    unsafe { asm!("syscall", in("rax") ty, in("rdi") status, options(noreturn)) }
 }
 
-/// This method will return a null address if the address is not mapped.
-#[must_use]
-pub fn translate_address(a: VirtAddr) -> PhysAddr {
+/// Starts the program at `path` as a child of the current process, and returns its PID. The
+/// initrd is the root directory, so `path` may be either `/name` or `name`.
+///
+/// The program receives `path` as the first argument, followed by `args`. Its environment is
+/// empty.
+///
+/// # Errors
+///
+/// This function returns `Error::NoSuchFile` if the file does not exist,
+/// `Error::InvalidArgument` if `args` has more than `MAX_ARGS` elements, and `Error::InboxFull` if
+/// the kernel has too many requests to start programs.
+pub fn spawn(path: &str, args: &[&str]) -> Result<i32> {
+    spawn_with_env(path, args, &[])
+}
+
+/// The same as `spawn`, but the program receives `env` as its environment, whose elements are
+/// usually of the form `NAME=value`.
+///
+/// # Errors
+///
+/// This function returns the errors of `spawn`, and `Error::InvalidArgument` if `env` has more
+/// than `MAX_ENV` elements.
+pub fn spawn_with_env(path: &str, args: &[&str], env: &[&str]) -> Result<i32> {
+    if args.len() > MAX_ARGS || env.len() > MAX_ENV {
+        return Err(Error::InvalidArgument);
+    }
+
+    let mut strings = [StrRef::default(); MAX_ARGS + 1 + MAX_ENV];
+    let all = core::iter::once(&path).chain(args).chain(env);
+    for (r, s) in strings.iter_mut().zip(all) {
+        *r = StrRef::from(*s);
+    }
+
+    // SAFETY: The arguments are passed properly.
+    unsafe {
+        checked_syscall(
+            Ty::Spawn,
+            strings.as_ptr() as u64,
+            usize_as_u64(args.len() + 1),
+            usize_as_u64(env.len()),
+        )
+    }
+    .map(|pid| {
+        pid.try_into()
+            .unwrap_or_else(|_| unreachable!("PID is out of `i32` range."))
+    })
+}
+
+/// Blocks until the child `pid` exits, and returns its exit status. The status of a child can be
+/// received only once.
+///
+/// # Errors
+///
+/// This function returns `Error::NoSuchProcess` if `pid` is not a child of the current process or
+/// its status has already been received.
+pub fn wait(pid: i32) -> Result<i32> {
+    // SAFETY: The arguments are passed properly.
+    unsafe { checked_syscall(Ty::Wait, pid_as_u64(pid), 0, 0) }.map(status_from_u64)
+}
+
+/// # Errors
+///
+/// This function returns `Error::NotMapped` if the address is not mapped.
+pub fn translate_address(a: VirtAddr) -> Result<PhysAddr> {
     // SAFETY: Parameters are passed properly.
-    PhysAddr::new(unsafe { general_syscall(Ty::TranslateAddress, a.as_u64(), 0, 0) })
+    unsafe { checked_syscall(Ty::TranslateAddress, a.as_u64(), 0, 0) }.map(PhysAddr::new)
 }
 
+/// Writes `nbyte` bytes from `buf` to the file `fildes`, and returns the number of written bytes.
+/// Only the standard output (1) is supported, and the bytes must be a UTF-8 string.
+///
 /// # Safety
 ///
 /// `buf` must be valid.
-#[must_use]
-pub unsafe fn write(fildes: i32, buf: *const c_void, nbyte: u32) -> i32 {
+///
+/// # Errors
+///
+/// This function returns an error if `fildes` is not supported, `buf` is not accessible from the
+/// process, or the bytes are not a UTF-8 string.
+pub unsafe fn write(fildes: i32, buf: *const c_void, nbyte: u32) -> Result<u32> {
     // SAFETY: The arguments are fulfilled properly.
-    general_syscall(
-        Ty::Write,
-        fildes.try_into().unwrap(),
-        buf as _,
-        nbyte.into(),
-    )
-    .try_into()
-    .unwrap()
+    checked_syscall(Ty::Write, pid_as_u64(fildes), buf as _, nbyte.into()).map(|n| {
+        n.try_into()
+            .unwrap_or_else(|_| unreachable!("Written bytes exceed the requested length."))
+    })
 }
 
 #[must_use]
@@ -173,16 +346,141 @@ pub fn notify_exists() -> bool {
     unsafe { general_syscall(Ty::NotifyExists, 0, 0, 0) != 0 }
 }
 
-pub fn notify_on_interrupt(vec: usize, pid: i32) {
+/// # Errors
+///
+/// This function returns `Error::InvalidArgument` if `vec` is not an interrupt vector, and
+/// `Error::PermissionDenied` if the process is not allowed to manage interrupts.
+pub fn notify_on_interrupt(vec: usize, pid: i32) -> Result<()> {
     // SAFETY: The arguments are passed correctly.
-    unsafe {
-        general_syscall(
-            Ty::NotifyOnInterrupt,
-            vec.try_into().unwrap(),
-            pid.try_into().unwrap(),
-            0,
-        );
-    }
+    unsafe { checked_syscall(Ty::NotifyOnInterrupt, usize_as_u64(vec), pid_as_u64(pid), 0) }
+        .map(|_| ())
+}
+
+/// Sends `m` to the process `to`. This function does not block.
+///
+/// The kernel sets the `sender` field of the message.
+///
+/// # Errors
+///
+/// This function returns an error if there is no such process or its inbox is full.
+pub fn send(to: i32, m: &Message) -> Result<()> {
+    // SAFETY: The arguments are passed properly.
+    unsafe { checked_syscall(Ty::Send, pid_as_u64(to), m as *const _ as u64, 0) }.map(|_| ())
+}
+
+/// Replies to the process which sent a message by `call`. The kernel sets the `sender` field of
+/// the message.
+///
+/// # Errors
+///
+/// This function returns `Error::InvalidArgument` if `to` is not waiting for a reply from the
+/// current process, and an error if there is no such process or its inbox is full.
+pub fn reply(to: i32, m: &Message) -> Result<()> {
+    // SAFETY: The arguments are passed properly.
+    unsafe { checked_syscall(Ty::Reply, pid_as_u64(to), m as *const _ as u64, 0) }.map(|_| ())
+}
+
+/// Receives a message from any process. This function blocks until a message arrives.
+#[must_use]
+pub fn receive() -> Message {
+    receive_from(ANY_PID)
+}
+
+/// Receives a message from the process `from`. This function blocks until a message arrives.
+///
+/// `INTERRUPT_PID` receives only interrupt notifications.
+#[must_use]
+pub fn receive_from(from: i32) -> Message {
+    let mut m = Message::default();
+
+    // SAFETY: The arguments are passed properly.
+    unsafe { checked_syscall(Ty::Receive, pid_as_u64(from), &mut m as *mut _ as u64, 0) }
+        .unwrap_or_else(|e| unreachable!("Failed to receive a message: {:?}", e));
+    m
+}
+
+/// Sends `m` to the process `to` and waits for the reply which it sends with `reply`. The other
+/// messages from `to` are left for `receive`.
+///
+/// # Errors
+///
+/// This function returns an error if there is no such process or its inbox is full.
+pub fn call(to: i32, m: &Message) -> Result<Message> {
+    let mut m = *m;
+
+    // SAFETY: The arguments are passed properly.
+    unsafe { checked_syscall(Ty::Call, pid_as_u64(to), &mut m as *mut _ as u64, 0) }.map(|_| m)
+}
+
+/// Changes the priority of the process `pid`. The new priority takes effect when the process is
+/// woken or preempted next time.
+///
+/// # Errors
+///
+/// This function returns `Error::NoSuchProcess` if there is no such process, and
+/// `Error::PermissionDenied` if the current process does not have the capability for scheduling
+/// and either the process is neither the current process nor its child or `priority` is higher
+/// than its current priority.
+pub fn set_priority(pid: i32, priority: Priority) -> Result<()> {
+    // SAFETY: This operation is safe as the arguments are passed by value.
+    unsafe { checked_syscall(Ty::SetPriority, pid_as_u64(pid), priority as u64, 0) }.map(|_| ())
+}
+
+/// Sets the time slice of the highest priority in milliseconds. Lower priorities get longer time
+/// slices.
+///
+/// # Errors
+///
+/// This function returns `Error::InvalidArgument` if the time slice is shorter than a tick, and
+/// `Error::PermissionDenied` if the current process does not have the capability for scheduling.
+pub fn set_quantum(milliseconds: u32) -> Result<()> {
+    // SAFETY: This operation is safe as the argument is passed by value.
+    unsafe { checked_syscall(Ty::SetQuantum, milliseconds.into(), 0, 0) }.map(|_| ())
+}
+
+/// Blocks the current process for at least `milliseconds` milliseconds.
+pub fn sleep_ms(milliseconds: u64) {
+    // SAFETY: This operation is safe as the argument is passed by value.
+    unsafe { general_syscall(Ty::Sleep, milliseconds, 0, 0) };
+}
+
+/// Blocks the current process until the uptime reaches `uptime_milliseconds`. This function
+/// returns immediately if the time has already passed.
+pub fn sleep_until(uptime_milliseconds: u64) {
+    // SAFETY: This operation is safe as the argument is passed by value.
+    unsafe { general_syscall(Ty::SleepUntil, uptime_milliseconds, 0, 0) };
+}
+
+/// Returns the milliseconds since the boot. The value never decreases.
+#[must_use]
+pub fn get_uptime() -> u64 {
+    // SAFETY: This operation is safe as it does not touch any unsafe things.
+    unsafe { general_syscall(Ty::GetUptime, 0, 0, 0) }
+}
+
+// Use `as` to keep the bit pattern of negative PIDs.
+#[allow(clippy::cast_sign_loss)]
+fn pid_as_u64(pid: i32) -> u64 {
+    i64::from(pid) as u64
+}
+
+/// The kernel returns an exit status zero-extended from its bit pattern as `u32` so that negative
+/// statuses are not confused with errors.
+// Use `as` to restore the bit pattern.
+#[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+fn status_from_u64(v: u64) -> i32 {
+    v as u32 as i32
+}
+
+fn usize_as_u64(n: usize) -> u64 {
+    n.try_into()
+        .unwrap_or_else(|_| unreachable!("On x86_64 architecture, `u64` == `usize`."))
+}
+
+/// SAFETY: This function is unsafe if arguments are invalid.
+#[allow(clippy::too_many_arguments)]
+unsafe fn checked_syscall(ty: Ty, a1: u64, a2: u64, a3: u64) -> Result<u64> {
+    Error::decode(general_syscall(ty, a1, a2, a3))
 }
 
 /// SAFETY: This function is unsafe if arguments are invalid.
@@ -196,7 +494,7 @@ unsafe fn general_syscall(ty: Ty, a1: u64, a2: u64, a3: u64) -> u64 {
     r
 }
 
-#[derive(FromPrimitive)]
+#[derive(Copy, Clone, FromPrimitive)]
 pub enum Ty {
     Inb,
     Outb,
@@ -216,4 +514,134 @@ pub enum Ty {
     Write,
     NotifyExists,
     NotifyOnInterrupt,
+    Send,
+    Receive,
+    Call,
+    SetPriority,
+    SetQuantum,
+    Sleep,
+    SleepUntil,
+    GetUptime,
+    Spawn,
+    Wait,
+    AllocateLazyPages,
+    SetStackLimit,
+    CreateSharedMemory,
+    MapSharedMemory,
+    UnmapSharedMemory,
+    DestroySharedMemory,
+    Reply,
+}
+
+/// `receive_from` with this PID receives a message from any process.
+pub const ANY_PID: i32 = -1;
+
+/// The maximum number of arguments which `spawn` passes to a program, excluding its path.
+pub const MAX_ARGS: usize = 16;
+
+/// The maximum number of the elements of the environment which `spawn_with_env` passes to a
+/// program.
+pub const MAX_ENV: usize = 16;
+
+/// The maximum size of the stack of a process. See `set_stack_limit`.
+pub const MAX_STACK_LIMIT: Bytes = Bytes::new(0x100_0000);
+
+/// The maximum size of the shared memory which a process owns in total. See
+/// `create_shared_memory`.
+pub const MAX_SHARED_MEMORY: Bytes = Bytes::new(0x400_0000);
+
+/// The exit status of a process which the kernel terminated, for example because of an exception
+/// or a broken executable.
+pub const STATUS_KILLED: i32 = -1;
+
+/// The sender of interrupt notifications. The first element of the body is the interrupt vector.
+pub const INTERRUPT_PID: i32 = -2;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Message {
+    pub sender: i32,
+    pub body: [u64; 4],
+}
+impl Message {
+    #[must_use]
+    pub fn new(body: [u64; 4]) -> Self {
+        Self { sender: 0, body }
+    }
+}
+
+/// A string passed to the kernel.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StrRef {
+    pub ptr: u64,
+    pub len: u64,
+}
+impl From<&str> for StrRef {
+    fn from(s: &str) -> Self {
+        Self {
+            ptr: s.as_ptr() as u64,
+            len: usize_as_u64(s.len()),
+        }
+    }
+}
+
+/// The scheduling priority of a process. A ready process of a higher priority runs first.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, FromPrimitive)]
+pub enum Priority {
+    High = 0,
+    Normal = 1,
+    Low = 2,
+}
+
+/// The access which a process has to the shared memory mapped by `map_shared_memory`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, FromPrimitive)]
+pub enum Access {
+    ReadOnly = 0,
+    ReadWrite = 1,
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// An error of a system call. A failed system call returns the negated error number.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, FromPrimitive)]
+pub enum Error {
+    /// The system call number is unknown.
+    InvalidSyscall = 1,
+    /// An argument is out of range or malformed.
+    InvalidArgument = 2,
+    NoSuchProcess = 3,
+    InboxFull = 4,
+    OutOfMemory = 5,
+    BadFileDescriptor = 6,
+    NotMapped = 7,
+    /// A pointer argument points to memory which the process cannot access.
+    BadAddress = 8,
+    /// The process does not have the capability for the request.
+    PermissionDenied = 9,
+    /// The file does not exist in the initrd.
+    NoSuchFile = 10,
+}
+impl Error {
+    /// Return values from `-MAX` to `-1` are errors.
+    const MAX: u64 = 4095;
+
+    /// Converts the result of a system call into the value returned to the caller.
+    #[must_use]
+    pub fn encode(r: Result<u64>) -> u64 {
+        match r {
+            Ok(v) => v,
+            Err(e) => (e as u64).wrapping_neg(),
+        }
+    }
+
+    fn decode(r: u64) -> Result<u64> {
+        let n = r.wrapping_neg();
+
+        if (1..=Self::MAX).contains(&n) {
+            Err(Self::from_u64(n).unwrap_or_else(|| unreachable!("Invalid error number: {}", n)))
+        } else {
+            Ok(r)
+        }
+    }
 }